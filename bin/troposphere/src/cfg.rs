@@ -1,6 +1,6 @@
 #[cfg(not(target_family = "wasm"))]
 mod desktop {
-    use crate::cfg::Config;
+    use crate::cfg::{Config, PersonaConfig};
     use directories::ProjectDirs;
     use ed25519_dalek::{
         pkcs8::{DecodePrivateKey, EncodePrivateKey},
@@ -15,6 +15,10 @@ mod desktop {
         net::{IpAddr, Ipv4Addr, Ipv6Addr},
         path::{Path, PathBuf},
     };
+    use troposphere_lib::{
+        AddressBook, BundleError, DeviceCertificate, DeviceError, IdentityBundle, LinkedDevices,
+        PeerKey, Succession, SuccessionLog,
+    };
 
     lazy_static::lazy_static! {
         pub(crate) static ref PROJECT_DIRS: ProjectDirs = ProjectDirs::from("org", "Signal Garden", "Troposphere").expect("could not get user home directory");
@@ -57,10 +61,25 @@ mod desktop {
     #[serde(default)]
     pub(crate) struct Desktop {
         pub(crate) key_file: PathBuf,
+        /// Load an unencrypted key file even if other users can read it.
+        pub(crate) allow_insecure_key: bool,
+        /// Unlocks the key file without prompting. Only ever taken from the command line or the
+        /// environment, never written to the config file.
+        #[serde(skip)]
+        pub(crate) key_passphrase: Option<Passphrase>,
         pub(crate) directories: Directories,
         pub(crate) mdns: MdnsConfig,
     }
 
+    #[derive(Clone)]
+    pub(crate) struct Passphrase(pub(crate) String);
+
+    impl std::fmt::Debug for Passphrase {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("Passphrase(..)")
+        }
+    }
+
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
     #[serde(default)]
     pub(crate) struct TcpIpConfig {
@@ -86,8 +105,48 @@ mod desktop {
         Io(#[from] std::io::Error),
         #[error(transparent)]
         Serialize(#[from] toml::ser::Error),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum KeyError {
+        #[error(transparent)]
+        Io(#[from] std::io::Error),
         #[error(transparent)]
         Key(#[from] ed25519_dalek::pkcs8::Error),
+        /// There's no key file yet, and no passphrase was given to create one with.
+        #[error("no signing key exists yet")]
+        Missing,
+        #[error("the signing key is encrypted and needs a passphrase")]
+        Locked,
+        #[error("wrong passphrase")]
+        WrongPassphrase,
+        #[error(
+            "refusing to load unencrypted key file {path:?}, which other users can read; restrict \
+             its permissions or set `desktop.allow_insecure_key`"
+        )]
+        Insecure { path: PathBuf },
+        #[error("could not record key succession: {0}")]
+        Succession(#[from] troposphere_lib::SuccessionError),
+        #[error("persona name {0:?} can't be used in a file name")]
+        InvalidPersonaName(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub(crate) enum IdentityError {
+        #[error(transparent)]
+        Io(#[from] std::io::Error),
+        #[error(transparent)]
+        Key(#[from] KeyError),
+        #[error(transparent)]
+        Bundle(#[from] BundleError),
+        #[error("could not record device certificate: {0}")]
+        Device(#[from] DeviceError),
+        #[error("could not update config: {0}")]
+        Config(#[from] WriteError),
+        #[error("no persona named {0:?}")]
+        UnknownPersona(String),
+        #[error("refusing to overwrite existing key file {path:?}")]
+        Exists { path: PathBuf },
     }
 
     impl Config {
@@ -107,6 +166,10 @@ mod desktop {
             if let Some(data_dir) = args.data_dir {
                 res.desktop.directories.data = data_dir;
             }
+            if args.allow_insecure_key {
+                res.desktop.allow_insecure_key = true;
+            }
+            res.desktop.key_passphrase = args.key_passphrase.map(Passphrase);
             // -- end merge cli args --
             if !res.desktop.key_file.has_root() {
                 res.desktop.key_file = res.desktop.directories.data.join("ed25519.sign");
@@ -119,12 +182,44 @@ mod desktop {
             std::fs::write(cfg_path, toml::to_string(self)?).map_err(From::from)
         }
 
-        pub(crate) fn get_key_or_init(&self) -> Result<SigningKey, WriteError> {
-            let path = &self.desktop.key_file;
-            if path.try_exists()? {
-                tracing::debug!(?path, "found key file");
-                SigningKey::read_pkcs8_der_file(path).map_err(From::from)
-            } else {
+        /// Where the signing key of the persona named `name` is kept. The name becomes part of
+        /// the file name, so names that could point anywhere else are refused.
+        pub(crate) fn persona_key_file(&self, name: &str) -> Result<PathBuf, KeyError> {
+            if name.is_empty()
+                || name.starts_with('.')
+                || name.contains("..")
+                || name.contains('\0')
+                || name.chars().any(std::path::is_separator)
+            {
+                return Err(KeyError::InvalidPersonaName(name.to_owned()));
+            }
+            Ok(self
+                .desktop
+                .directories
+                .data
+                .join("personas")
+                .join(format!("{name}.sign")))
+        }
+
+        /// Read the signing key, decrypting it with `passphrase` if it's encrypted. If there's no
+        /// key file yet, a key is generated and written encrypted with `passphrase`, or in the
+        /// clear if the passphrase is empty.
+        ///
+        /// This can take a while; the key derivation is deliberately slow.
+        pub(crate) fn load_key(&self, passphrase: Option<&str>) -> Result<SigningKey, KeyError> {
+            self.load_key_at(&self.desktop.key_file, passphrase)
+        }
+
+        /// Like [`Self::load_key`], for the key file at `path`.
+        pub(crate) fn load_key_at(
+            &self,
+            path: &Path,
+            passphrase: Option<&str>,
+        ) -> Result<SigningKey, KeyError> {
+            if !path.try_exists()? {
+                let Some(passphrase) = passphrase else {
+                    return Err(KeyError::Missing);
+                };
                 tracing::warn!(?path, "key file does not exist; generating new");
                 if let Some(dir) = path.parent() {
                     if !dir.try_exists()? {
@@ -133,10 +228,304 @@ mod desktop {
                     }
                 }
                 let key = SigningKey::generate(&mut OsRng);
-                SigningKey::write_pkcs8_der_file(&key, path)?;
-                Ok(key)
+                write_key(path, &key, passphrase)?;
+                return Ok(key);
+            }
+            tracing::debug!(?path, "found key file");
+            let bytes = std::fs::read(path)?;
+            if let Ok(info) = pkcs8::EncryptedPrivateKeyInfo::try_from(bytes.as_slice()) {
+                let Some(passphrase) = passphrase else {
+                    return Err(KeyError::Locked);
+                };
+                // a wrong passphrase decrypts to garbage as often as it fails outright
+                let document = info
+                    .decrypt(passphrase)
+                    .map_err(|_err| KeyError::WrongPassphrase)?;
+                return SigningKey::from_pkcs8_der(document.as_bytes())
+                    .map_err(|_err| KeyError::WrongPassphrase);
+            }
+            if !self.desktop.allow_insecure_key && readable_by_others(path)? {
+                return Err(KeyError::Insecure {
+                    path: path.to_owned(),
+                });
+            }
+            SigningKey::from_pkcs8_der(&bytes).map_err(From::from)
+        }
+
+        /// Where the address book is kept.
+        pub(crate) fn contacts_file(&self) -> PathBuf {
+            self.desktop.directories.data.join("contacts.syrup")
+        }
+
+        /// Where the certificates of linked devices, ours and our peers', are kept.
+        pub(crate) fn devices_file(&self) -> PathBuf {
+            self.desktop.directories.data.join("devices.syrup")
+        }
+
+        /// Where the profiles peers last presented are kept.
+        pub(crate) fn profile_cache(&self) -> PathBuf {
+            self.desktop.directories.cache.join("profiles.syrup")
+        }
+
+        /// Where our own avatar images are kept.
+        pub(crate) fn avatar_dir(&self) -> PathBuf {
+            self.desktop.directories.data.join("avatars")
+        }
+
+        /// Where avatar images fetched from peers are kept.
+        pub(crate) fn fetched_avatar_dir(&self) -> PathBuf {
+            self.desktop.directories.cache.join("avatars")
+        }
+
+        /// Where the record of our key successions is kept.
+        pub(crate) fn succession_log(&self) -> PathBuf {
+            self.desktop.directories.data.join("successions.syrup")
+        }
+
+        /// Retire the signing key of `persona`, or the default one, in favor of a new key. The
+        /// old key signs a succession that's presented to every peer we connect to from now on.
+        /// It's kept next to the new one, renamed, so messages sealed to it can still be read.
+        pub(crate) fn rotate_key(&self, persona: Option<&str>) -> Result<SigningKey, KeyError> {
+            let path = match persona {
+                Some(name) => self.persona_key_file(name)?,
+                None => self.desktop.key_file.clone(),
+            };
+            let passphrase = self
+                .desktop
+                .key_passphrase
+                .as_ref()
+                .map(|passphrase| passphrase.0.as_str());
+            if !path.try_exists()? {
+                return Err(KeyError::Missing);
+            }
+            let old = self.load_key_at(&path, passphrase)?;
+            let log = SuccessionLog::open(self.succession_log())?;
+            // write the new key before recording the succession, so we can't end up with a
+            // succession whose key is lost
+            let staged = path.with_extension("new");
+            let new = SigningKey::generate(&mut OsRng);
+            write_key(&staged, &new, passphrase.unwrap_or_default())?;
+            log.record(Succession::new(&old, new.verifying_key()))?;
+            let retired =
+                path.with_extension(format!("{}.retired", troposphere_lib::unix_millis()));
+            std::fs::rename(&path, retired)?;
+            std::fs::rename(staged, &path)?;
+            Ok(new)
+        }
+
+        /// Load the keys [`Self::rotate_key`] retired from the key file at `path`, oldest first.
+        /// Retired keys that can't be loaded are left out.
+        pub(crate) fn load_retired_keys(
+            &self,
+            path: &Path,
+            passphrase: Option<&str>,
+        ) -> Result<Vec<SigningKey>, KeyError> {
+            let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+                return Ok(Vec::new());
+            };
+            let prefix = format!("{}.", stem.to_string_lossy());
+            let mut retired = Vec::new();
+            for entry in std::fs::read_dir(dir)? {
+                let name = entry?.file_name();
+                let Some(millis) = name
+                    .to_str()
+                    .and_then(|name| name.strip_prefix(&prefix))
+                    .and_then(|name| name.strip_suffix(".retired"))
+                    .and_then(|millis| millis.parse::<u64>().ok())
+                else {
+                    continue;
+                };
+                retired.push((millis, dir.join(name)));
+            }
+            retired.sort();
+            Ok(retired
+                .into_iter()
+                .filter_map(|(_, path)| {
+                    self.load_key_at(&path, passphrase)
+                        .inspect_err(|error| {
+                            tracing::error!(?path, %error, "could not load retired key");
+                        })
+                        .ok()
+                })
+                .collect())
+        }
+
+        /// Seal the identity of `persona`, or the default one, into a bundle at `path` that
+        /// [`Self::import_identity`] reads on another device. With `linked`, the bundle holds a
+        /// new key for that device, certified by the persona's, rather than the persona's own key.
+        /// Returns the key the bundle holds.
+        pub(crate) fn export_identity(
+            &self,
+            path: &Path,
+            persona: Option<&str>,
+            linked: bool,
+            bundle_passphrase: &str,
+        ) -> Result<PeerKey, IdentityError> {
+            let (key_file, profile, home, joined) = match persona {
+                Some(name) => {
+                    let Some(persona) = self.personas.iter().find(|persona| persona.name == name)
+                    else {
+                        return Err(IdentityError::UnknownPersona(name.to_owned()));
+                    };
+                    (
+                        self.persona_key_file(name)?,
+                        &persona.profile,
+                        persona.home,
+                        &persona.joined,
+                    )
+                }
+                None => (
+                    self.desktop.key_file.clone(),
+                    &self.profile,
+                    self.channels.home,
+                    &self.channels.joined,
+                ),
+            };
+            if !key_file.try_exists()? {
+                return Err(KeyError::Missing.into());
+            }
+            let passphrase = self
+                .desktop
+                .key_passphrase
+                .as_ref()
+                .map(|passphrase| passphrase.0.as_str());
+            let primary = self.load_key_at(&key_file, passphrase)?;
+            let (key, certificate) = if linked {
+                let device = SigningKey::generate(&mut OsRng);
+                let certificate = DeviceCertificate::new(&primary, &device);
+                LinkedDevices::open(self.devices_file())?.record(certificate.clone())?;
+                (device, Some(certificate))
+            } else {
+                (primary, None)
+            };
+            let bundle = IdentityBundle::new(
+                &key,
+                certificate,
+                profile.username.clone(),
+                Some(profile.avatar.clone()),
+                &AddressBook::open(self.contacts_file())?,
+                home,
+                joined.iter().copied(),
+            );
+            write_private(path, &bundle.seal(bundle_passphrase)?)?;
+            Ok(key.verifying_key())
+        }
+
+        /// Take on the identity in the bundle at `path` as `persona`, or as the default persona.
+        /// The key is stored encrypted with the key passphrase, if one was given. The bundle's
+        /// contacts are added to the address book and its profile replaces ours. The channels
+        /// are only hosted here if the bundle holds the persona's own key; a linked device copies
+        /// their history from the primary instead. Returns the key the bundle held.
+        pub(crate) fn import_identity(
+            &self,
+            path: &Path,
+            persona: Option<&str>,
+            bundle_passphrase: &str,
+        ) -> Result<PeerKey, IdentityError> {
+            let bundle = IdentityBundle::open(&std::fs::read(path)?, bundle_passphrase)?;
+            let key = bundle.signing_key()?;
+            let key_file = match persona {
+                Some(name) => self.persona_key_file(name)?,
+                None => self.desktop.key_file.clone(),
+            };
+            if key_file.try_exists()? {
+                return Err(IdentityError::Exists { path: key_file });
+            }
+            if let Some(dir) = key_file.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let passphrase = self
+                .desktop
+                .key_passphrase
+                .as_ref()
+                .map_or("", |passphrase| passphrase.0.as_str());
+            write_key(&key_file, &key, passphrase)?;
+            let restored = bundle.restore_contacts(&AddressBook::open(self.contacts_file())?)?;
+            tracing::info!(restored, "restored contacts");
+            if let Some(certificate) = &bundle.certificate {
+                LinkedDevices::open(self.devices_file())?.record(certificate.clone())?;
+            }
+            let home = bundle
+                .certificate
+                .is_none()
+                .then(|| bundle.channels().next())
+                .flatten();
+            let mut cfg = self.clone();
+            let (profile, home_slot, joined) = match persona {
+                Some(name) => {
+                    let index = match cfg.personas.iter().position(|persona| persona.name == name) {
+                        Some(index) => index,
+                        None => {
+                            cfg.personas.push(PersonaConfig {
+                                name: name.to_owned(),
+                                profile: Default::default(),
+                                home: None,
+                                gateway: None,
+                                joined: Vec::new(),
+                            });
+                            cfg.personas.len() - 1
+                        }
+                    };
+                    let persona = &mut cfg.personas[index];
+                    (&mut persona.profile, &mut persona.home, &mut persona.joined)
+                }
+                None => (
+                    &mut cfg.profile,
+                    &mut cfg.channels.home,
+                    &mut cfg.channels.joined,
+                ),
+            };
+            profile.username.clone_from(&bundle.username);
+            if let Some(avatar) = &bundle.avatar {
+                profile.avatar.clone_from(avatar);
+            }
+            if home.is_some() {
+                *home_slot = home;
+            }
+            for channel in bundle.joined() {
+                if !joined.contains(&channel) {
+                    joined.push(channel);
+                }
             }
+            cfg.write()?;
+            Ok(key.verifying_key())
+        }
+    }
+
+    /// Write `key` to `path`, encrypted with `passphrase` unless it's empty.
+    fn write_key(path: &Path, key: &SigningKey, passphrase: &str) -> Result<(), KeyError> {
+        if passphrase.is_empty() {
+            tracing::warn!(?path, "storing key without a passphrase");
+            key.write_pkcs8_der_file(path)?;
+        } else {
+            key.to_pkcs8_encrypted_der(OsRng, passphrase)?
+                .write_der_file(path)
+                .map_err(ed25519_dalek::pkcs8::Error::from)?;
         }
+        Ok(())
+    }
+
+    /// Write `contents` to a new file at `path` that only we can read, the way key files are.
+    fn write_private(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+        use std::io::Write;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        file.write_all(contents)?;
+        file.sync_all()
+    }
+
+    #[cfg(target_family = "unix")]
+    fn readable_by_others(path: &Path) -> Result<bool, std::io::Error> {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(std::fs::metadata(path)?.permissions().mode() & 0o044 != 0)
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    fn readable_by_others(_: &Path) -> Result<bool, std::io::Error> {
+        Ok(false)
     }
 }
 #[cfg(not(target_family = "wasm"))]
@@ -177,6 +566,9 @@ mod web {
         pub(crate) fn read() -> Result<Self, figment::Error> {
             Ok(Self {
                 profile: Default::default(),
+                personas: Default::default(),
+                channels: Default::default(),
+                access: Default::default(),
                 netlayers: Default::default(),
                 web: Web {
                     signing_key: SigningKey::generate(&mut OsRng),
@@ -187,11 +579,20 @@ mod web {
         pub(crate) fn get_key_or_init(&self) -> Result<SigningKey, WriteError> {
             Ok(self.web.signing_key.clone())
         }
+
+        /// Persona keys aren't stored on the web; each session gets fresh ones.
+        pub(crate) fn persona_key(&self, _name: &str) -> Result<SigningKey, WriteError> {
+            Ok(SigningKey::generate(&mut OsRng))
+        }
     }
 }
 #[cfg(target_family = "wasm")]
 pub(crate) use web::*;
 
+use std::time::Duration;
+
+use troposphere_lib::{ChannelId, ReconnectPolicy, DEFAULT_PERSONA};
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 #[serde(default)]
 pub(crate) struct Profile {
@@ -208,10 +609,76 @@ impl Default for Profile {
     }
 }
 
+/// A persona besides the default one, kept apart from it: its own key, profile and channel.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub(crate) struct PersonaConfig {
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) profile: Profile,
+    /// The channel hosted as this persona; generated on first run.
+    #[serde(default)]
+    pub(crate) home: Option<ChannelId>,
+    /// The secret part of the persona's invite locator, so the persona can't be found by guessing
+    /// its name; generated on first run.
+    #[serde(default)]
+    pub(crate) gateway: Option<uuid::Uuid>,
+    /// Channels hosted elsewhere that we joined as this persona.
+    #[serde(default)]
+    pub(crate) joined: Vec<ChannelId>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct ChannelsConfig {
+    /// The channel hosted by this node; generated on first run.
+    pub(crate) home: Option<ChannelId>,
+    /// Channels hosted elsewhere that we joined.
+    pub(crate) joined: Vec<ChannelId>,
+    /// Whether the hosted channel is end-to-end encrypted between its members.
+    pub(crate) encrypted: bool,
+    /// Whether this node forwards messages between members of the hosted channel.
+    pub(crate) relay: bool,
+    /// Whether to tell other members when we've read their messages; off unless asked for.
+    pub(crate) read_receipts: bool,
+}
+
+impl Default for ChannelsConfig {
+    fn default() -> Self {
+        Self {
+            home: None,
+            joined: Vec::new(),
+            encrypted: false,
+            relay: true,
+            read_receipts: false,
+        }
+    }
+}
+
+/// Which peers may open a portal to this node.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum AccessPolicyKind {
+    AllowAll,
+    Allowlist,
+    Blocklist,
+    TrustOnFirstUse,
+    #[default]
+    Ask,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub(crate) struct AccessConfig {
+    pub(crate) policy: AccessPolicyKind,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
 #[serde(default)]
 pub(crate) struct Config {
     pub(crate) profile: Profile,
+    pub(crate) personas: Vec<PersonaConfig>,
+    pub(crate) channels: ChannelsConfig,
+    pub(crate) access: AccessConfig,
     pub(crate) netlayers: NetlayerConfig,
     #[cfg(not(target_family = "wasm"))]
     pub(crate) desktop: Desktop,
@@ -219,9 +686,68 @@ pub(crate) struct Config {
     pub(crate) web: Web,
 }
 
+impl Config {
+    /// The profile configured for `persona`, the default one included.
+    pub(crate) fn persona_profile_mut(&mut self, persona: &str) -> Option<&mut Profile> {
+        if persona == DEFAULT_PERSONA {
+            return Some(&mut self.profile);
+        }
+        self.personas
+            .iter_mut()
+            .find(|own| own.name == persona)
+            .map(|own| &mut own.profile)
+    }
+
+    /// The channels `persona`, the default one included, joined.
+    pub(crate) fn persona_joined_mut(&mut self, persona: &str) -> Option<&mut Vec<ChannelId>> {
+        if persona == DEFAULT_PERSONA {
+            return Some(&mut self.channels.joined);
+        }
+        self.personas
+            .iter_mut()
+            .find(|own| own.name == persona)
+            .map(|own| &mut own.joined)
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
 #[serde(default)]
 pub(crate) struct NetlayerConfig {
     #[cfg(not(target_family = "wasm"))]
     pub(crate) tcpip: TcpIpConfig,
+    pub(crate) reconnect: ReconnectConfig,
+}
+
+/// How peers are redialed after a session drops.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct ReconnectConfig {
+    pub(crate) enabled: bool,
+    /// Give up after this many failed attempts; 0 keeps trying forever.
+    pub(crate) max_attempts: u32,
+    /// The delay before the first attempt, doubled after every failure.
+    pub(crate) initial_delay_ms: u64,
+    pub(crate) max_delay_ms: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 10,
+            initial_delay_ms: 1_000,
+            max_delay_ms: 300_000,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    pub(crate) fn policy(&self) -> ReconnectPolicy {
+        ReconnectPolicy {
+            enabled: self.enabled,
+            max_attempts: (self.max_attempts > 0).then_some(self.max_attempts),
+            initial_delay: Duration::from_millis(self.initial_delay_ms),
+            max_delay: Duration::from_millis(self.max_delay_ms),
+        }
+    }
 }
@@ -66,5 +66,51 @@ pub(super) struct Cli {
     /// Path to the data directory.
     #[arg(long, env = "STATE_DIRECTORY")]
     pub(super) data_dir: Option<PathBuf>,
+    /// Passphrase to unlock the signing key with, instead of being prompted for it. Also used to
+    /// encrypt the key when it's first generated; prefer the environment variable.
+    #[arg(long, env = "TROPOSPHERE_KEY_PASSPHRASE", hide_env_values = true)]
+    pub(super) key_passphrase: Option<String>,
+    /// Load an unencrypted signing key even if other users can read it.
+    #[arg(long, env = "TROPOSPHERE_ALLOW_INSECURE_KEY")]
+    pub(super) allow_insecure_key: bool,
+    #[command(subcommand)]
+    pub(super) command: Option<Command>,
 }
 
+#[derive(clap::Subcommand, Debug, Clone)]
+pub(super) enum Command {
+    /// Replace the signing key with a new one. Peers that knew the old key are told on their next
+    /// connection and carry their trust over. An encrypted key is unlocked with --key-passphrase,
+    /// which also encrypts the new key.
+    RotateKey {
+        /// Rotate this persona's key instead of the default one.
+        #[arg(long)]
+        persona: Option<String>,
+    },
+    /// Write an identity to a bundle another device can import, sealed with a passphrase of its
+    /// own. The signing key is unlocked with --key-passphrase.
+    ExportIdentity {
+        path: PathBuf,
+        /// Export this persona instead of the default one.
+        #[arg(long)]
+        persona: Option<String>,
+        /// Give the other device a key of its own, certified as speaking for this one, instead of
+        /// a copy of this key. Peers accept both devices, and they share their history.
+        #[arg(long)]
+        linked: bool,
+        /// Passphrase the bundle is sealed with.
+        #[arg(long, env = "TROPOSPHERE_BUNDLE_PASSPHRASE", hide_env_values = true)]
+        bundle_passphrase: String,
+    },
+    /// Take on the identity in a bundle written by export-identity. The key is stored encrypted
+    /// with --key-passphrase.
+    ImportIdentity {
+        path: PathBuf,
+        /// Import as this persona instead of the default one.
+        #[arg(long)]
+        persona: Option<String>,
+        /// Passphrase the bundle was sealed with.
+        #[arg(long, env = "TROPOSPHERE_BUNDLE_PASSPHRASE", hide_env_values = true)]
+        bundle_passphrase: String,
+    },
+}
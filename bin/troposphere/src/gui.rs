@@ -1,16 +1,22 @@
 #![allow(clippy::string_to_string)]
 
-use std::{borrow::Cow, collections::HashMap, ops::Deref, str::FromStr, sync::Arc};
+use std::{
+    borrow::Cow, cell::Cell, collections::HashMap, ops::Deref, rc::Rc, str::FromStr, sync::Arc,
+};
 
 use dioxus::prelude::*;
 use pulldown_cmark::Parser;
 use rexa::{captp::RemoteKey, locator::NodeLocator};
 use tokio::sync::mpsc;
-use troposphere_lib::{ChannelId, ChannelListing, RemotePortal};
+use qrcode::QrCode;
+use troposphere_lib::{
+    is_avatar_blob, AddressBook, ChannelId, ChannelListing, PeerKey, Profile, RemotePortal,
+    SafetyNumber, MAX_AVATAR_SIZE,
+};
 
 use crate::{
     cfg::Config,
-    gui::chat::{ChannelState, ChatState, ManagerEvent},
+    gui::chat::{ChannelState, ChatState, KeyPrompt, ManagerEvent, HISTORY_PAGE_SIZE},
     spawn_coroutine,
 };
 
@@ -32,15 +38,130 @@ fn ocapn_handler(request: wry::http::Request<Vec<u8>>) -> wry::http::Response<Co
         .unwrap()
 }
 
+/// Serves `avatar://localhost/<name>` from the avatars we made or fetched from peers.
+#[cfg(not(target_family = "wasm"))]
+fn avatar_handler(
+    avatars: troposphere_lib::AvatarStore,
+) -> impl Fn(wry::http::Request<Vec<u8>>) -> wry::http::Response<Cow<'static, [u8]>> + 'static {
+    use troposphere_lib::AvatarFormat;
+    use wry::http::{Request, Response};
+    move |request: Request<Vec<u8>>| {
+        let name = request.uri().path().trim_start_matches('/');
+        match avatars.get(name) {
+            Ok(Some(bytes)) => {
+                let mime_type = AvatarFormat::sniff(&bytes)
+                    .map_or("application/octet-stream", AvatarFormat::mime_type);
+                Response::builder()
+                    .status(200)
+                    .header("Content-Type", mime_type)
+                    .body(Cow::from(bytes))
+                    .unwrap()
+            }
+            Ok(None) => Response::builder().status(404).body(Cow::from(&[])).unwrap(),
+            Err(error) => {
+                tracing::error!(name, %error, "failed to read avatar");
+                Response::builder().status(500).body(Cow::from(&[])).unwrap()
+            }
+        }
+    }
+}
+
+/// Where the image for `avatar` is loaded from: bundled ones come with the assets, and avatar
+/// blobs from the store. Peers that didn't pick one get the pond.
+fn avatar_src(avatar: Option<&str>) -> String {
+    match avatar {
+        #[cfg(not(target_family = "wasm"))]
+        Some(name) if is_avatar_blob(name) => format!("avatar://localhost/{name}"),
+        Some(name) if !is_avatar_blob(name) => format!("/assets/avatars/{name}"),
+        _ => "/assets/avatars/pond.svg".to_owned(),
+    }
+}
+
 fn use_current_channel() -> Signal<Option<Arc<ChannelState>>> {
     use_context()
 }
 
+/// The peer whose safety number is being shown, if any.
+fn use_verify_target() -> Signal<Option<PeerKey>> {
+    use_context()
+}
+
+/// How a peer is shown: by the petname we gave it if there is one, otherwise by the name it claims
+/// for itself.
+#[derive(Clone, PartialEq)]
+struct PeerName {
+    name: String,
+    petname: bool,
+    /// The petname of another contact that the claimed name imitates.
+    impersonates: Option<String>,
+    verified: bool,
+}
+
+impl PeerName {
+    fn new(address_book: &AddressBook, peer_key: &PeerKey, profile: Option<&Profile>) -> Self {
+        let verified = address_book.is_verified(peer_key);
+        if let Some(petname) = address_book.petname(peer_key) {
+            return Self {
+                name: petname,
+                petname: true,
+                impersonates: None,
+                verified,
+            };
+        }
+        let Some(profile) = profile else {
+            return Self {
+                name: rexa::hash(peer_key).to_string(),
+                petname: false,
+                impersonates: None,
+                verified,
+            };
+        };
+        Self {
+            name: profile.username.clone(),
+            petname: false,
+            impersonates: address_book
+                .collision(peer_key, &profile.username)
+                .and_then(|owner| address_book.petname(&owner)),
+            verified,
+        }
+    }
+
+    fn render(&self) -> Element {
+        let name = &self.name;
+        let badge = self.verified.then(|| rsx! {
+            span { class: "verified", title: "Safety number verified", " ✔" }
+        });
+        let name = match &self.impersonates {
+            Some(petname) => rsx! {
+                span { class: "name-collision",
+                    title: "Calls itself the name you gave \"{petname}\", but is someone else.",
+                    "⚠ {name}"
+                }
+            },
+            None if self.petname => rsx! { span { class: "petname", "{name}" } },
+            None => rsx! { span { class: "claimed-name", "{name}" } },
+        };
+        rsx! {
+            {name}
+            {badge}
+        }
+    }
+}
+
 #[tracing::instrument]
 pub(super) fn run(cfg: Config) {
     let mut builder = LaunchBuilder::new();
     #[cfg(not(target_family = "wasm"))]
     {
+        // the chat manager opens the same store and gives up if it can't, so do likewise
+        let avatars =
+            match troposphere_lib::AvatarStore::open(cfg.avatar_dir(), cfg.fetched_avatar_dir()) {
+                Ok(avatars) => avatars,
+                Err(error) => {
+                    tracing::error!(%error, "could not open avatar store");
+                    std::process::exit(1);
+                }
+            };
         builder = builder.with_cfg(
             dioxus_desktop::Config::new()
                 .with_window(
@@ -52,7 +173,8 @@ pub(super) fn run(cfg: Config) {
                 .with_background_color((0, 0, 0, 0))
                 .with_resource_directory(cfg.desktop.directories.data.join("assets"))
                 .with_data_directory(cfg.desktop.directories.data.join("dioxus"))
-                .with_custom_protocol("ocapn".to_owned(), ocapn_handler),
+                .with_custom_protocol("ocapn".to_owned(), ocapn_handler)
+                .with_custom_protocol("avatar".to_owned(), avatar_handler(avatars)),
         );
     }
     builder.with_context(Arc::new(cfg)).launch(App);
@@ -67,6 +189,10 @@ fn App() -> Element {
     let current_channel =
         use_context_provider::<Signal<Option<Arc<ChannelState>>>>(move || current_channel_signal);
 
+    let verify_target_signal = use_signal(|| None);
+    let _verify_target =
+        use_context_provider::<Signal<Option<PeerKey>>>(move || verify_target_signal);
+
     #[cfg(not(target_family = "wasm"))]
     {
         let _mdns_state = use_context_provider(crate::native::MdnsState::provider);
@@ -78,6 +204,9 @@ fn App() -> Element {
             link { href: "/assets/style.css", rel: "stylesheet" }
             Navigator { }
             Channel { current_channel }
+            AccessPrompt { }
+            VerifyDialog { }
+            UnlockPrompt { }
         }
     }
 }
@@ -191,12 +320,25 @@ fn Navigator() -> Element {
     let navigators = {
         #[cfg(not(target_family = "wasm"))]
         {
-            [ChannelNav(), PortalNav(), crate::native::MdnsNav()]
+            [
+                PersonaSwitcher(),
+                ProfileSettings(),
+                ChannelNav(),
+                DirectNav(),
+                PortalNav(),
+                crate::native::MdnsNav(),
+            ]
         }
 
         #[cfg(target_family = "wasm")]
         {
-            [ChannelNav(), PortalNav()]
+            [
+                PersonaSwitcher(),
+                ProfileSettings(),
+                ChannelNav(),
+                DirectNav(),
+                PortalNav(),
+            ]
         }
     };
 
@@ -207,6 +349,98 @@ fn Navigator() -> Element {
     }
 }
 
+/// Picks the persona new sessions are opened as. Hidden unless more than one is configured.
+#[allow(non_snake_case)]
+#[component]
+fn PersonaSwitcher() -> Element {
+    let manager = use_coroutine_handle::<ManagerEvent>();
+    let ChatState {
+        personas,
+        active_persona,
+        ..
+    } = use_context::<ChatState>();
+    let personas = personas.read();
+    if personas.len() < 2 {
+        return None;
+    }
+    let active = active_persona.read().clone();
+    let options = personas.iter().map(|name| {
+        rsx! {
+            option { value: name.clone(), selected: *name == active, {name.clone()} }
+        }
+    });
+    rsx! {
+        nav { class: "persona-switcher",
+            h1 { "Persona" },
+            select {
+                title: "Who new connections see you as",
+                onchange: move |event| {
+                    manager.send(ManagerEvent::SwitchPersona { name: event.value() });
+                },
+                {options}
+            }
+        }
+    }
+}
+
+/// How the active persona presents itself to peers.
+#[allow(non_snake_case)]
+#[component]
+fn ProfileSettings() -> Element {
+    let manager = use_coroutine_handle::<ManagerEvent>();
+    let ChatState {
+        self_key,
+        profiles,
+        active_persona,
+        ..
+    } = use_context::<ChatState>();
+    // the key changes with the persona, but isn't a signal
+    drop(active_persona.read());
+    let profiles = profiles.read();
+    let Some(profile) = profiles.get(&*self_key.read()) else {
+        return None;
+    };
+    rsx! {
+        nav { class: "profile-settings",
+            h1 { "Profile" },
+            form {
+                onsubmit: move |event| {
+                    let username = event.values()["username"].as_value();
+                    if !username.trim().is_empty() {
+                        manager.send(ManagerEvent::SetUsername { username });
+                    }
+                },
+                input {
+                    name: "username",
+                    placeholder: "Username",
+                    title: "The name peers see, unless they gave you one",
+                    value: profile.username.clone(),
+                }
+            }
+            label { class: "avatar-picker",
+                title: "Pick a PNG, JPEG, GIF or WebP image of up to {MAX_AVATAR_SIZE / 1024} KiB",
+                img { src: avatar_src(profile.avatar.as_deref()), alt: "Avatar" }
+                input {
+                    r#type: "file",
+                    accept: "image/png,image/jpeg,image/gif,image/webp",
+                    onchange: move |event| async move {
+                        let Some(files) = event.files() else {
+                            return;
+                        };
+                        let Some(file) = files.files().into_iter().next() else {
+                            return;
+                        };
+                        match files.read_file(&file).await {
+                            Some(bytes) => manager.send(ManagerEvent::SetAvatar { bytes }),
+                            None => tracing::warn!(file, "could not read avatar image"),
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 #[component]
 fn ChannelNav() -> Element {
@@ -235,19 +469,78 @@ fn ChannelNav() -> Element {
     }
 }
 
+#[allow(non_snake_case)]
+#[component]
+fn DirectNav() -> Element {
+    let mut current_channel = use_current_channel();
+    let ChatState {
+        profiles,
+        address_book,
+        direct_messages,
+        ..
+    } = use_context::<ChatState>();
+    let profiles = profiles.read();
+    let address_book = address_book.read();
+    let dm_ref = direct_messages.read();
+    let conversations = dm_ref.iter().map(|(peer_key, (_, state))| {
+        let state = state.clone();
+        let name = PeerName::new(&address_book, peer_key, profiles.get(peer_key));
+        rsx! {
+            li {
+                onclick: move |_| {
+                    *current_channel.write() = Some(state.clone());
+                },
+                {name.render()}
+            }
+        }
+    });
+    rsx! {
+        nav {
+            h1 { "Direct Messages" },
+            menu {
+                {conversations}
+            }
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 #[component]
 fn PortalNav() -> Element {
-    let state = use_context::<ChatState>().opened_portals;
+    let manager = use_coroutine_handle::<ManagerEvent>();
+    let ChatState {
+        opened_portals: state,
+        profiles,
+        address_book,
+        ..
+    } = use_context::<ChatState>();
+    let profiles = profiles.read();
+    let address_book = address_book.read();
+    let mut verify_target = use_verify_target();
     let state_ref = state.read();
     let portals = state_ref.iter().map(|(session_key, state)| {
         let channels = match &state.channels {
             Some(Ok(channels)) => {
+                let session_key = *session_key;
+                let entries = channels.iter().map(|channel| {
+                    let listing = channel.clone();
+                    rsx! {
+                        li {
+                            title: channel.info.description.clone(),
+                            onclick: move |_| {
+                                manager.send(ManagerEvent::ConnectChannel {
+                                    session_key,
+                                    listing: listing.clone(),
+                                });
+                            },
+                            {channel.info.name.clone()}
+                            {channel.info.encrypted.then(|| rsx! { span { class: "encrypted", title: "End-to-end encrypted", " 🔒" } })}
+                        }
+                    }
+                });
                 rsx! {
                     menu {
-                        for channel in channels {
-                            li { title: channel.info.description.clone(), {channel.info.name.clone()} }
-                        }
+                        {entries}
                     }
                 }
             }
@@ -260,9 +553,54 @@ fn PortalNav() -> Element {
                 small { "Fetching channels..." }
             },
         };
+        let message = state.peer_key.map(|peer_key| {
+            rsx! {
+                button {
+                    class: "open-direct",
+                    title: "Send a direct message",
+                    onclick: move |_| {
+                        manager.send(ManagerEvent::OpenDirect { peer_key });
+                    },
+                    "✉"
+                }
+                button {
+                    class: "verify-peer",
+                    title: "Compare safety numbers",
+                    onclick: move |_| {
+                        *verify_target.write() = Some(peer_key);
+                    },
+                    "🛡"
+                }
+            }
+        });
+        let name = match state.peer_key {
+            Some(peer_key) => {
+                PeerName::new(&address_book, &peer_key, profiles.get(&peer_key)).render()
+            }
+            None => rsx! { {rexa::hash(session_key).to_string()} },
+        };
+        let petname = state.peer_key.map(|peer_key| {
+            let current = address_book.petname(&peer_key).unwrap_or_default();
+            rsx! {
+                form { class: "petname-form",
+                    onsubmit: move |event| {
+                        let petname = event.values()["petname"].as_value();
+                        manager.send(ManagerEvent::SetPetname { peer_key, petname });
+                    },
+                    input {
+                        name: "petname",
+                        placeholder: "Petname",
+                        title: "A name only you see for this peer",
+                        value: current,
+                    }
+                }
+            }
+        });
         rsx! {
             li {
-                {rexa::hash(session_key).to_string()}
+                {name}
+                {message}
+                {petname}
                 {channels}
             }
         }
@@ -277,6 +615,160 @@ fn PortalNav() -> Element {
     }
 }
 
+#[allow(non_snake_case)]
+#[component]
+fn AccessPrompt() -> Element {
+    let manager = use_coroutine_handle::<ManagerEvent>();
+    let requests = use_context::<ChatState>().access_requests;
+    let Some(peer_key) = requests.read().first().copied() else {
+        return None;
+    };
+    let resolve = move |allow: bool, remember: bool| {
+        move |_| {
+            manager.send(ManagerEvent::ResolveAccess {
+                peer_key,
+                allow,
+                remember,
+            });
+        }
+    };
+    rsx! {
+        dialog { class: "access-prompt", open: true,
+            h1 { "Connection request" }
+            p {
+                "Peer "
+                code { {rexa::hash(&peer_key).to_string()} }
+                " wants to open a portal to you."
+            }
+            menu {
+                button { onclick: resolve(true, false), "Allow once" }
+                button { onclick: resolve(true, true), "Always allow" }
+                button { onclick: resolve(false, false), "Deny" }
+                button { onclick: resolve(false, true), "Block" }
+            }
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+fn UnlockPrompt() -> Element {
+    let manager = use_coroutine_handle::<ManagerEvent>();
+    let prompt = use_context::<ChatState>().key_prompt;
+    let mut mismatch = use_signal(|| false);
+    let Some(prompt) = prompt.read().clone() else {
+        return None;
+    };
+    let creating = prompt == KeyPrompt::Create;
+    let (title, text, submit) = if creating {
+        (
+            "Protect your identity",
+            "Choose a passphrase to encrypt your signing key with. \
+             Leave it empty to store the key unencrypted.",
+            "Create",
+        )
+    } else {
+        (
+            "Unlock your identity",
+            "Enter the passphrase your signing key is encrypted with.",
+            "Unlock",
+        )
+    };
+    let error = match &prompt {
+        _ if *mismatch.read() => Some("The passphrases don't match.".to_owned()),
+        KeyPrompt::Unlock { error } => error.clone(),
+        KeyPrompt::Create => None,
+    };
+    let confirm = creating.then(|| rsx! {
+        input { r#type: "password", name: "confirm", placeholder: "Confirm passphrase" }
+    });
+    rsx! {
+        dialog { class: "unlock-prompt", open: true,
+            h1 { "{title}" }
+            p { "{text}" }
+            {error.map(|error| rsx! { p { class: "unlock-error", "{error}" } })}
+            form {
+                onsubmit: move |event| {
+                    let values = event.values();
+                    let passphrase = values["passphrase"].as_value();
+                    if creating && values["confirm"].as_value() != passphrase {
+                        mismatch.set(true);
+                        return;
+                    }
+                    mismatch.set(false);
+                    manager.send(ManagerEvent::Unlock { passphrase });
+                },
+                input {
+                    r#type: "password",
+                    name: "passphrase",
+                    placeholder: "Passphrase",
+                    autofocus: true,
+                }
+                {confirm}
+                input { r#type: "submit", value: submit }
+            }
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[component]
+fn VerifyDialog() -> Element {
+    let manager = use_coroutine_handle::<ManagerEvent>();
+    let mut target = use_verify_target();
+    let ChatState {
+        self_key,
+        profiles,
+        address_book,
+        ..
+    } = use_context::<ChatState>();
+    let Some(peer_key) = *target.read() else {
+        return None;
+    };
+    let profiles = profiles.read();
+    let address_book = address_book.read();
+    let number = SafetyNumber::new(&self_key.read(), &peer_key);
+    let qr = match QrCode::new(number.digits()) {
+        Ok(code) => code
+            .render::<qrcode::render::svg::Color<'_>>()
+            .min_dimensions(200, 200)
+            .build(),
+        Err(error) => {
+            tracing::error!(%error, "failed to render safety number");
+            String::new()
+        }
+    };
+    let groups = number.groups().map(|group| rsx! { code { "{group}" } });
+    // the peer's claimed name too, so an impersonator is flagged before it gets verified
+    let name = PeerName::new(&address_book, &peer_key, profiles.get(&peer_key)).render();
+    let verified = address_book.is_verified(&peer_key);
+    let set_verified = move |verified: bool| {
+        move |_| {
+            manager.send(ManagerEvent::SetVerified { peer_key, verified });
+        }
+    };
+    let toggle = if verified {
+        rsx! { button { onclick: set_verified(false), "Unmark as verified" } }
+    } else {
+        rsx! { button { onclick: set_verified(true), "Mark as verified" } }
+    };
+    rsx! {
+        dialog { class: "verify-dialog", open: true,
+            h1 { "Verify " {name} }
+            p {
+                "Compare this number with the peer in person or over a call, or scan each "
+                "other's code. If they match, no one is standing in between you."
+            }
+            div { class: "safety-number", {groups} }
+            div { class: "safety-qr", dangerous_inner_html: qr }
+            menu {
+                {toggle}
+                button { onclick: move |_| { *target.write() = None; }, "Close" }
+            }
+        }
+    }
+}
+
 #[allow(non_snake_case)]
 #[component]
 fn About() -> Element {
@@ -352,32 +844,104 @@ fn Channel(current_channel: Signal<Option<Arc<ChannelState>>>) -> Element {
     drop(peers_changed.read());
     drop(messages_changed.read());
 
+    let last_marked = use_hook(|| Rc::new(Cell::new(None)));
+
+    let ChatState {
+        self_key,
+        profiles,
+        address_book,
+        ..
+    } = use_context::<ChatState>();
+    let self_key = *self_key.read();
+    let profiles = profiles.read();
+    let address_book = address_book.read();
+    let mut verify_target = use_verify_target();
     let peers = state.peers();
-    let peer_list = peers.iter().map(|(_, profile)| {
+    let peer_list = peers.iter().map(|(peer_key, profile)| {
+        let peer_key = *peer_key;
+        let name = PeerName::new(&address_book, &peer_key, Some(profile)).render();
+        if peer_key == self_key {
+            return rsx! { li { {name} } };
+        }
         rsx! {
-            li { {profile.username.clone()} }
+            li { class: "verifiable",
+                title: "Compare safety numbers",
+                onclick: move |_| {
+                    *verify_target.write() = Some(peer_key);
+                },
+                {name}
+            }
         }
     });
 
     let messages = state.messages();
+    // marking read covers every message we have, so only a newer one needs another pass
+    let newest = messages.last().map(|msg| msg.id);
+    if use_context::<Arc<Config>>().channels.read_receipts && newest != last_marked.get() {
+        last_marked.set(newest);
+        drop(state.cmd_sender.send(chat::ChannelCommand::MarkRead));
+    }
     let messages = messages.iter().map(|msg| {
-        let profile = &peers[&msg.sender];
+        // history can contain senders that aren't connected, or that we never fetched a profile for
+        let profile = peers
+            .get(&msg.sender)
+            .or_else(|| profiles.get(&msg.sender));
+        let avatar = profile.and_then(|profile| profile.avatar.as_deref());
         Message(MessageData {
-            username: profile.username.clone(),
-            avatar: format!(
-                "/assets/avatars/{}",
-                profile.avatar.as_deref().unwrap_or("pond.svg")
-            ),
+            name: PeerName::new(&address_book, &msg.sender, profile),
+            avatar: avatar_src(avatar),
             message: msg.msg.clone(),
+            undelivered: state.undelivered(&msg.id),
+            delivery: state
+                .channel
+                .delivery_state(&msg.id)
+                .map(|delivery| match (delivery.read.len(), delivery.delivered.len()) {
+                    (0, 0) if delivery.pending > 0 => "Sending…".to_owned(),
+                    (0, 0) if delivery.is_pending() => format!(
+                        "🕓 Pending until {} offline member(s) reconnect",
+                        delivery.queued.len()
+                    ),
+                    (0, delivered) => format!("✓ Delivered to {delivered}"),
+                    (read, _) => format!("✓✓ Read by {read}"),
+                }),
         })
     });
 
+    let rejected = state.rejected();
+    let forged_indicator = (rejected > 0).then(|| {
+        rsx! {
+            p { class: "forged-indicator",
+                title: "These messages failed signature or sender verification and were not shown.",
+                "⚠ {rejected} forged message(s) dropped"
+            }
+        }
+    });
+
+    let load_older = state.has_older().then(|| {
+        let state = state.clone();
+        rsx! {
+            button {
+                class: "load-older",
+                onclick: move |_| {
+                    if let Err(error) = state.load_older(HISTORY_PAGE_SIZE) {
+                        tracing::error!(%error, "failed to load older messages");
+                    }
+                },
+                "Load older messages"
+            }
+        }
+    });
+
     rsx! {
         article { class: "channel",
             div { class: "channel-info",
                 header {
-                    h1 { {state.channel.info().name.clone()} }
+                    h1 {
+                        {state.channel.info().name.clone()}
+                        {state.channel.info().encrypted.then(|| rsx! { span { class: "encrypted", title: "End-to-end encrypted", " 🔒" } })}
+                    }
                     {state.channel.info().description.clone()}
+                    {forged_indicator}
                 }
                 section { class: "peer-list",
                     h1 { "Peers" }
@@ -387,6 +951,7 @@ fn Channel(current_channel: Signal<Option<Arc<ChannelState>>>) -> Element {
                 }
             }
             div { class: "channel-content",
+                {load_older}
                 {messages}
             }
             {MessageInput(state.cmd_sender.clone())}
@@ -396,17 +961,23 @@ fn Channel(current_channel: Signal<Option<Arc<ChannelState>>>) -> Element {
 
 #[derive(Clone, PartialEq, Props)]
 pub(super) struct MessageData {
-    username: String,
+    name: PeerName,
     avatar: String,
     message: String,
+    /// How many members didn't receive this message.
+    undelivered: usize,
+    /// Delivery progress, for messages we sent.
+    delivery: Option<String>,
 }
 
 #[allow(non_snake_case)]
 fn Message(
     MessageData {
-        username,
+        name,
         avatar,
         message,
+        undelivered,
+        delivery,
     }: MessageData,
 ) -> Element {
     use pulldown_cmark::Options;
@@ -425,11 +996,20 @@ fn Message(
         article { class: "message",
             address {
                 img { src: avatar }
-                h1 { class: "username", "{username}" }
+                h1 { class: "username", {name.render()} }
             }
             div { class: "message-content",
                 dangerous_inner_html: parsed_msg
             }
+            {delivery.map(|delivery| rsx! {
+                p { class: "delivery-state", "{delivery}" }
+            })}
+            {(undelivered > 0).then(|| rsx! {
+                p { class: "delivery-failed",
+                    title: "Some members couldn't be reached; they won't see this message.",
+                    "⚠ not delivered to {undelivered} peer(s)"
+                }
+            })}
         }
     }
 }
@@ -463,4 +1043,4 @@ fn MessageInput(cmd_sender: mpsc::UnboundedSender<chat::ChannelCommand>) -> Elem
             input { r#type: "submit", value: "Send" }
         }
     }
-}
\ No newline at end of file
+}
@@ -7,20 +7,28 @@ use std::{
 
 use dashmap::DashMap;
 use dioxus::prelude::*;
-use ed25519_dalek::{ed25519::signature::SignerMut, SigningKey};
+use ed25519_dalek::SigningKey;
 use futures::StreamExt;
 use parking_lot::{Condvar, Mutex, RwLock};
 use rexa::{
-    captp::{object::DeliverError, AbstractCapTpSession, RemoteKey},
-    locator::{NodeLocator, SturdyRefLocator},
+    captp::{
+        object::{DeliverError, ObjectError},
+        AbstractCapTpSession, RemoteKey,
+    },
+    locator::NodeLocator,
 };
 use tokio::{sync::mpsc, task::JoinSet};
 use troposphere_lib::{
-    Channel, ChannelEvent, ChannelId, ChannelInfo, ChannelListing, ChatEvent, ChatManager, Message,
-    NetlayerManager, PeerKey, Profile, RemotePortal, RemotePortalError, UserId,
+    AddressBook, AllowAll, Allowlist, AskUser, AvatarStore, Blocklist, Channel, ChannelEvent,
+    ChannelId, ChannelInfo, ChannelListing, ChatEvent, ChatManager, HistoryError, HistoryStore,
+    Identity, LinkedDevices, OpenDirectError, OpenPortalError, OutboundQueue, PeerKey, Profile,
+    ProfileCache, RemotePortal, SuccessionLog, TrustOnFirstUse, TrustStore, UserId,
+    DEFAULT_PERSONA,
 };
 
-use crate::cfg::{Config, WriteError};
+#[cfg(not(target_family = "wasm"))]
+use crate::cfg::KeyError;
+use crate::cfg::{AccessPolicyKind, Config, WriteError};
 
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum ChatError {
@@ -28,8 +36,31 @@ pub(crate) enum ChatError {
     Inner(String),
     #[error(transparent)]
     WriteConfig(#[from] WriteError),
+    #[cfg(not(target_family = "wasm"))]
+    #[error("could not load signing key: {0}")]
+    Key(#[from] KeyError),
+    #[error(transparent)]
+    PortalOpen(#[from] OpenPortalError),
+    #[error(transparent)]
+    History(#[from] HistoryError),
+    #[error(transparent)]
+    ChannelConnect(#[from] ObjectError),
     #[error(transparent)]
-    PortalOpen(#[from] RemotePortalError),
+    OpenDirect(#[from] OpenDirectError),
+    #[error("could not open trust database: {0}")]
+    TrustStore(std::io::Error),
+    #[error("could not open outbound queue: {0}")]
+    OutboundQueue(std::io::Error),
+    #[error("could not open address book: {0}")]
+    AddressBook(std::io::Error),
+    #[error("could not open succession log: {0}")]
+    SuccessionLog(std::io::Error),
+    #[error("could not open linked devices: {0}")]
+    LinkedDevices(std::io::Error),
+    #[error("could not open profile cache: {0}")]
+    ProfileCache(std::io::Error),
+    #[error("could not open avatar store: {0}")]
+    AvatarStore(std::io::Error),
 }
 
 impl From<Box<dyn std::error::Error + Send + Sync + 'static>> for ChatError {
@@ -38,18 +69,34 @@ impl From<Box<dyn std::error::Error + Send + Sync + 'static>> for ChatError {
     }
 }
 
+/// How many messages are loaded from the history store at a time.
+pub(crate) const HISTORY_PAGE_SIZE: usize = 50;
+
 pub(super) type ListChannelsResult = Result<Vec<ChannelListing>, Arc<DeliverError>>;
 
 #[derive(Default, Clone)]
 pub(super) struct PortalState {
+    pub(super) peer_key: Option<PeerKey>,
     pub(super) channels: Option<ListChannelsResult>,
 }
 
+/// What the user is asked for before the signing key can be loaded.
+#[derive(Clone, PartialEq)]
+pub(super) enum KeyPrompt {
+    /// Choose a passphrase for a newly generated key.
+    Create,
+    Unlock {
+        error: Option<String>,
+    },
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct ChatState {
     pub(crate) self_key: Arc<RwLock<PeerKey>>,
 
     pub(super) profiles: SyncSignal<HashMap<PeerKey, Profile>>,
+    /// Written to whenever a contact changes, so names are redrawn.
+    pub(super) address_book: SyncSignal<Arc<AddressBook>>,
 
     pub(crate) done_binding: Arc<(Mutex<bool>, Condvar)>,
     pub(crate) bound_addresses: Arc<RwLock<Vec<SocketAddr>>>,
@@ -57,6 +104,16 @@ pub(crate) struct ChatState {
     pub(super) opened_portals: SyncSignal<HashMap<RemoteKey, PortalState>>,
 
     pub(super) connected_channels: SyncSignal<HashMap<ChannelId, (Channel, Arc<ChannelState>)>>,
+    pub(super) direct_messages: SyncSignal<HashMap<PeerKey, (Channel, Arc<ChannelState>)>>,
+
+    /// Peers waiting on the user to decide whether they may open a portal.
+    pub(super) access_requests: SyncSignal<Vec<PeerKey>>,
+
+    pub(super) key_prompt: SyncSignal<Option<KeyPrompt>>,
+
+    pub(super) personas: SyncSignal<Vec<String>>,
+    /// The persona new sessions are opened as.
+    pub(super) active_persona: SyncSignal<String>,
 }
 
 impl ChatState {
@@ -69,6 +126,11 @@ pub(crate) enum ManagerEvent {
     Chat(ChatEvent),
     ConnectChannel {
         session_key: RemoteKey,
+        listing: ChannelListing,
+    },
+    ConnectedChannel {
+        channel: Channel,
+        events: mpsc::UnboundedReceiver<ChannelEvent>,
     },
     OpenPortal {
         locator: NodeLocator,
@@ -81,6 +143,42 @@ pub(crate) enum ManagerEvent {
         session_key: RemoteKey,
         channels: ListChannelsResult,
     },
+    ResolveAccess {
+        peer_key: PeerKey,
+        allow: bool,
+        remember: bool,
+    },
+    OpenDirect {
+        peer_key: PeerKey,
+    },
+    OpenedDirect {
+        peer_key: PeerKey,
+        channel: Channel,
+    },
+    /// Name a peer; a blank petname clears it.
+    SetPetname {
+        peer_key: PeerKey,
+        petname: String,
+    },
+    SetVerified {
+        peer_key: PeerKey,
+        verified: bool,
+    },
+    /// Answer a [`KeyPrompt`].
+    Unlock {
+        passphrase: String,
+    },
+    SwitchPersona {
+        name: String,
+    },
+    /// Change the active persona's username, and tell connected peers.
+    SetUsername {
+        username: String,
+    },
+    /// Make the image in `bytes` the active persona's avatar, and tell connected peers.
+    SetAvatar {
+        bytes: Vec<u8>,
+    },
 }
 
 impl From<ChatEvent> for ManagerEvent {
@@ -89,25 +187,18 @@ impl From<ChatEvent> for ManagerEvent {
     }
 }
 
-#[tracing::instrument(fields(session = rexa::hash(&session.remote_vkey())), skip(signing_key))]
+#[tracing::instrument(fields(session = rexa::hash(&session.remote_vkey())), skip(manager))]
 fn handle_new_session(
+    manager: &ChatManager,
     session: Arc<dyn AbstractCapTpSession + Send + Sync>,
-    signing_key: &mut SigningKey,
+    persona: &str,
 ) -> impl std::future::Future<Output = Result<ManagerEvent, ChatError>> {
     tracing::info!("handling new session");
-    let signature = signing_key.sign(b"FIXTHIS");
-    let vkey = signing_key.verifying_key();
+    let session_key = *session.remote_vkey();
+    let open = manager.open_portal_as(session, persona);
     async move {
-        let session_key = *session.remote_vkey();
-        let portal = match RemotePortal::open(
-            &session.into_remote_bootstrap(),
-            &vkey,
-            b"FIXTHIS",
-            &signature,
-        )
-        .await
-        {
-            Ok(p) => Arc::new(p),
+        let portal = match open.await {
+            Ok(p) => p,
             Err(error) => {
                 tracing::error!(
                     session = rexa::hash(&session_key),
@@ -124,28 +215,278 @@ fn handle_new_session(
     }
 }
 
+/// Create the GUI state for a channel and start managing its events, as whichever persona hosts
+/// or joined it.
+fn attach_channel(
+    manager: &ChatManager,
+    channel: Channel,
+    ev_receiver: mpsc::UnboundedReceiver<ChannelEvent>,
+    peers: HashMap<PeerKey, Profile>,
+    channel_tasks: &mut JoinSet<Result<(), ChatError>>,
+) -> Result<Arc<ChannelState>, ChatError> {
+    let (cmd_sender, cmd_receiver) = mpsc::unbounded_channel();
+    let signing_key = manager.identity_of(channel.id()).signing_key.clone();
+
+    let state = Arc::new(ChannelState::new(
+        channel.clone(),
+        cmd_sender,
+        RwLock::new(peers),
+        Default::default(),
+        signing_key.clone(),
+    ));
+    state.load_latest(HISTORY_PAGE_SIZE)?;
+
+    channel_tasks.spawn(manage_channel(
+        channel,
+        state.clone(),
+        cmd_receiver,
+        ev_receiver,
+        signing_key,
+        manager.profiles().clone(),
+    ));
+    Ok(state)
+}
+
+/// Host a channel as `identity`, returning it along with its events.
+fn host_channel(
+    manager: &ChatManager,
+    identity: &Identity,
+    channel_id: ChannelId,
+    username: &str,
+    cfg: &Config,
+) -> (Channel, mpsc::UnboundedReceiver<ChannelEvent>) {
+    let (ev_sender, ev_receiver) = mpsc::unbounded_channel();
+    let info = ChannelInfo {
+        name: format!("{username}'s Channel"),
+        description: "Channel!".to_string(),
+        encrypted: cfg.channels.encrypted,
+    };
+    let channel = if info.encrypted {
+        Channel::new_encrypted(
+            channel_id,
+            info,
+            identity.signing_key.clone(),
+            manager.history().clone(),
+            ev_sender,
+        )
+    } else {
+        Channel::new(channel_id, info, manager.history().clone(), ev_sender)
+    };
+    channel.set_relay(cfg.channels.relay);
+    manager.register_channel_as(identity, channel.clone());
+    (channel, ev_receiver)
+}
+
+/// Load the keys retired from the default key, and the keys of the configured personas, in order,
+/// with those retired from each. They're unlocked with the passphrase the default key was unlocked
+/// with. A persona whose key can't be loaded is left out.
+#[cfg(not(target_family = "wasm"))]
+async fn load_persona_keys(
+    cfg: &Arc<Config>,
+    passphrase: String,
+) -> Result<PersonaKeys, ChatError> {
+    let cfg = cfg.clone();
+    tokio::task::spawn_blocking(move || {
+        let retired = cfg
+            .load_retired_keys(&cfg.desktop.key_file, Some(&passphrase))
+            .inspect_err(|error| tracing::error!(%error, "could not load retired keys"))
+            .unwrap_or_default();
+        let personas = cfg
+            .personas
+            .iter()
+            .map(|persona| {
+                let path = cfg.persona_key_file(&persona.name);
+                path.and_then(|path| {
+                    let key = cfg.load_key_at(&path, Some(&passphrase))?;
+                    let retired = cfg.load_retired_keys(&path, Some(&passphrase))?;
+                    Ok((key, retired))
+                })
+                .inspect_err(|error| {
+                    tracing::error!(persona = persona.name, %error, "could not load persona key");
+                })
+                .ok()
+            })
+            .collect::<Vec<_>>();
+        (retired, personas)
+    })
+    .await
+    .map_err(|error| ChatError::Inner(error.to_string()))
+}
+
+/// Keys retired from the default key, and each configured persona's key with those retired from
+/// it, if it could be loaded.
+#[cfg(not(target_family = "wasm"))]
+type PersonaKeys = (Vec<SigningKey>, Vec<Option<(SigningKey, Vec<SigningKey>)>>);
+
+/// Load our signing key. Unless the passphrase was given on the command line, the user is asked
+/// for it until the key unlocks. Returns the key and the passphrase that unlocked it, which is
+/// empty if the key file isn't encrypted.
+#[cfg(not(target_family = "wasm"))]
+async fn unlock_key(
+    cfg: &Arc<Config>,
+    input: &mut UnboundedReceiver<ManagerEvent>,
+    mut prompt: SyncSignal<Option<KeyPrompt>>,
+) -> Result<(SigningKey, String), ChatError> {
+    let mut passphrase = cfg
+        .desktop
+        .key_passphrase
+        .as_ref()
+        .map(|passphrase| passphrase.0.clone());
+    loop {
+        let load = {
+            let cfg = cfg.clone();
+            let passphrase = passphrase.take();
+            tokio::task::spawn_blocking(move || {
+                let res = cfg.load_key(passphrase.as_deref());
+                res.map(|key| (key, passphrase.unwrap_or_default()))
+            })
+        };
+        let next = match load
+            .await
+            .map_err(|error| ChatError::Inner(error.to_string()))?
+        {
+            Ok(unlocked) => {
+                *prompt.write() = None;
+                return Ok(unlocked);
+            }
+            Err(KeyError::Missing) => KeyPrompt::Create,
+            Err(KeyError::Locked) => KeyPrompt::Unlock { error: None },
+            Err(error @ KeyError::WrongPassphrase) => KeyPrompt::Unlock {
+                error: Some(error.to_string()),
+            },
+            Err(error) => return Err(error.into()),
+        };
+        *prompt.write() = Some(next);
+        passphrase = loop {
+            match input.next().await {
+                Some(ManagerEvent::Unlock { passphrase }) => break Some(passphrase),
+                Some(_) => tracing::debug!("ignoring event while the signing key is locked"),
+                None => {
+                    return Err(ChatError::Inner(
+                        "gui closed before the signing key was unlocked".to_owned(),
+                    ))
+                }
+            }
+        };
+    }
+}
+
+/// Show `profile` in every channel its peer is in, ourselves included.
+fn show_profile<'c>(
+    channels: impl Iterator<Item = &'c (Channel, Arc<ChannelState>)>,
+    profile: &Profile,
+) {
+    for (channel, state) in channels {
+        if channel.has_member(&profile.vkey) || state.peers().contains_key(&profile.vkey) {
+            state.peers_mut().insert(profile.vkey, profile.clone());
+        }
+    }
+}
+
+// the key prompt is only used on desktop
+#[cfg_attr(target_family = "wasm", allow(unused_variables))]
 async fn manager_loop(
     mut input: UnboundedReceiver<ManagerEvent>,
     cfg: Arc<Config>,
     ChatState {
         self_key,
         mut profiles,
+        mut address_book,
         done_binding,
         bound_addresses,
         mut opened_portals,
         mut connected_channels,
+        mut direct_messages,
+        mut access_requests,
+        key_prompt,
+        mut personas,
+        mut active_persona,
     }: ChatState,
 ) -> Result<(), ChatError> {
     tracing::trace!("initializing manager...");
 
-    let signing_key = cfg.get_key_or_init()?;
-    let self_vkey = signing_key.verifying_key();
-    *self_key.write() = self_vkey;
-
+    #[cfg(not(target_family = "wasm"))]
+    let (signing_key, (retired_keys, persona_keys)) = {
+        let (signing_key, passphrase) = unlock_key(&cfg, &mut input, key_prompt).await?;
+        (signing_key, load_persona_keys(&cfg, passphrase).await?)
+    };
+    #[cfg(target_family = "wasm")]
+    let (signing_key, retired_keys, persona_keys) = (
+        cfg.get_key_or_init()?,
+        Vec::new(),
+        cfg.personas
+            .iter()
+            .map(|persona| {
+                cfg.persona_key(&persona.name)
+                    .ok()
+                    .map(|key| (key, Vec::new()))
+            })
+            .collect::<Vec<_>>(),
+    );
+    *self_key.write() = signing_key.verifying_key();
+
+    // the config as last written, which the ids generated below and profile changes go into
+    let mut saved_cfg = (*cfg).clone();
     let mut manager = {
         let mut builder = ChatManager::builder(signing_key)
+            .with_retired_keys(retired_keys)
             .with_username(cfg.profile.username.clone())
-            .with_avatar(cfg.profile.avatar.clone());
+            .with_avatar(cfg.profile.avatar.clone())
+            .with_reconnect_policy(cfg.netlayers.reconnect.policy());
+
+        for (persona, key) in saved_cfg.personas.iter_mut().zip(persona_keys) {
+            let gateway = persona.gateway.get_or_insert_with(uuid::Uuid::new_v4);
+            if let Some((key, retired)) = key {
+                builder = builder.with_persona(
+                    persona.name.clone(),
+                    gateway.as_bytes().to_vec(),
+                    key,
+                    retired,
+                    persona.profile.username.clone(),
+                    Some(persona.profile.avatar.clone()),
+                );
+            }
+        }
+
+        builder = match cfg.access.policy {
+            AccessPolicyKind::AllowAll => builder.with_access_policy(AllowAll),
+            AccessPolicyKind::Allowlist => builder.with_access_policy(Allowlist),
+            AccessPolicyKind::Blocklist => builder.with_access_policy(Blocklist),
+            AccessPolicyKind::TrustOnFirstUse => builder.with_access_policy(TrustOnFirstUse),
+            AccessPolicyKind::Ask => builder.with_access_policy(AskUser),
+        };
+
+        #[cfg(not(target_family = "wasm"))]
+        {
+            builder = builder
+                .with_history(HistoryStore::open(
+                    cfg.desktop.directories.data.join("history"),
+                )?)
+                .with_trust_store(
+                    TrustStore::open(cfg.desktop.directories.data.join("trust.syrup"))
+                        .map_err(ChatError::TrustStore)?,
+                )
+                .with_outbound_queue(
+                    OutboundQueue::open(cfg.desktop.directories.data.join("outbound.syrup"))
+                        .map_err(ChatError::OutboundQueue)?,
+                )
+                .with_address_book(
+                    AddressBook::open(cfg.contacts_file()).map_err(ChatError::AddressBook)?,
+                )
+                .with_succession_log(
+                    SuccessionLog::open(cfg.succession_log()).map_err(ChatError::SuccessionLog)?,
+                )
+                .with_linked_devices(
+                    LinkedDevices::open(cfg.devices_file()).map_err(ChatError::LinkedDevices)?,
+                )
+                .with_profile_cache(
+                    ProfileCache::open(cfg.profile_cache()).map_err(ChatError::ProfileCache)?,
+                )
+                .with_avatar_store(
+                    AvatarStore::open(cfg.avatar_dir(), cfg.fetched_avatar_dir())
+                        .map_err(ChatError::AvatarStore)?,
+                );
+        }
 
         #[cfg(not(target_family = "wasm"))]
         {
@@ -177,9 +518,35 @@ async fn manager_loop(
         builder.build()
     };
 
-    let self_profile = manager.persona.profile.read().await.clone();
-
-    profiles.write().insert(self_vkey, self_profile.clone());
+    // what peers told us last time, then our own profile under every persona
+    profiles.write().extend(
+        manager
+            .profiles()
+            .entries()
+            .into_iter()
+            .map(|profile| (profile.vkey, profile)),
+    );
+    for identity in manager.identities() {
+        let profile = identity.persona.profile.read().await.clone();
+        profiles.write().insert(identity.key(), profile);
+    }
+    // the members a channel starts out with: whichever of us is in it
+    let own_peers = move |manager: &ChatManager, channel_id: &ChannelId| {
+        let key = manager.identity_of(channel_id).key();
+        HashMap::from_iter(
+            profiles
+                .read()
+                .get(&key)
+                .map(|profile| (key, profile.clone())),
+        )
+    };
+    *personas.write() = manager
+        .identities()
+        .iter()
+        .map(|identity| identity.name().to_owned())
+        .collect();
+    *active_persona.write() = DEFAULT_PERSONA.to_owned();
+    *address_book.write() = manager.address_book().clone();
 
     tracing::trace!("starting chat manager loop");
 
@@ -188,45 +555,62 @@ async fn manager_loop(
     let mut tasks = JoinSet::<Result<ManagerEvent, ChatError>>::new();
 
     {
-        let channel_id = ChannelId::new_v4();
-        let (ev_sender, ev_receiver) = mpsc::unbounded_channel();
-        let channel = Channel::new(
-            channel_id,
-            ChannelInfo {
-                name: format!("{}'s Channel", cfg.profile.username),
-                description: "Channel!".to_string(),
-            },
-            ev_sender,
-        );
-
-        let (cmd_sender, cmd_receiver) = mpsc::unbounded_channel();
-
-        let state = Arc::new(ChannelState::new(
-            channel.clone(),
-            cmd_sender,
-            RwLock::new(HashMap::from_iter([(self_vkey, self_profile)])),
-            Default::default(),
-        ));
-
-        manager.register_channel(channel.clone());
-
-        connected_channels
-            .write()
-            .insert(channel_id, (channel.clone(), state.clone()));
-
-        channel_tasks.spawn(manage_channel(
-            channel,
-            state,
-            cmd_receiver,
-            ev_receiver,
-            manager.layers().clone(),
-            manager.signing_key.clone(),
-        ));
+        // every persona hosts a home channel of its own
+        let mut homes = vec![(
+            DEFAULT_PERSONA,
+            &cfg.profile.username,
+            saved_cfg
+                .channels
+                .home
+                .get_or_insert_with(ChannelId::new_v4),
+        )];
+        for persona in &mut saved_cfg.personas {
+            homes.push((
+                persona.name.as_str(),
+                &persona.profile.username,
+                persona.home.get_or_insert_with(ChannelId::new_v4),
+            ));
+        }
+        for (name, username, channel_id) in homes {
+            let Some(identity) = manager.identity(name) else {
+                continue;
+            };
+            let (channel, ev_receiver) =
+                host_channel(&manager, &identity, *channel_id, username, &cfg);
+            let state = attach_channel(
+                &manager,
+                channel.clone(),
+                ev_receiver,
+                own_peers(&manager, channel.id()),
+                &mut channel_tasks,
+            )?;
+            connected_channels
+                .write()
+                .insert(*channel_id, (channel, state));
+        }
+        #[cfg(not(target_family = "wasm"))]
+        if saved_cfg.channels.home != cfg.channels.home
+            || saved_cfg
+                .personas
+                .iter()
+                .zip(&cfg.personas)
+                .any(|(updated, persona)| {
+                    updated.home != persona.home || updated.gateway != persona.gateway
+                })
+        {
+            saved_cfg.write()?;
+        }
     }
 
     loop {
         let event: ManagerEvent = tokio::select! {
-            event = manager.recv_event() => event.unwrap().into(),
+            event = manager.recv_event() => match event {
+                Ok(event) => event.into(),
+                Err(error) => {
+                    tracing::error!(%error, "chat manager task failed");
+                    continue
+                }
+            },
             Some(Ok(task)) = tasks.join_next() => match task {
                 Ok(event) => event,
                 Err(error) => {
@@ -239,10 +623,8 @@ async fn manager_loop(
 
         match event {
             ManagerEvent::Chat(ChatEvent::SessionStarted { session }) => {
-                tasks.spawn(handle_new_session(
-                    session,
-                    &mut (*manager.signing_key.write()),
-                ));
+                let persona = active_persona.read().clone();
+                tasks.spawn(handle_new_session(&manager, session, &persona));
             }
             ManagerEvent::Chat(ChatEvent::SessionAborted {
                 session_key,
@@ -253,6 +635,67 @@ async fn manager_loop(
                     opened_portals.write().remove(&session_key);
                     tracing::info!(session_key = rexa::hash(&session_key), %reason, "session aborted");
                 }
+                // there's no one left to answer if the request came over this session
+                access_requests
+                    .write()
+                    .retain(|peer_key| manager.is_access_pending(peer_key));
+            }
+            ManagerEvent::Chat(ChatEvent::AccessRequested { peer_key }) => {
+                tracing::info!(peer_key = rexa::hash(&peer_key), "peer requested access");
+                let mut requests = access_requests.write();
+                if !requests.contains(&peer_key) {
+                    requests.push(peer_key);
+                }
+            }
+            ManagerEvent::Chat(ChatEvent::Reconnected { peer_key, attempts }) => {
+                tracing::info!(
+                    peer_key = rexa::hash(&peer_key),
+                    attempts,
+                    "reconnected to peer"
+                );
+            }
+            ManagerEvent::Chat(ChatEvent::PeerIntroduced { channel, peer_key }) => {
+                tracing::info!(
+                    %channel,
+                    peer_key = rexa::hash(&peer_key),
+                    "connected to introduced channel member"
+                );
+            }
+            ManagerEvent::Chat(ChatEvent::KeySucceeded { old_key, new_key }) => {
+                tracing::info!(
+                    old_key = rexa::hash(&old_key),
+                    new_key = rexa::hash(&new_key),
+                    "peer rotated its key"
+                );
+                address_book.write();
+            }
+            ManagerEvent::Chat(ChatEvent::ProfileUpdated { profile }) => {
+                tracing::debug!(
+                    peer_key = rexa::hash(&profile.vkey),
+                    username = profile.username,
+                    "peer profile updated"
+                );
+                show_profile(
+                    connected_channels
+                        .read()
+                        .values()
+                        .chain(direct_messages.read().values()),
+                    &profile,
+                );
+                profiles.write().insert(profile.vkey, profile);
+            }
+            ManagerEvent::Chat(ChatEvent::AvatarFetched { peer_key, name }) => {
+                tracing::debug!(peer_key = rexa::hash(&peer_key), name, "fetched avatar");
+                // redraw whoever shows the peer, now the image can be loaded
+                profiles.write();
+            }
+            ManagerEvent::ResolveAccess {
+                peer_key,
+                allow,
+                remember,
+            } => {
+                access_requests.write().retain(|key| key != &peer_key);
+                manager.resolve_access(&peer_key, allow, remember).await;
             }
             ManagerEvent::ListedChannels {
                 session_key,
@@ -265,7 +708,7 @@ async fn manager_loop(
                     .channels = Some(channels);
             }
             ManagerEvent::OpenPortal { locator } => {
-                if let Err(error) = manager.layers().request_connect(locator.clone()) {
+                if let Err(error) = manager.connect(locator.clone()) {
                     tracing::error!(?locator, %error, "failed to process connect request");
                 }
                 // we'll receive a ChatEvent::SessionStarted when the session's connected
@@ -276,9 +719,13 @@ async fn manager_loop(
             } => {
                 tracing::info!(session = rexa::hash(&session_key), "opened portal");
                 portals.insert(session_key, portal.clone());
-                opened_portals
-                    .write()
-                    .insert(session_key, PortalState::default());
+                opened_portals.write().insert(
+                    session_key,
+                    PortalState {
+                        peer_key: Some(*portal.peer_key()),
+                        channels: None,
+                    },
+                );
 
                 tasks.spawn(async move {
                     Ok(ManagerEvent::ListedChannels {
@@ -287,6 +734,162 @@ async fn manager_loop(
                     })
                 });
             }
+            ManagerEvent::ConnectChannel {
+                session_key,
+                listing,
+            } => {
+                if connected_channels.read().contains_key(&listing.id) {
+                    continue;
+                }
+                let Some(portal) = portals.get(&session_key).cloned() else {
+                    tracing::warn!(
+                        session = rexa::hash(&session_key),
+                        "tried to connect to channel through unopened portal"
+                    );
+                    continue;
+                };
+                let (ev_sender, events) = mpsc::unbounded_channel();
+                let connect = manager.connect_channel(portal, listing, ev_sender);
+                tasks.spawn(async move {
+                    let channel = connect.await?;
+                    Ok(ManagerEvent::ConnectedChannel { channel, events })
+                });
+            }
+            ManagerEvent::ConnectedChannel { channel, events } => {
+                tracing::info!(channel = %channel.id(), "connected to channel");
+                let persona = manager.identity_of(channel.id()).name().to_owned();
+                if let Some(joined) = saved_cfg.persona_joined_mut(&persona) {
+                    if !joined.contains(channel.id()) {
+                        joined.push(*channel.id());
+                        #[cfg(not(target_family = "wasm"))]
+                        if let Err(error) = saved_cfg.write() {
+                            tracing::error!(%error, "failed to save joined channel");
+                        }
+                    }
+                }
+                let peers = own_peers(&manager, channel.id());
+                match attach_channel(&manager, channel.clone(), events, peers, &mut channel_tasks) {
+                    Ok(state) => {
+                        connected_channels
+                            .write()
+                            .insert(*channel.id(), (channel, state));
+                    }
+                    Err(error) => tracing::error!(%error, "failed to attach connected channel"),
+                }
+            }
+            ManagerEvent::OpenDirect { peer_key } => {
+                let open = manager.open_direct(peer_key);
+                tasks.spawn(async move {
+                    let channel = open.await?;
+                    Ok(ManagerEvent::OpenedDirect { peer_key, channel })
+                });
+            }
+            ManagerEvent::OpenedDirect { peer_key, channel } => {
+                tracing::info!(
+                    peer_key = rexa::hash(&peer_key),
+                    mailbox = %channel.id(),
+                    "opened direct messages"
+                );
+            }
+            ManagerEvent::SetPetname { peer_key, petname } => {
+                if let Err(error) = manager.address_book().set_petname(peer_key, Some(petname)) {
+                    tracing::error!(peer_key = rexa::hash(&peer_key), %error, "failed to set petname");
+                }
+                address_book.write();
+            }
+            ManagerEvent::SetVerified { peer_key, verified } => {
+                if let Err(error) = manager.address_book().set_verified(peer_key, verified) {
+                    tracing::error!(
+                        peer_key = rexa::hash(&peer_key),
+                        %error,
+                        "failed to record verification"
+                    );
+                }
+                address_book.write();
+            }
+            ManagerEvent::Unlock { .. } => tracing::warn!("signing key is already unlocked"),
+            ManagerEvent::SwitchPersona { name } => match manager.identity(&name) {
+                Some(identity) => {
+                    tracing::info!(persona = name, "switched persona");
+                    *self_key.write() = identity.key();
+                    *active_persona.write() = name;
+                }
+                None => tracing::warn!(persona = name, "no such persona"),
+            },
+            ManagerEvent::SetUsername { username } => {
+                let persona = active_persona.read().clone();
+                let Some(identity) = manager.identity(&persona) else {
+                    continue;
+                };
+                let avatar = identity.persona.profile.read().await.avatar.clone();
+                let profile = manager.set_profile(&identity, username, avatar).await;
+                tracing::info!(persona, username = profile.username, "changed username");
+                show_profile(
+                    connected_channels
+                        .read()
+                        .values()
+                        .chain(direct_messages.read().values()),
+                    &profile,
+                );
+                profiles.write().insert(profile.vkey, profile.clone());
+                if let Some(own) = saved_cfg.persona_profile_mut(&persona) {
+                    own.username = profile.username;
+                }
+                #[cfg(not(target_family = "wasm"))]
+                if let Err(error) = saved_cfg.write() {
+                    tracing::error!(%error, "failed to save profile");
+                }
+            }
+            ManagerEvent::SetAvatar { bytes } => {
+                let persona = active_persona.read().clone();
+                let Some(identity) = manager.identity(&persona) else {
+                    continue;
+                };
+                let profile = match manager.set_avatar(&identity, bytes).await {
+                    Ok(profile) => profile,
+                    Err(error) => {
+                        tracing::error!(persona, %error, "failed to set avatar");
+                        continue;
+                    }
+                };
+                tracing::info!(persona, avatar = profile.avatar, "changed avatar");
+                show_profile(
+                    connected_channels
+                        .read()
+                        .values()
+                        .chain(direct_messages.read().values()),
+                    &profile,
+                );
+                profiles.write().insert(profile.vkey, profile.clone());
+                if let (Some(own), Some(avatar)) =
+                    (saved_cfg.persona_profile_mut(&persona), profile.avatar)
+                {
+                    own.avatar = avatar;
+                }
+                #[cfg(not(target_family = "wasm"))]
+                if let Err(error) = saved_cfg.write() {
+                    tracing::error!(%error, "failed to save profile");
+                }
+            }
+            ManagerEvent::Chat(ChatEvent::MailboxOpened {
+                peer_key,
+                channel,
+                events,
+            }) => {
+                let Some(events) = events.take() else {
+                    continue;
+                };
+                let mut peers = own_peers(&manager, channel.id());
+                if let Some(profile) = profiles.read().get(&peer_key) {
+                    peers.insert(peer_key, profile.clone());
+                }
+                match attach_channel(&manager, channel.clone(), events, peers, &mut channel_tasks) {
+                    Ok(state) => {
+                        direct_messages.write().insert(peer_key, (channel, state));
+                    }
+                    Err(error) => tracing::error!(%error, "failed to attach mailbox"),
+                }
+            }
         }
     }
 }
@@ -311,12 +914,19 @@ mod _channel_state {
     use std::{
         collections::HashMap,
         ops::{Deref, DerefMut},
-        sync::Arc,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
     };
 
+    use ed25519_dalek::SigningKey;
     use parking_lot::RwLock;
     use tokio::sync::{mpsc, Notify};
-    use troposphere_lib::{Channel, Message, PeerKey, Profile};
+    use troposphere_lib::{
+        Channel, HistoryCursor, HistoryError, HistoryQuery, Message, MessageId, PeerKey, Profile,
+        SendReport,
+    };
 
     use super::ChannelCommand;
 
@@ -330,6 +940,13 @@ mod _channel_state {
 
         messages_changed: Arc<Notify>,
         messages: RwLock<Vec<Message>>,
+        older_available: AtomicBool,
+        rejected: AtomicUsize,
+        /// Our messages that some members didn't receive and won't get on reconnecting, and which
+        /// members.
+        undelivered: RwLock<HashMap<MessageId, Vec<PeerKey>>>,
+
+        signing_key: Arc<RwLock<SigningKey>>,
     }
 
     impl ChannelState {
@@ -338,6 +955,7 @@ mod _channel_state {
             cmd_sender: mpsc::UnboundedSender<ChannelCommand>,
             peers: RwLock<HashMap<PeerKey, Profile>>,
             messages: RwLock<Vec<Message>>,
+            signing_key: Arc<RwLock<SigningKey>>,
         ) -> Self {
             Self {
                 channel,
@@ -346,7 +964,31 @@ mod _channel_state {
                 peers,
                 messages_changed: Default::default(),
                 messages,
+                older_available: AtomicBool::new(false),
+                rejected: AtomicUsize::new(0),
+                undelivered: Default::default(),
+                signing_key,
+            }
+        }
+
+        /// Decrypt sealed messages for display.
+        fn readable(&self, mut message: Message) -> Message {
+            if message.is_sealed() {
+                let signing_key = self.signing_key.read().clone();
+                message.msg = match self.channel.open_message(&message, &signing_key) {
+                    Ok(msg) => msg,
+                    Err(error) => {
+                        tracing::warn!(message = %message.id, %error, "could not open sealed message");
+                        "*🔒 this message could not be decrypted*".to_owned()
+                    }
+                };
             }
+            message
+        }
+
+        pub(super) fn push_message(&self, message: Message) {
+            let message = self.readable(message);
+            self.messages_mut().push(message);
         }
 
         pub(crate) fn peer_notif(&self) -> Arc<Notify> {
@@ -380,59 +1022,103 @@ mod _channel_state {
             self.messages_changed.notify_waiters();
             write
         }
+
+        /// How many incoming messages have been dropped for failing verification.
+        pub(crate) fn rejected(&self) -> usize {
+            self.rejected.load(Ordering::Acquire)
+        }
+
+        pub(super) fn record_rejection(&self) {
+            self.rejected.fetch_add(1, Ordering::AcqRel);
+            self.messages_changed.notify_waiters();
+        }
+
+        /// Re-render the messages, e.g. because their delivery state changed.
+        pub(super) fn touch_messages(&self) {
+            self.messages_changed.notify_waiters();
+        }
+
+        /// How many members didn't receive `message`.
+        pub(crate) fn undelivered(&self, message: &MessageId) -> usize {
+            self.undelivered.read().get(message).map_or(0, Vec::len)
+        }
+
+        pub(super) fn record_delivery(&self, message: MessageId, report: &SendReport) {
+            let mut undelivered = self.undelivered.write();
+            if report.is_complete() {
+                undelivered.remove(&message);
+            } else {
+                undelivered.insert(
+                    message,
+                    report
+                        .failed
+                        .iter()
+                        .map(|(peer_key, _)| *peer_key)
+                        .filter(|peer_key| !report.queued.contains(peer_key))
+                        .collect(),
+                );
+            }
+            drop(undelivered);
+            self.messages_changed.notify_waiters();
+        }
+
+        /// Whether the history store holds messages older than the ones currently loaded.
+        pub(crate) fn has_older(&self) -> bool {
+            self.older_available.load(Ordering::Acquire)
+        }
+
+        /// Replace the loaded messages with the newest page from the history store.
+        pub(crate) fn load_latest(&self, limit: usize) -> Result<(), HistoryError> {
+            let page = self.channel.query_history(&HistoryQuery::latest(limit))?;
+            self.older_available.store(page.has_more, Ordering::Release);
+            let messages = page
+                .messages
+                .into_iter()
+                .map(|stored| self.readable(stored.message))
+                .collect();
+            *self.messages_mut() = messages;
+            Ok(())
+        }
+
+        /// Prepend the page of history preceding the oldest loaded message.
+        pub(crate) fn load_older(&self, limit: usize) -> Result<(), HistoryError> {
+            let Some(oldest) = self.messages().first().map(|msg| msg.id) else {
+                return self.load_latest(limit);
+            };
+            let page = self
+                .channel
+                .query_history(&HistoryQuery::before(HistoryCursor::Message(oldest), limit))?;
+            self.older_available.store(page.has_more, Ordering::Release);
+            let older = page
+                .messages
+                .into_iter()
+                .map(|stored| self.readable(stored.message))
+                .collect::<Vec<_>>();
+            self.messages_mut().splice(0..0, older);
+            Ok(())
+        }
     }
 }
 pub(crate) use _channel_state::*;
 
 pub(super) enum ChannelCommand {
-    SendMsg { message: String },
+    SendMsg {
+        message: String,
+    },
+    /// Send read receipts for the loaded messages.
+    MarkRead,
 }
 
-#[tracing::instrument(fields(?channel), skip(state, ev_receiver, layers))]
+#[tracing::instrument(fields(?channel), skip(state, ev_receiver, profiles))]
 async fn manage_channel(
     channel: Channel,
     mut state: Arc<ChannelState>,
     mut cmd_receiver: mpsc::UnboundedReceiver<ChannelCommand>,
     mut ev_receiver: mpsc::UnboundedReceiver<ChannelEvent>,
-    layers: Arc<NetlayerManager>,
     signing_key: Arc<RwLock<SigningKey>>,
+    profiles: Arc<ProfileCache>,
 ) -> Result<(), ChatError> {
-    #[tracing::instrument(fields(?channel, peer_key = rexa::hash(&peer_key), ?locator, swiss = rexa::hash(&swiss)), skip(state, layers))]
-    async fn introduce_peer(
-        state: Arc<ChannelState>,
-        layers: Arc<NetlayerManager>,
-        channel: Channel,
-        peer_key: PeerKey,
-        SturdyRefLocator {
-            node_locator: locator,
-            swiss_num: swiss,
-        }: SturdyRefLocator,
-    ) {
-        let conn_res = match layers.request_connect(locator) {
-            Ok(req) => req.await,
-            Err(error) => {
-                tracing::error!(
-                    %error,
-                    "failed to send connect request for introduced peer"
-                );
-                return;
-            }
-        };
-
-        let session = match conn_res {
-            Ok(session) => session,
-            Err(error) => {
-                tracing::error!(%error, "failed to connect to introduced peer");
-                return;
-            }
-        };
-
-        todo!()
-    }
-
-    let self_vkey = signing_key.read().verifying_key();
-
-    let mut tasks = JoinSet::new();
+    let mut sends = JoinSet::new();
 
     loop {
         let event = tokio::select! {
@@ -445,16 +1131,48 @@ async fn manage_channel(
             }
             Some(cmd) = cmd_receiver.recv() => match cmd {
                 ChannelCommand::SendMsg { message } => {
-                    let message = match Message::new(self_vkey, message, &mut (*signing_key.write())) {
+                    let message = match channel.compose(message, &mut signing_key.write()) {
                         Ok(msg) => msg,
-                        Err(error) => todo!()
+                        Err(error) => {
+                            tracing::error!(%error, "failed to compose message");
+                            continue
+                        }
                     };
-                    if let Err(error) = channel.send_msg(&message).await {
-                        todo!()
+                    state.push_message(message.clone());
+                    let channel = channel.clone();
+                    sends.spawn(async move {
+                        let res = channel.send_msg(&message).await;
+                        (message.id, res)
+                    });
+                    continue
+                }
+                ChannelCommand::MarkRead => {
+                    let mut signing_key = signing_key.read().clone();
+                    if let Err(error) = channel.mark_read(state.messages().iter(), &mut signing_key) {
+                        tracing::error!(%error, "failed to send read receipts");
                     }
-                    state.messages_mut().push(message);
                     continue
                 }
+            },
+            Some(res) = sends.join_next() => {
+                match res {
+                    Ok((message, Ok(report))) => {
+                        for (peer_key, error) in &report.failed {
+                            tracing::warn!(
+                                %message,
+                                peer_key = rexa::hash(peer_key),
+                                %error,
+                                "message not delivered"
+                            );
+                        }
+                        state.record_delivery(message, &report);
+                    }
+                    Ok((message, Err(error))) => {
+                        tracing::error!(%message, %error, "failed to send message");
+                    }
+                    Err(error) => tracing::error!(%error, "message send task failed"),
+                }
+                continue
             }
         };
 
@@ -463,29 +1181,67 @@ async fn manage_channel(
                 channel: _,
                 message,
             } => {
-                state.messages_mut().push(message);
+                state.push_message(message);
             }
-            ChannelEvent::Introduce {
-                channel,
+            ChannelEvent::HistorySynced {
+                channel: _,
+                received,
+            } => {
+                tracing::debug!(received, "channel history synced");
+                if received > 0 {
+                    state.load_latest(HISTORY_PAGE_SIZE)?;
+                }
+            }
+            ChannelEvent::SenderKeyReceived { channel: _, sender } => {
+                tracing::debug!(sender = rexa::hash(&sender), "received sender key");
+                // messages sealed with this key may already be loaded
+                state.load_latest(HISTORY_PAGE_SIZE)?;
+            }
+            ChannelEvent::DeliveryUpdated {
+                channel: _,
+                message,
+            } => {
+                tracing::trace!(%message, "message delivery updated");
+                state.touch_messages();
+            }
+            ChannelEvent::RejectedMessage {
+                channel: _,
+                message,
+                reason,
+            } => {
+                tracing::warn!(
+                    message = %message.id,
+                    sender = rexa::hash(&message.sender),
+                    %reason,
+                    "dropped forged message"
+                );
+                state.record_rejection();
+            }
+            ChannelEvent::PeerConnected {
+                channel: _,
                 peer_key,
-                locator,
             } => {
-                tasks.spawn(introduce_peer(
-                    state.clone(),
-                    layers.clone(),
-                    channel,
-                    peer_key,
-                    locator,
-                ));
+                tracing::debug!(
+                    peer_key = rexa::hash(&peer_key),
+                    "peer connected to channel"
+                );
+                // the first time we meet a peer, its profile arrives later as a ProfileUpdated
+                if let Some(profile) = profiles.get(&peer_key) {
+                    state.peers_mut().insert(peer_key, profile);
+                }
+            }
+            ChannelEvent::PeerDisconnected {
+                channel: _,
+                peer_key,
+            } => {
+                tracing::debug!(
+                    peer_key = rexa::hash(&peer_key),
+                    "peer disconnected from channel"
+                );
+                state.peers_mut().remove(&peer_key);
             }
-        }
-    }
-
-    while let Some(res) = tasks.join_next().await {
-        if let Err(error) = res {
-            tracing::error!(%error, "channel task failed");
         }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -33,7 +33,11 @@ fn main() {
             tracing::warn!(configuration_directory = ?config_dir, "config dir does not exist");
             std::fs::create_dir_all(&config_dir).expect("could not create configuration directory");
         }
+        let command = args.command.clone();
         cfg = Config::read(args, config_dir).expect("could not read config");
+        if let Some(command) = command {
+            return run_command(&cfg, command);
+        }
     }
 
     #[cfg(target_family = "wasm")]
@@ -44,6 +48,60 @@ fn main() {
     gui::run(cfg);
 }
 
+#[cfg(not(target_family = "wasm"))]
+fn run_command(cfg: &Config, command: cli::Command) {
+    match command {
+        cli::Command::RotateKey { persona } => match cfg.rotate_key(persona.as_deref()) {
+            Ok(key) => {
+                let hex = hex_key(&key.verifying_key());
+                println!("rotated signing key; the new key is {hex}");
+            }
+            Err(error) => {
+                eprintln!("could not rotate signing key: {error}");
+                std::process::exit(1);
+            }
+        },
+        cli::Command::ExportIdentity {
+            path,
+            persona,
+            linked,
+            bundle_passphrase,
+        } => match cfg.export_identity(&path, persona.as_deref(), linked, &bundle_passphrase) {
+            Ok(key) if linked => {
+                let hex = hex_key(&key);
+                println!("exported identity for a linked device with key {hex} to {path:?}");
+            }
+            Ok(_) => println!("exported identity to {path:?}"),
+            Err(error) => {
+                eprintln!("could not export identity: {error}");
+                std::process::exit(1);
+            }
+        },
+        cli::Command::ImportIdentity {
+            path,
+            persona,
+            bundle_passphrase,
+        } => match cfg.import_identity(&path, persona.as_deref(), &bundle_passphrase) {
+            Ok(key) => {
+                let hex = hex_key(&key);
+                println!("imported identity; its key is {hex}");
+            }
+            Err(error) => {
+                eprintln!("could not import identity: {error}");
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn hex_key(key: &ed25519_dalek::VerifyingKey) -> String {
+    key.as_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
 pub(crate) fn spawn_coroutine<M, G, F>(init: G) -> dioxus::hooks::Coroutine<M>
 where
     M: 'static,
@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+
+use dashmap::DashMap;
+
+use crate::{store, PeerKey, Timestamp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustLevel {
+    Trusted,
+    Blocked,
+}
+
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "trust-record")]
+struct TrustRecord {
+    peer_key: PeerKey,
+    trusted: bool,
+    updated_at: Timestamp,
+}
+
+/// Persisted trust decisions, keyed by peer identity.
+pub struct TrustStore {
+    path: Option<PathBuf>,
+    entries: DashMap<PeerKey, (TrustLevel, Timestamp)>,
+}
+
+impl std::fmt::Debug for TrustStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrustStore")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl TrustStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let entries = DashMap::new();
+        for record in store::read_records::<TrustRecord>(&path)? {
+            let level = if record.trusted {
+                TrustLevel::Trusted
+            } else {
+                TrustLevel::Blocked
+            };
+            entries.insert(record.peer_key, (level, record.updated_at));
+        }
+        Ok(Self {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: DashMap::new(),
+        }
+    }
+
+    pub fn get(&self, peer_key: &PeerKey) -> Option<TrustLevel> {
+        self.entries.get(peer_key).map(|entry| entry.0)
+    }
+
+    pub fn set(&self, peer_key: PeerKey, level: TrustLevel) -> Result<(), std::io::Error> {
+        self.entries.insert(peer_key, (level, crate::unix_millis()));
+        self.persist()
+    }
+
+    /// Carry the decision about `old_key` over to the key that succeeded it, unless the new key
+    /// has one of its own already.
+    pub fn succeed(&self, old_key: &PeerKey, new_key: PeerKey) -> Result<(), std::io::Error> {
+        let Some(level) = self.get(old_key) else {
+            return Ok(());
+        };
+        if self.entries.contains_key(&new_key) {
+            return Ok(());
+        }
+        self.set(new_key, level)
+    }
+
+    pub fn remove(&self, peer_key: &PeerKey) -> Result<(), std::io::Error> {
+        if self.entries.remove(peer_key).is_some() {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Vec<(PeerKey, TrustLevel)> {
+        self.entries
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().0))
+            .collect()
+    }
+
+    fn persist(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let records = self
+            .entries
+            .iter()
+            .map(|entry| TrustRecord {
+                peer_key: *entry.key(),
+                trusted: entry.value().0 == TrustLevel::Trusted,
+                updated_at: entry.value().1,
+            })
+            .collect::<Vec<_>>();
+        store::write_records(path, &records)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessDecision {
+    Allow,
+    Deny,
+    /// Defer the decision to the user; see [`crate::ChatEvent::AccessRequested`].
+    Ask,
+}
+
+/// Decides whether an authenticated peer may open a portal to us.
+pub trait AccessPolicy: Send + Sync {
+    fn check(&self, peer_key: &PeerKey, trust: &TrustStore) -> AccessDecision;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+impl AccessPolicy for AllowAll {
+    fn check(&self, _: &PeerKey, _: &TrustStore) -> AccessDecision {
+        AccessDecision::Allow
+    }
+}
+
+/// Only admit peers marked as trusted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Allowlist;
+
+impl AccessPolicy for Allowlist {
+    fn check(&self, peer_key: &PeerKey, trust: &TrustStore) -> AccessDecision {
+        match trust.get(peer_key) {
+            Some(TrustLevel::Trusted) => AccessDecision::Allow,
+            _ => AccessDecision::Deny,
+        }
+    }
+}
+
+/// Admit everyone except peers marked as blocked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blocklist;
+
+impl AccessPolicy for Blocklist {
+    fn check(&self, peer_key: &PeerKey, trust: &TrustStore) -> AccessDecision {
+        match trust.get(peer_key) {
+            Some(TrustLevel::Blocked) => AccessDecision::Deny,
+            _ => AccessDecision::Allow,
+        }
+    }
+}
+
+/// Trust any peer the first time it connects, and remember it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrustOnFirstUse;
+
+impl AccessPolicy for TrustOnFirstUse {
+    fn check(&self, peer_key: &PeerKey, trust: &TrustStore) -> AccessDecision {
+        match trust.get(peer_key) {
+            Some(TrustLevel::Trusted) => AccessDecision::Allow,
+            Some(TrustLevel::Blocked) => AccessDecision::Deny,
+            None => {
+                if let Err(error) = trust.set(*peer_key, TrustLevel::Trusted) {
+                    tracing::error!(%error, "failed to persist trust decision");
+                }
+                AccessDecision::Allow
+            }
+        }
+    }
+}
+
+/// Ask the user about any peer that isn't already trusted or blocked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AskUser;
+
+impl AccessPolicy for AskUser {
+    fn check(&self, peer_key: &PeerKey, trust: &TrustStore) -> AccessDecision {
+        match trust.get(peer_key) {
+            Some(TrustLevel::Trusted) => AccessDecision::Allow,
+            Some(TrustLevel::Blocked) => AccessDecision::Deny,
+            None => AccessDecision::Ask,
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+
+/// The largest avatar we hand out or accept from a peer.
+pub const MAX_AVATAR_SIZE: usize = 256 * 1024;
+
+/// The image formats avatars may be in. SVG isn't one of them, since it can carry scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AvatarFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl AvatarFormat {
+    /// Recognize the format from the image's leading bytes, whatever its file is called.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, ..] => Some(Self::Png),
+            [0xff, 0xd8, 0xff, ..] => Some(Self::Jpeg),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Self::Gif),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    fn from_extension(extension: &str) -> Option<Self> {
+        [Self::Png, Self::Jpeg, Self::Gif, Self::Webp]
+            .into_iter()
+            .find(|format| format.extension() == extension)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AvatarError {
+    #[error("avatar is {0} bytes; at most {MAX_AVATAR_SIZE} are allowed")]
+    TooLarge(usize),
+    #[error("avatar is not a PNG, JPEG, GIF or WebP image")]
+    UnsupportedFormat,
+    #[error("avatar does not match the name it was fetched by")]
+    Mismatch,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// The content-addressed name of an avatar: the SHA-256 of its bytes, with the extension of its
+/// format. Fails if the avatar is too large or in a format we don't accept.
+pub fn avatar_name(bytes: &[u8]) -> Result<String, AvatarError> {
+    if bytes.len() > MAX_AVATAR_SIZE {
+        return Err(AvatarError::TooLarge(bytes.len()));
+    }
+    let format = AvatarFormat::sniff(bytes).ok_or(AvatarError::UnsupportedFormat)?;
+    let digest = Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    Ok(format!("{digest}.{}", format.extension()))
+}
+
+/// Whether `name` is the name of an avatar blob, rather than of an image bundled with the client.
+pub fn is_avatar_blob(name: &str) -> bool {
+    let Some((digest, extension)) = name.split_once('.') else {
+        return false;
+    };
+    digest.len() == 64
+        && digest
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+        && AvatarFormat::from_extension(extension).is_some()
+}
+
+/// Avatar images by content-addressed name: ours, and those we fetched from peers. Ours are kept
+/// apart from the fetched ones, which can be cleared like any other cache.
+pub struct AvatarStore {
+    dirs: Option<AvatarDirs>,
+    blobs: DashMap<String, Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct AvatarDirs {
+    own: PathBuf,
+    fetched: PathBuf,
+}
+
+impl std::fmt::Debug for AvatarStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AvatarStore")
+            .field("dirs", &self.dirs)
+            .field("blobs", &self.blobs.len())
+            .finish()
+    }
+}
+
+impl Default for AvatarStore {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl AvatarStore {
+    /// Keep our own avatars as files in `own`, and those fetched from peers in `fetched`.
+    pub fn open(
+        own: impl Into<PathBuf>,
+        fetched: impl Into<PathBuf>,
+    ) -> Result<Self, std::io::Error> {
+        let dirs = AvatarDirs {
+            own: own.into(),
+            fetched: fetched.into(),
+        };
+        std::fs::create_dir_all(&dirs.own)?;
+        std::fs::create_dir_all(&dirs.fetched)?;
+        Ok(Self {
+            dirs: Some(dirs),
+            blobs: DashMap::new(),
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            dirs: None,
+            blobs: DashMap::new(),
+        }
+    }
+
+    /// Where the avatar named `name` is kept, if it's kept on disk: among ours if it's there,
+    /// otherwise among those fetched from peers.
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        if !is_avatar_blob(name) {
+            return None;
+        }
+        let dirs = self.dirs.as_ref()?;
+        let own = dirs.own.join(name);
+        if own.exists() {
+            return Some(own);
+        }
+        Some(dirs.fetched.join(name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        match self.path(name) {
+            Some(path) => path.exists(),
+            None => self.blobs.contains_key(name),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<Vec<u8>>, std::io::Error> {
+        if !is_avatar_blob(name) {
+            return Ok(None);
+        }
+        let Some(path) = self.path(name) else {
+            return Ok(self.blobs.get(name).map(|blob| blob.clone()));
+        };
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Add an avatar of our own, returning the name peers can fetch it by.
+    pub fn insert(&self, bytes: Vec<u8>) -> Result<String, AvatarError> {
+        let name = avatar_name(&bytes)?;
+        let dir = self.dirs.as_ref().map(|dirs| dirs.own.as_path());
+        self.store(dir, &name, bytes)?;
+        Ok(name)
+    }
+
+    /// Add an avatar a peer sent us, provided it's the one `name` refers to.
+    pub fn insert_named(&self, name: &str, bytes: Vec<u8>) -> Result<(), AvatarError> {
+        if avatar_name(&bytes)? != name {
+            return Err(AvatarError::Mismatch);
+        }
+        let dir = self.dirs.as_ref().map(|dirs| dirs.fetched.as_path());
+        self.store(dir, name, bytes).map_err(From::from)
+    }
+
+    fn store(&self, dir: Option<&Path>, name: &str, bytes: Vec<u8>) -> Result<(), std::io::Error> {
+        match dir {
+            Some(dir) => {
+                // write under another name first, so a reader never sees half an image, and a
+                // unique one, so fetches of the same avatar don't write over each other
+                let staged = dir.join(format!("{name}.{:016x}.part", rand::random::<u64>()));
+                let res = std::fs::write(&staged, bytes)
+                    .and_then(|()| std::fs::rename(&staged, dir.join(name)));
+                if res.is_err() {
+                    drop(std::fs::remove_file(&staged));
+                }
+                res
+            }
+            None => {
+                self.blobs.insert(name.to_owned(), bytes);
+                Ok(())
+            }
+        }
+    }
+}
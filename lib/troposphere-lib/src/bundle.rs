@@ -0,0 +1,219 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use ed25519_dalek::SigningKey;
+use syrup::{Deserialize, Serialize};
+
+use crate::{contacts::ContactRecord, AddressBook, ChannelId, DeviceCertificate, SyrupUuid};
+
+const BUNDLE_MAGIC: &[u8] = b"troposphere/identity-bundle/v1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    #[error("not an identity bundle")]
+    Format,
+    #[error("wrong passphrase, or the bundle is damaged")]
+    Decrypt,
+    #[error("bundles must be sealed with a passphrase")]
+    EmptyPassphrase,
+    #[error("could not encrypt bundle")]
+    Encrypt,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// What another device needs to act as one of our personas: the key, profile, contacts, the
+/// channels the persona hosts and those it joined. The key is either the persona's own or, for a
+/// linked device, a key of the device's own that [`Self::certificate`] vouches for.
+#[derive(Serialize, Deserialize, Clone)]
+#[syrup(name = "identity-bundle")]
+pub struct IdentityBundle {
+    secret_key: syrup::Bytes<Vec<u8>>,
+    pub certificate: Option<DeviceCertificate>,
+    pub username: String,
+    pub avatar: Option<String>,
+    contacts: Vec<ContactRecord>,
+    channels: Vec<SyrupUuid>,
+    joined: Vec<SyrupUuid>,
+}
+
+impl std::fmt::Debug for IdentityBundle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentityBundle")
+            .field("certificate", &self.certificate)
+            .field("username", &self.username)
+            .field("contacts", &self.contacts.len())
+            .field("channels", &self.channels.len())
+            .field("joined", &self.joined.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl IdentityBundle {
+    pub fn new(
+        signing_key: &SigningKey,
+        certificate: Option<DeviceCertificate>,
+        username: String,
+        avatar: Option<String>,
+        address_book: &AddressBook,
+        channels: impl IntoIterator<Item = ChannelId>,
+        joined: impl IntoIterator<Item = ChannelId>,
+    ) -> Self {
+        Self {
+            secret_key: syrup::Bytes(signing_key.to_bytes().to_vec()),
+            certificate,
+            username,
+            avatar,
+            contacts: address_book.records(),
+            channels: channels.into_iter().map(SyrupUuid::from).collect(),
+            joined: joined.into_iter().map(SyrupUuid::from).collect(),
+        }
+    }
+
+    pub fn signing_key(&self) -> Result<SigningKey, BundleError> {
+        let bytes = <[u8; 32]>::try_from(self.secret_key.0.as_slice())
+            .map_err(|_err| BundleError::Format)?;
+        Ok(SigningKey::from_bytes(&bytes))
+    }
+
+    /// The channels the persona hosts.
+    pub fn channels(&self) -> impl Iterator<Item = ChannelId> + '_ {
+        self.channels.iter().map(|&id| id.into())
+    }
+
+    /// The channels hosted elsewhere that the persona joined.
+    pub fn joined(&self) -> impl Iterator<Item = ChannelId> + '_ {
+        self.joined.iter().map(|&id| id.into())
+    }
+
+    /// Add the bundled contacts to `address_book`, keeping its own where both know a peer.
+    /// Returns how many were added.
+    pub fn restore_contacts(&self, address_book: &AddressBook) -> Result<usize, BundleError> {
+        address_book.import(&self.contacts).map_err(From::from)
+    }
+
+    /// Encrypt the bundle with a key stretched from `passphrase`, which mustn't be empty; the
+    /// bundle holds a signing key.
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>, BundleError> {
+        if passphrase.is_empty() {
+            return Err(BundleError::EmptyPassphrase);
+        }
+        let plaintext = syrup::ser::to_bytes(self).map_err(|_err| BundleError::Encrypt)?;
+        let salt = rand::random::<[u8; SALT_LEN]>();
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: BUNDLE_MAGIC,
+                },
+            )
+            .map_err(|_err| BundleError::Encrypt)?;
+        Ok([BUNDLE_MAGIC, &salt, &nonce, &ciphertext].concat())
+    }
+
+    pub fn open(sealed: &[u8], passphrase: &str) -> Result<Self, BundleError> {
+        let rest = sealed
+            .strip_prefix(BUNDLE_MAGIC)
+            .ok_or(BundleError::Format)?;
+        if rest.len() < SALT_LEN + NONCE_LEN {
+            return Err(BundleError::Format);
+        }
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let plaintext = cipher(passphrase, salt)?
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: BUNDLE_MAGIC,
+                },
+            )
+            .map_err(|_err| BundleError::Decrypt)?;
+        syrup::de::from_bytes(&plaintext).map_err(|_err| BundleError::Format)
+    }
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305, BundleError> {
+    let mut key = chacha20poly1305::Key::default();
+    scrypt::scrypt(
+        passphrase.as_bytes(),
+        salt,
+        &scrypt::Params::recommended(),
+        &mut key,
+    )
+    .map_err(|_err| BundleError::Encrypt)?;
+    Ok(XChaCha20Poly1305::new(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    fn bundle(signing_key: &SigningKey) -> IdentityBundle {
+        IdentityBundle::new(
+            signing_key,
+            None,
+            "alice".to_owned(),
+            None,
+            &AddressBook::in_memory(),
+            [ChannelId::new_v4()],
+            [ChannelId::new_v4(), ChannelId::new_v4()],
+        )
+    }
+
+    #[test]
+    fn opens_with_its_passphrase() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let original = bundle(&signing_key);
+        let opened = IdentityBundle::open(&original.seal("hunter2").unwrap(), "hunter2").unwrap();
+        assert_eq!(
+            opened.signing_key().unwrap().to_bytes(),
+            signing_key.to_bytes()
+        );
+        assert_eq!(opened.username, "alice");
+        assert_eq!(
+            opened.channels().collect::<Vec<_>>(),
+            original.channels().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            opened.joined().collect::<Vec<_>>(),
+            original.joined().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn refuses_wrong_passphrase_and_tampering() {
+        let sealed = bundle(&SigningKey::generate(&mut OsRng))
+            .seal("hunter2")
+            .unwrap();
+        assert!(matches!(
+            IdentityBundle::open(&sealed, "hunter3"),
+            Err(BundleError::Decrypt)
+        ));
+        let mut tampered = sealed;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            IdentityBundle::open(&tampered, "hunter2"),
+            Err(BundleError::Decrypt)
+        ));
+        assert!(matches!(
+            IdentityBundle::open(b"not a bundle", "hunter2"),
+            Err(BundleError::Format)
+        ));
+    }
+
+    #[test]
+    fn refuses_empty_passphrase() {
+        assert!(matches!(
+            bundle(&SigningKey::generate(&mut OsRng)).seal(""),
+            Err(BundleError::EmptyPassphrase)
+        ));
+    }
+}
@@ -1,22 +1,35 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+};
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use ed25519_dalek::{ed25519::signature::SignerMut, Signature, SignatureError, SigningKey};
+use futures::{stream::FuturesUnordered, StreamExt};
 use rexa::{
     captp::{
-        object::{DeliverOnlyError, ObjectError, RemoteObject},
-        RemoteKey,
+        object::{ObjectError, RemoteError, RemoteObject},
+        AbstractCapTpSession, RemoteKey,
     },
     impl_object,
     locator::{NodeLocator, SturdyRefLocator},
 };
-use syrup::{Deserialize, Serialize};
-use tokio::{
-    sync::mpsc,
-    task::{JoinError, JoinSet},
+use syrup::{Deserialize, FromSyrupItem, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{
+    group::GroupSession, ConversationKey, DeliveryState, EventSender, HistoryBatch, HistoryCursor,
+    HistoryError, HistoryPage, HistoryQuery, HistoryRequest, HistoryStore, LinkedDevices,
+    NetworkEvent, OutboundQueue, PeerKey, ReadReceipt, SealError, SenderKeyGrant, SyrupUuid,
+    Timestamp, UserId,
 };
 
-use crate::{PeerKey, SyrupUuid, UserId};
+mod outbox;
+use outbox::{Delivery, Outbox};
+pub use outbox::{DeliveryError, PeerStatus, SendReport, OUTBOX_CAPACITY, OUTBOX_MAX_ATTEMPTS};
 
 pub type MessageId = uuid::Uuid;
 
@@ -28,53 +41,323 @@ pub struct ChannelListing {
     pub info: ChannelInfo,
 }
 
+/// The current signed-payload format of [`Message`].
+pub const MESSAGE_VERSION: u64 = 2;
+/// The format used before messages were bound to a channel; only accepted from stored history.
+pub const LEGACY_MESSAGE_VERSION: u64 = 1;
+const MESSAGE_DOMAIN: &[u8] = b"troposphere/channel-message/v2";
+/// Messages whose body is end-to-end encrypted; the signature covers the ciphertext.
+pub const SEALED_MESSAGE_VERSION: u64 = 3;
+const SEALED_MESSAGE_DOMAIN: &[u8] = b"troposphere/sealed-message/v3";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "sealed-payload")]
+pub struct SealedPayload {
+    /// The addressee of a direct message; `None` for channel messages, which are sealed with the
+    /// sender's key for the channel.
+    pub recipient: Option<PeerKey>,
+    /// Which of the sender's channel keys was used; always 0 for direct messages.
+    pub epoch: u64,
+    pub nonce: syrup::Bytes<Vec<u8>>,
+    pub ciphertext: syrup::Bytes<Vec<u8>>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[syrup(name = "message")]
 pub struct Message {
     #[syrup(as = SyrupUuid)]
     pub id: MessageId,
+    pub version: u64,
+    #[syrup(as = SyrupUuid)]
+    pub channel: ChannelId,
     pub sender: PeerKey,
+    /// When the sender created the message, according to the sender's clock.
+    pub timestamp: Timestamp,
+    pub reply_to: Option<SyrupUuid>,
+    /// The message body; empty if the message is sealed.
     pub msg: String,
+    pub sealed: Option<SealedPayload>,
     pub signature: Signature,
 }
 
 impl Message {
-    fn fields_to_bytes(id: MessageId, sender: PeerKey, msg: &str) -> Vec<u8> {
+    fn legacy_payload(id: MessageId, sender: PeerKey, msg: &str) -> Vec<u8> {
         let mut res = syrup::ser::to_bytes(&SyrupUuid(id)).unwrap();
         res.extend_from_slice(&syrup::ser::to_bytes(&sender).unwrap());
         res.extend_from_slice(&syrup::ser::to_bytes(msg).unwrap());
         res
     }
 
-    pub fn new_signed(sender: PeerKey, msg: String, signature: Signature) -> Self {
+    fn payload(
+        id: MessageId,
+        channel: ChannelId,
+        sender: PeerKey,
+        timestamp: Timestamp,
+        reply_to: Option<MessageId>,
+        msg: &str,
+    ) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(MESSAGE_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid(id)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid(channel)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&sender).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&timestamp).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&reply_to.map(SyrupUuid)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(msg).unwrap());
+        res
+    }
+
+    fn sealed_payload(
+        id: MessageId,
+        channel: ChannelId,
+        sender: PeerKey,
+        timestamp: Timestamp,
+        reply_to: Option<MessageId>,
+        sealed: &SealedPayload,
+    ) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(SEALED_MESSAGE_DOMAIN)).unwrap();
+        res.extend_from_slice(&Self::seal_aad(id, channel, sender, timestamp));
+        res.extend_from_slice(&syrup::ser::to_bytes(&reply_to.map(SyrupUuid)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&sealed.recipient).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&sealed.epoch).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&sealed.nonce).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&sealed.ciphertext).unwrap());
+        res
+    }
+
+    /// Associated data for the encrypted body, so a ciphertext can't be lifted into another
+    /// message.
+    fn seal_aad(
+        id: MessageId,
+        channel: ChannelId,
+        sender: PeerKey,
+        timestamp: Timestamp,
+    ) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&SyrupUuid(id)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid(channel)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&sender).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&timestamp).unwrap());
+        res
+    }
+
+    pub fn new_signed(
+        id: MessageId,
+        channel: ChannelId,
+        sender: PeerKey,
+        timestamp: Timestamp,
+        reply_to: Option<MessageId>,
+        msg: String,
+        signature: Signature,
+    ) -> Self {
         Self {
-            id: MessageId::new_v4(),
+            id,
+            version: MESSAGE_VERSION,
+            channel,
             sender,
+            timestamp,
+            reply_to: reply_to.map(SyrupUuid),
             msg,
+            sealed: None,
             signature,
         }
     }
 
+    fn sign(
+        channel: ChannelId,
+        sender: PeerKey,
+        reply_to: Option<MessageId>,
+        msg: String,
+        signing_key: &mut SigningKey,
+    ) -> Result<Self, ed25519_dalek::ed25519::Error> {
+        let id = MessageId::new_v4();
+        let timestamp = crate::unix_millis();
+        let signature = signing_key.try_sign(&Self::payload(
+            id, channel, sender, timestamp, reply_to, &msg,
+        ))?;
+        Ok(Self::new_signed(
+            id, channel, sender, timestamp, reply_to, msg, signature,
+        ))
+    }
+
     pub fn new(
+        channel: ChannelId,
+        sender: PeerKey,
+        msg: String,
+        signing_key: &mut SigningKey,
+    ) -> Result<Self, ed25519_dalek::ed25519::Error> {
+        Self::sign(channel, sender, None, msg, signing_key)
+    }
+
+    pub fn reply(
+        channel: ChannelId,
         sender: PeerKey,
+        reply_to: MessageId,
         msg: String,
         signing_key: &mut SigningKey,
     ) -> Result<Self, ed25519_dalek::ed25519::Error> {
+        Self::sign(channel, sender, Some(reply_to), msg, signing_key)
+    }
+
+    /// Create a message readable only by `recipient` (and the sender).
+    pub fn seal(
+        channel: ChannelId,
+        recipient: PeerKey,
+        reply_to: Option<MessageId>,
+        msg: &str,
+        signing_key: &mut SigningKey,
+    ) -> Result<Self, SealError> {
+        let key = ConversationKey::derive(signing_key, &recipient, &channel)?;
+        Self::seal_with(
+            channel,
+            Some(recipient),
+            0,
+            &key,
+            reply_to,
+            msg,
+            signing_key,
+        )
+    }
+
+    fn seal_with(
+        channel: ChannelId,
+        recipient: Option<PeerKey>,
+        epoch: u64,
+        key: &ConversationKey,
+        reply_to: Option<MessageId>,
+        msg: &str,
+        signing_key: &mut SigningKey,
+    ) -> Result<Self, SealError> {
         let id = MessageId::new_v4();
-        let signature = signing_key.try_sign(&Self::fields_to_bytes(id, sender, &msg))?;
+        let sender = signing_key.verifying_key();
+        let timestamp = crate::unix_millis();
+        let (nonce, ciphertext) = key.seal(
+            &Self::seal_aad(id, channel, sender, timestamp),
+            msg.as_bytes(),
+        )?;
+        let sealed = SealedPayload {
+            recipient,
+            epoch,
+            nonce: syrup::Bytes(nonce),
+            ciphertext: syrup::Bytes(ciphertext),
+        };
+        let signature = signing_key.try_sign(&Self::sealed_payload(
+            id, channel, sender, timestamp, reply_to, &sealed,
+        ))?;
         Ok(Self {
             id,
+            version: SEALED_MESSAGE_VERSION,
+            channel,
             sender,
-            msg,
+            timestamp,
+            reply_to: reply_to.map(SyrupUuid),
+            msg: String::new(),
+            sealed: Some(sealed),
             signature,
         })
     }
 
+    pub fn is_sealed(&self) -> bool {
+        self.sealed.is_some()
+    }
+
+    /// Decrypt a sealed direct message's body. Works for both the sender and the recipient;
+    /// channel messages are opened through [`Channel::open_message`].
+    pub fn open(&self, signing_key: &SigningKey) -> Result<String, SealError> {
+        let Some(sealed) = &self.sealed else {
+            return Err(SealError::NotSealed);
+        };
+        let Some(recipient) = sealed.recipient else {
+            return Err(SealError::NotRecipient);
+        };
+        let local_key = signing_key.verifying_key();
+        let counterpart = if self.sender == local_key {
+            recipient
+        } else if recipient == local_key {
+            self.sender
+        } else {
+            return Err(SealError::NotRecipient);
+        };
+        self.open_with(&ConversationKey::derive(
+            signing_key,
+            &counterpart,
+            &self.channel,
+        )?)
+    }
+
+    fn open_with(&self, key: &ConversationKey) -> Result<String, SealError> {
+        let Some(sealed) = &self.sealed else {
+            return Err(SealError::NotSealed);
+        };
+        let plaintext = key.open(
+            &Self::seal_aad(self.id, self.channel, self.sender, self.timestamp),
+            &sealed.nonce.0,
+            &sealed.ciphertext.0,
+        )?;
+        String::from_utf8(plaintext).map_err(|_err| SealError::Decrypt)
+    }
+
+    pub fn reply_to(&self) -> Option<MessageId> {
+        self.reply_to.map(From::from)
+    }
+
+    pub fn is_legacy(&self) -> bool {
+        self.version == LEGACY_MESSAGE_VERSION
+    }
+
+    /// Check the signature over the message. Legacy signatures don't cover the channel, so a
+    /// legacy message only means something in the history it was stored in; messages from peers
+    /// must be checked with [`Self::is_legacy`] too.
     pub fn verify_strict(&self, key: &PeerKey) -> Result<(), SignatureError> {
-        key.verify_strict(
-            &Self::fields_to_bytes(self.id, self.sender, &self.msg),
-            &self.signature,
-        )
+        let payload = match self.version {
+            LEGACY_MESSAGE_VERSION => Self::legacy_payload(self.id, self.sender, &self.msg),
+            MESSAGE_VERSION => Self::payload(
+                self.id,
+                self.channel,
+                self.sender,
+                self.timestamp,
+                self.reply_to(),
+                &self.msg,
+            ),
+            SEALED_MESSAGE_VERSION => match &self.sealed {
+                Some(sealed) => Self::sealed_payload(
+                    self.id,
+                    self.channel,
+                    self.sender,
+                    self.timestamp,
+                    self.reply_to(),
+                    sealed,
+                ),
+                None => return Err(SignatureError::new()),
+            },
+            _ => return Err(SignatureError::new()),
+        };
+        key.verify_strict(&payload, &self.signature)
+    }
+}
+
+/// A message as stored before channel-bound signatures, kept so old history can still be read.
+#[derive(Serialize, Deserialize, Clone)]
+#[syrup(name = "message")]
+pub(crate) struct LegacyMessage {
+    #[syrup(as = SyrupUuid)]
+    id: MessageId,
+    sender: PeerKey,
+    msg: String,
+    signature: Signature,
+}
+
+impl LegacyMessage {
+    pub(crate) fn upgrade(self, channel: ChannelId, timestamp: Timestamp) -> Message {
+        Message {
+            id: self.id,
+            version: LEGACY_MESSAGE_VERSION,
+            channel,
+            sender: self.sender,
+            timestamp,
+            reply_to: None,
+            msg: self.msg,
+            sealed: None,
+            signature: self.signature,
+        }
     }
 }
 
@@ -87,36 +370,102 @@ pub enum ChannelEvent {
         channel: Channel,
         peer_key: PeerKey,
     },
-    Introduce {
+    /// The last session `peer_key` was attached to the channel over ended.
+    PeerDisconnected {
         channel: Channel,
         peer_key: PeerKey,
-        locator: SturdyRefLocator,
+    },
+    HistorySynced {
+        channel: Channel,
+        received: usize,
+    },
+    /// Another member of an encrypted channel gave us a new sender key.
+    SenderKeyReceived {
+        channel: Channel,
+        sender: PeerKey,
+    },
+    RejectedMessage {
+        channel: Channel,
+        message: Message,
+        reason: MessageRejection,
+    },
+    /// A message we sent was acknowledged or read by another member; see
+    /// [`Channel::delivery_state`].
+    DeliveryUpdated {
+        channel: Channel,
+        message: MessageId,
     },
 }
 
-struct Outbox {
-    base: RemoteObject,
-    peer_key: PeerKey,
+#[derive(Debug, thiserror::Error)]
+pub enum MessageRejection {
+    #[error("message arrived over a session with no connected peer")]
+    UnknownSession,
+    #[error(
+        "message claims to be from {} but was sent by {}",
+        rexa::hash(.claimed),
+        rexa::hash(.authenticated)
+    )]
+    SenderMismatch {
+        claimed: PeerKey,
+        authenticated: PeerKey,
+    },
+    #[error("message was sent to channel {got} but arrived on {expected}")]
+    WrongChannel { expected: ChannelId, got: ChannelId },
+    #[error("unsupported message version {0}")]
+    UnsupportedVersion(u64),
+    #[error("messages in this channel must be end-to-end encrypted")]
+    Unsealed,
+    #[error("direct message is addressed to someone else")]
+    NotRecipient,
+    #[error("invalid message signature: {0}")]
+    Signature(#[from] SignatureError),
 }
 
-impl Outbox {
-    fn new(base: RemoteObject, peer_key: PeerKey) -> Arc<Self> {
-        Arc::new(Self { base, peer_key })
+impl MessageRejection {
+    /// The reason sent to the peer when its message is refused.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::UnknownSession => "unknown-session",
+            Self::SenderMismatch { .. } => "sender-mismatch",
+            Self::WrongChannel { .. } => "wrong-channel",
+            Self::UnsupportedVersion(_) => "unsupported-version",
+            Self::Unsealed => "unsealed",
+            Self::NotRecipient => "not-recipient",
+            Self::Signature(_) => "bad-signature",
+        }
     }
 
-    async fn send_msg(&self, message: &Message) -> Result<(), DeliverOnlyError> {
-        self.base.call_only("send_msg", [message]).await
+    /// Whether the message can never be accepted, as opposed to arriving before the session it
+    /// came over was set up.
+    pub fn is_permanent(&self) -> bool {
+        !matches!(self, Self::UnknownSession)
     }
+}
 
-    async fn into_send_msg(self: Arc<Self>, msg: Message) -> Result<(), DeliverOnlyError> {
-        self.send_msg(&msg).await
-    }
+/// A member's answer to a message delivered with `recv_msg`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "recv-ack")]
+pub struct RecvAck {
+    /// Whether the message was new to the member.
+    pub new: bool,
+    /// Why the member refused the message, if it did for good; sending it again won't help.
+    pub rejected: Option<String>,
 }
 
 #[derive(Clone, syrup::Deserialize, syrup::Serialize, Debug)]
 pub struct ChannelInfo {
     pub name: String,
     pub description: String,
+    /// Whether messages in the channel are end-to-end encrypted between members.
+    pub encrypted: bool,
+}
+
+/// The two ends of a direct-message mailbox.
+#[derive(Clone, Copy)]
+struct DirectEnds {
+    local: PeerKey,
+    remote: PeerKey,
 }
 
 struct ChannelCore {
@@ -124,6 +473,32 @@ struct ChannelCore {
     info: ChannelInfo,
 
     ev_sender: mpsc::UnboundedSender<ChannelEvent>,
+    history: Arc<HistoryStore>,
+    /// For direct-message mailboxes, who the messages are between.
+    direct: Option<DirectEnds>,
+    group: Option<GroupSession>,
+    /// The manager's event pipe, through which introductions are handed off.
+    network: OnceLock<EventSender>,
+    /// Whether messages from one member are forwarded to the others.
+    relay: AtomicBool,
+    /// For a channel hosted elsewhere, the host; only it may introduce members to us.
+    host: OnceLock<PeerKey>,
+    /// Sessions with the host, over which its introductions may arrive before it joins us.
+    host_sessions: DashSet<RemoteKey>,
+    /// Members the host vouched for, which may connect to us directly.
+    admitted: DashSet<PeerKey>,
+    /// Delivery of the messages we've sent this session.
+    deliveries: DashMap<MessageId, DeliveryState>,
+    /// Messages we've sent read receipts for.
+    read: DashSet<MessageId>,
+    /// Where messages for offline members wait until they reconnect.
+    outbound: OnceLock<Arc<OutboundQueue>>,
+    /// Members that were connected this session but aren't anymore.
+    absent: DashSet<PeerKey>,
+    /// Keys we've rotated away from, which messages from before the rotation are sealed with.
+    retired: OnceLock<Arc<[SigningKey]>>,
+    /// For direct-message mailboxes, where the keys our other devices shared for it are kept.
+    devices: OnceLock<Arc<LinkedDevices>>,
 
     exported_at: DashMap<RemoteKey, u64>,
     outboxes: DashMap<RemoteKey, Arc<Outbox>>,
@@ -141,9 +516,41 @@ impl std::fmt::Debug for ChannelCore {
 #[derive(Debug, thiserror::Error)]
 pub enum SendMsgError {
     #[error(transparent)]
-    Join(#[from] JoinError),
+    History(#[from] HistoryError),
+    #[error(transparent)]
+    Seal(#[from] SealError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackfillError {
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
     #[error(transparent)]
-    Deliver(#[from] DeliverOnlyError),
+    History(#[from] HistoryError),
+}
+
+pub struct RemoteChannel {
+    base: RemoteObject,
+}
+
+impl RemoteChannel {
+    pub(crate) fn new(base: RemoteObject) -> Self {
+        Self { base }
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn history(&self, request: &HistoryRequest) -> Result<HistoryBatch, RemoteError> {
+        let Some(batch) = self
+            .base
+            .call_and("history", &syrup::raw_syrup_unwrap![request])
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "HistoryBatch"));
+        };
+        HistoryBatch::from_syrup_item(&batch)
+            .map_err(|_err| RemoteError::unexpected("HistoryBatch", 0, batch))
+    }
 }
 
 pub type ChannelId = uuid::Uuid;
@@ -168,9 +575,57 @@ impl Channel {
         &self.core.id
     }
 
+    pub fn history(&self) -> &Arc<HistoryStore> {
+        &self.core.history
+    }
+
+    pub fn query_history(&self, query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        self.core.history.query(&self.core.id, query)
+    }
+
     pub fn new(
         id: uuid::Uuid,
         info: ChannelInfo,
+        history: Arc<HistoryStore>,
+        ev_sender: mpsc::UnboundedSender<ChannelEvent>,
+    ) -> Self {
+        Self::with_core(id, info, history, None, None, ev_sender)
+    }
+
+    /// Create a channel whose messages are end-to-end encrypted between its members.
+    pub fn new_encrypted(
+        id: uuid::Uuid,
+        mut info: ChannelInfo,
+        signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+        history: Arc<HistoryStore>,
+        ev_sender: mpsc::UnboundedSender<ChannelEvent>,
+    ) -> Self {
+        info.encrypted = true;
+        let group = GroupSession::new(id, signing_key, history.key_path(&id));
+        Self::with_core(id, info, history, None, Some(group), ev_sender)
+    }
+
+    pub(crate) fn new_direct(
+        id: uuid::Uuid,
+        info: ChannelInfo,
+        local_key: PeerKey,
+        peer_key: PeerKey,
+        history: Arc<HistoryStore>,
+        ev_sender: mpsc::UnboundedSender<ChannelEvent>,
+    ) -> Self {
+        let ends = DirectEnds {
+            local: local_key,
+            remote: peer_key,
+        };
+        Self::with_core(id, info, history, Some(ends), None, ev_sender)
+    }
+
+    fn with_core(
+        id: uuid::Uuid,
+        info: ChannelInfo,
+        history: Arc<HistoryStore>,
+        direct: Option<DirectEnds>,
+        group: Option<GroupSession>,
         ev_sender: mpsc::UnboundedSender<ChannelEvent>,
     ) -> Self {
         Self {
@@ -178,6 +633,20 @@ impl Channel {
                 id,
                 info,
                 ev_sender,
+                history,
+                direct,
+                group,
+                network: OnceLock::new(),
+                relay: AtomicBool::new(false),
+                host: OnceLock::new(),
+                host_sessions: DashSet::new(),
+                admitted: DashSet::new(),
+                deliveries: DashMap::new(),
+                read: DashSet::new(),
+                outbound: OnceLock::new(),
+                absent: DashSet::new(),
+                retired: OnceLock::new(),
+                devices: OnceLock::new(),
 
                 exported_at: DashMap::new(),
                 outboxes: DashMap::new(),
@@ -185,14 +654,487 @@ impl Channel {
         }
     }
 
-    pub async fn send_msg(&self, message: &Message) -> Result<(), SendMsgError> {
-        let mut send_tasks = JoinSet::new();
-        for outbox in &self.core.outboxes {
-            send_tasks.spawn(outbox.clone().into_send_msg(message.clone()));
+    /// If this is a direct-message mailbox, the peer on the other end.
+    pub fn direct_peer(&self) -> Option<&PeerKey> {
+        self.core.direct.as_ref().map(|ends| &ends.remote)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.core.group.is_some()
+    }
+
+    pub fn has_member(&self, peer_key: &PeerKey) -> bool {
+        self.core
+            .outboxes
+            .iter()
+            .any(|outbox| &outbox.peer_key == peer_key)
+    }
+
+    /// Whether we forward messages between members, so that a channel works as a star around its
+    /// host without every member connecting to every other.
+    pub fn relays(&self) -> bool {
+        self.core.relay.load(Ordering::Acquire)
+    }
+
+    pub fn set_relay(&self, relay: bool) {
+        self.core.relay.store(relay, Ordering::Release);
+    }
+
+    pub(crate) fn attach_network(&self, ev_sender: EventSender) {
+        drop(self.core.network.set(ev_sender));
+    }
+
+    /// Record that the channel is hosted by `host`, which alone may introduce its members to us.
+    pub(crate) fn set_host(&self, host: PeerKey) {
+        drop(self.core.host.set(host));
+    }
+
+    /// Whether `peer_key` may connect to our copy of a channel hosted elsewhere: the host itself,
+    /// or a member it introduced.
+    pub(crate) fn admits(&self, peer_key: &PeerKey) -> bool {
+        self.core.host.get() == Some(peer_key) || self.core.admitted.contains(peer_key)
+    }
+
+    /// Note a session with `peer_key` over which we're joining the channel, so that introductions
+    /// sent over it count as the host's if `peer_key` is the host.
+    pub(crate) fn note_session(&self, peer_key: &PeerKey, session_key: RemoteKey) {
+        if self.core.host.get() == Some(peer_key) {
+            self.core.host_sessions.insert(session_key);
+        }
+    }
+
+    fn is_host_session(&self, session_key: &RemoteKey) -> bool {
+        self.core.host_sessions.contains(session_key)
+    }
+
+    /// Queue messages for offline members in `outbound`, picking up anything already queued for
+    /// this channel and the members that were offline.
+    pub(crate) fn attach_outbound(&self, outbound: Arc<OutboundQueue>) {
+        for peer_key in outbound.absent_from(&self.core.id) {
+            if !self.has_member(&peer_key) {
+                self.core.absent.insert(peer_key);
+            }
+        }
+        for (peer_key, message) in outbound.queued_in(&self.core.id) {
+            self.core
+                .deliveries
+                .entry(message)
+                .or_default()
+                .queued
+                .insert(peer_key);
+        }
+        drop(self.core.outbound.set(outbound));
+    }
+
+    /// Keep reading messages sealed with `retired` keys, ones we've since rotated away from.
+    pub(crate) fn attach_retired_keys(&self, retired: Arc<[SigningKey]>) {
+        if let Some(group) = &self.core.group {
+            group.attach_retired(retired.clone());
         }
-        while let Some(res) = send_tasks.join_next().await {
-            res??;
+        drop(self.core.retired.set(retired));
+    }
+
+    /// Read direct messages our other devices exchanged in this mailbox, with the keys they
+    /// shared in `devices`.
+    pub(crate) fn attach_linked_devices(&self, devices: Arc<LinkedDevices>) {
+        drop(self.core.devices.set(devices));
+    }
+
+    fn retired_keys(&self) -> &[SigningKey] {
+        self.core.retired.get().map_or(&[], |retired| retired)
+    }
+
+    /// Members we can't deliver to right now: those that disconnected, and the other end of a
+    /// direct-message mailbox if it isn't connected.
+    pub fn absent_members(&self) -> Vec<PeerKey> {
+        let mut res = self
+            .core
+            .absent
+            .iter()
+            .map(|peer_key| *peer_key)
+            .collect::<Vec<_>>();
+        if let Some(DirectEnds {
+            remote: peer_key, ..
+        }) = self.core.direct
+        {
+            if !res.contains(&peer_key) && !self.has_member(&peer_key) {
+                res.push(peer_key);
+            }
+        }
+        res
+    }
+
+    /// Sign a new message for this channel, sealing it if this is a direct-message mailbox or an
+    /// encrypted channel.
+    pub fn compose(&self, msg: String, signing_key: &mut SigningKey) -> Result<Message, SealError> {
+        if let Some(DirectEnds {
+            remote: recipient, ..
+        }) = self.core.direct
+        {
+            return Message::seal(self.core.id, recipient, None, &msg, signing_key);
+        }
+        if let Some(group) = &self.core.group {
+            let (epoch, key) = group.current_key();
+            return Message::seal_with(self.core.id, None, epoch, &key, None, &msg, signing_key);
+        }
+        Ok(Message::new(
+            self.core.id,
+            signing_key.verifying_key(),
+            msg,
+            signing_key,
+        )?)
+    }
+
+    /// Read a message sent to this channel, decrypting it if necessary.
+    pub fn open_message(
+        &self,
+        message: &Message,
+        signing_key: &SigningKey,
+    ) -> Result<String, SealError> {
+        let Some(sealed) = &message.sealed else {
+            return Ok(message.msg.clone());
+        };
+        if sealed.recipient.is_some() {
+            // it may have been sealed with a key we've since retired
+            let ends = |key: &&SigningKey| {
+                let local_key = key.verifying_key();
+                message.sender == local_key || sealed.recipient == Some(local_key)
+            };
+            let Some(signing_key) = std::iter::once(signing_key)
+                .chain(self.retired_keys())
+                .find(ends)
+            else {
+                return self.open_linked(message, signing_key);
+            };
+            return message.open(signing_key);
+        }
+        let Some(group) = &self.core.group else {
+            return Err(SealError::UnknownSenderKey);
+        };
+        message.open_with(&group.key_for(&message.sender, sealed.epoch)?)
+    }
+
+    /// Open a direct message another device of ours sent or received, with the mailbox key it
+    /// shared with us.
+    fn open_linked(
+        &self,
+        message: &Message,
+        signing_key: &SigningKey,
+    ) -> Result<String, SealError> {
+        let (Some(devices), Some(recipient)) = (
+            self.core.devices.get(),
+            message.sealed.as_ref().and_then(|sealed| sealed.recipient),
+        ) else {
+            return Err(SealError::NotRecipient);
+        };
+        let Some(shared) = [message.sender, recipient]
+            .iter()
+            .find_map(|holder| devices.mailbox_key(&self.core.id, holder))
+        else {
+            return Err(SealError::NotRecipient);
+        };
+        let counterpart = if shared.holder == message.sender {
+            recipient
+        } else {
+            message.sender
+        };
+        if shared.counterpart != counterpart {
+            return Err(SealError::NotRecipient);
+        }
+        message.open_with(&shared.open(signing_key)?)
+    }
+
+    /// Record `message` and queue it for every connected member. Each member is delivered to
+    /// independently; the report says which of them received it. If the channel has an outbound
+    /// queue, members that are offline or can't be reached get the message when they reconnect.
+    pub async fn send_msg(&self, message: &Message) -> Result<SendReport, SendMsgError> {
+        self.core.history.append(&self.core.id, message.clone())?;
+        let outboxes = self
+            .core
+            .outboxes
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        let outbound = self.core.outbound.get();
+        let mut report = SendReport::default();
+        if let Some(outbound) = outbound {
+            for peer_key in self.absent_members() {
+                if outbound.push(peer_key, message) {
+                    report.queued.push(peer_key);
+                } else {
+                    report.failed.push((peer_key, DeliveryError::QueueFull));
+                }
+            }
+        }
+        let mut grants = Vec::new();
+        if let Some(group) = &self.core.group {
+            // queued ahead of the message, so it arrives after the key needed to read it
+            for grant in group.grants_due(self.known_members())? {
+                for outbox in self.routes_to(None, &grant.recipient) {
+                    grants.push((
+                        grant.clone(),
+                        outbox.enqueue(Delivery::SenderKey(grant.clone())),
+                    ));
+                }
+                group.mark_granted(&grant);
+            }
+        }
+        self.core.deliveries.insert(
+            message.id,
+            DeliveryState {
+                pending: outboxes.len(),
+                queued: report.queued.iter().copied().collect(),
+                ..Default::default()
+            },
+        );
+        let mut deliveries = outboxes
+            .iter()
+            .map(|outbox| {
+                let peer_key = outbox.peer_key;
+                let delivery = outbox.enqueue(Delivery::Message(message.clone()));
+                async move { (peer_key, delivery.await) }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        while let Some((peer_key, res)) = deliveries.next().await {
+            // a member that refused the message won't take it later either
+            let requeue = outbound
+                .filter(|_| res.as_ref().is_err_and(|error| !error.is_rejection()))
+                .filter(|outbound| outbound.push(peer_key, message));
+            if requeue.is_some() {
+                report.queued.push(peer_key);
+            }
+            if let Some(mut state) = self.core.deliveries.get_mut(&message.id) {
+                state.pending = state.pending.saturating_sub(1);
+                if res.is_ok() {
+                    state.delivered.insert(peer_key);
+                } else if requeue.is_some() {
+                    state.queued.insert(peer_key);
+                }
+            }
+            drop(self.core.ev_sender.send(ChannelEvent::DeliveryUpdated {
+                channel: self.clone(),
+                message: message.id,
+            }));
+            match res {
+                Ok(()) => report.delivered.push(peer_key),
+                Err(error) => report.failed.push((peer_key, error)),
+            }
+        }
+        for (grant, delivery) in grants {
+            if delivery.await.is_err() {
+                if let Some(group) = &self.core.group {
+                    // try again with the next message
+                    group.revoke_grant(&grant);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Who has received and read a message we sent this session.
+    pub fn delivery_state(&self, message: &MessageId) -> Option<DeliveryState> {
+        self.core
+            .deliveries
+            .get(message)
+            .map(|state| state.value().clone())
+    }
+
+    /// Send read receipts for `messages` to their authors, skipping our own messages and any
+    /// we've already acknowledged. Returns how many receipts were sent.
+    pub fn mark_read<'m>(
+        &self,
+        messages: impl IntoIterator<Item = &'m Message>,
+        signing_key: &mut SigningKey,
+    ) -> Result<usize, SignatureError> {
+        let local_key = signing_key.verifying_key();
+        let mut sent = 0;
+        for message in messages {
+            if message.sender == local_key || self.core.read.contains(&message.id) {
+                continue;
+            }
+            let receipt = ReadReceipt::new(self.core.id, message.id, message.sender, signing_key)?;
+            self.route_receipt(None, &receipt);
+            self.core.read.insert(message.id);
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Everyone we know to be in the channel: the members connected to us, and those the host
+    /// vouched for.
+    fn known_members(&self) -> HashSet<PeerKey> {
+        let mut res = self
+            .core
+            .outboxes
+            .iter()
+            .map(|entry| entry.peer_key)
+            .collect::<HashSet<_>>();
+        res.extend(self.core.admitted.iter().map(|peer_key| *peer_key));
+        res
+    }
+
+    /// The outboxes leading to `peer_key`: its own if it's connected, otherwise any member that
+    /// relays. Something that arrived over `from` is already being relayed, so it only goes on
+    /// directly.
+    fn routes_to(&self, from: Option<&RemoteKey>, peer_key: &PeerKey) -> Vec<Arc<Outbox>> {
+        let direct = self
+            .core
+            .outboxes
+            .iter()
+            .filter(|entry| Some(entry.key()) != from && &entry.peer_key == peer_key)
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        if direct.is_empty() && from.is_none() {
+            self.core
+                .outboxes
+                .iter()
+                .filter(|entry| entry.relays)
+                .map(|entry| entry.value().clone())
+                .collect()
+        } else {
+            direct
+        }
+    }
+
+    /// Whether `receipt` comes from a member and names the author of a message in this channel's
+    /// history.
+    fn receipt_matches(&self, receipt: &ReadReceipt) -> bool {
+        if !self.known_members().contains(&receipt.reader) {
+            return false;
+        }
+        match self.core.history.get(&self.core.id, &receipt.message) {
+            Ok(Some(message)) => {
+                message.channel == self.core.id && message.sender == receipt.author
+            }
+            Ok(None) => false,
+            Err(error) => {
+                tracing::error!(channel = %self.core.id, %error, "failed to read channel history");
+                false
+            }
+        }
+    }
+
+    /// Send a receipt towards its author: directly if they're connected, otherwise through any
+    /// member that relays.
+    fn route_receipt(&self, from: Option<&RemoteKey>, receipt: &ReadReceipt) {
+        for outbox in self.routes_to(from, &receipt.author) {
+            drop(outbox.enqueue(Delivery::ReadReceipt(receipt.clone())));
         }
+    }
+
+    /// The state of each connected member's delivery queue.
+    pub fn peer_status(&self) -> Vec<PeerStatus> {
+        self.core
+            .outboxes
+            .iter()
+            .map(|entry| entry.value().status())
+            .collect()
+    }
+
+    /// Fetch everything `remote` has recorded after `since`, verify it, and add it to the local
+    /// history. Returns the number of newly recorded messages.
+    #[tracing::instrument(skip(self, remote), fields(channel = %self.core.id))]
+    pub async fn backfill(
+        &self,
+        remote: &RemoteChannel,
+        since: Option<HistoryCursor>,
+    ) -> Result<usize, BackfillError> {
+        let mut request = HistoryRequest::since(since);
+        let mut received = 0;
+        loop {
+            let batch = remote.history(&request).await?;
+            for message in &batch.messages {
+                // a legacy signature could have been lifted from any other channel
+                if message.is_legacy() {
+                    tracing::warn!(message = %message.id, "dropping backfilled legacy message");
+                    continue;
+                }
+                if message.channel != self.core.id {
+                    tracing::warn!(
+                        message = %message.id,
+                        other_channel = %message.channel,
+                        "dropping backfilled message from another channel"
+                    );
+                    continue;
+                }
+                if let Err(error) = message.verify_strict(&message.sender) {
+                    tracing::warn!(
+                        message = %message.id,
+                        sender = rexa::hash(&message.sender),
+                        %error,
+                        "dropping backfilled message with invalid signature"
+                    );
+                    continue;
+                }
+                if self.core.history.append(&self.core.id, message.clone())? {
+                    received += 1;
+                }
+            }
+            match batch.messages.last() {
+                Some(last) if batch.has_more => {
+                    request = HistoryRequest::since(Some(HistoryCursor::Message(last.id)));
+                }
+                _ => break,
+            }
+        }
+        tracing::debug!(received, "backfilled channel history");
+        drop(self.core.ev_sender.send(ChannelEvent::HistorySynced {
+            channel: self.clone(),
+            received,
+        }));
+        Ok(received)
+    }
+
+    /// Check that `message` is signed by its sender, and that the sender is the peer authenticated
+    /// on the session it arrived over.
+    fn verify_incoming(
+        &self,
+        session_key: &RemoteKey,
+        message: &Message,
+    ) -> Result<(), MessageRejection> {
+        if message.version != MESSAGE_VERSION && message.version != SEALED_MESSAGE_VERSION {
+            return Err(MessageRejection::UnsupportedVersion(message.version));
+        }
+        if let Some(ends) = &self.core.direct {
+            let Some(sealed) = &message.sealed else {
+                return Err(MessageRejection::Unsealed);
+            };
+            let to_us = sealed.recipient.is_some_and(|recipient| {
+                recipient == ends.local
+                    || self
+                        .retired_keys()
+                        .iter()
+                        .any(|key| key.verifying_key() == recipient)
+            });
+            if !to_us {
+                return Err(MessageRejection::NotRecipient);
+            }
+        }
+        if self.core.group.is_some()
+            && !message
+                .sealed
+                .as_ref()
+                .is_some_and(|sealed| sealed.recipient.is_none())
+        {
+            return Err(MessageRejection::Unsealed);
+        }
+        if message.channel != self.core.id {
+            return Err(MessageRejection::WrongChannel {
+                expected: self.core.id,
+                got: message.channel,
+            });
+        }
+        let Some(outbox) = self.core.outboxes.get(session_key) else {
+            return Err(MessageRejection::UnknownSession);
+        };
+        if message.sender != outbox.peer_key && !outbox.relays {
+            return Err(MessageRejection::SenderMismatch {
+                claimed: message.sender,
+                authenticated: outbox.peer_key,
+            });
+        }
+        message.verify_strict(&message.sender)?;
         Ok(())
     }
 
@@ -208,32 +1150,429 @@ impl Channel {
         session_key: RemoteKey,
         peer_key: PeerKey,
         outbox: RemoteObject,
+        locator: Option<SturdyRefLocator>,
+        relays: bool,
     ) {
-        let outbox = Outbox::new(outbox, peer_key);
+        // a peer reconnecting over a new session replaces whatever it left behind on the old one
+        let stale = self
+            .core
+            .outboxes
+            .iter()
+            .filter(|entry| entry.peer_key == peer_key)
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        for stale_key in stale {
+            if stale_key != session_key {
+                self.core.exported_at.remove(&stale_key);
+            }
+            if let Some((_, stale)) = self.core.outboxes.remove(&stale_key) {
+                stale.close();
+            }
+        }
 
-        self.core.outboxes.insert(session_key, outbox);
+        let outbox = Outbox::new(outbox, peer_key, locator, relays);
+        self.core.outboxes.insert(session_key, outbox.clone());
+        self.core.absent.remove(&peer_key);
+        if let Some(outbound) = self.core.outbound.get() {
+            outbound.set_absent(self.core.id, peer_key, false);
+        }
+        self.flush_outbound(&outbox);
+        if let Some(group) = &self.core.group {
+            // don't let the new member read anything sent before it joined
+            group.rotate();
+        }
 
         drop(self.core.ev_sender.send(ChannelEvent::PeerConnected {
             channel: self.clone(),
             peer_key,
         }));
     }
+
+    pub(crate) fn disconnect_peer(&self, session_key: &RemoteKey) {
+        self.core.exported_at.remove(session_key);
+        let Some((_, outbox)) = self.core.outboxes.remove(session_key) else {
+            return;
+        };
+        outbox.close();
+        if self.has_member(&outbox.peer_key) {
+            return;
+        }
+        self.core.absent.insert(outbox.peer_key);
+        if let Some(outbound) = self.core.outbound.get() {
+            outbound.set_absent(self.core.id, outbox.peer_key, true);
+        }
+        if let Some(group) = &self.core.group {
+            group.forget_member(&outbox.peer_key);
+        }
+        drop(self.core.ev_sender.send(ChannelEvent::PeerDisconnected {
+            channel: self.clone(),
+            peer_key: outbox.peer_key,
+        }));
+    }
+
+    /// Send everything queued for the member behind `outbox` while it was offline, in the order
+    /// it was sent. Messages stay queued until the member acknowledges or refuses them. The queue
+    /// may hold more than fits in the outbox, so each message waits for room before it's handed
+    /// over.
+    fn flush_outbound(&self, outbox: &Arc<Outbox>) {
+        let Some(outbound) = self.core.outbound.get() else {
+            return;
+        };
+        let peer_key = outbox.peer_key;
+        let messages = outbound.waiting(&self.core.id, &peer_key);
+        if messages.is_empty() {
+            return;
+        }
+        for message in &messages {
+            if let Some(mut state) = self.core.deliveries.get_mut(&message.id) {
+                state.queued.remove(&peer_key);
+                state.pending += 1;
+            }
+        }
+        tracing::debug!(
+            channel = %self.core.id,
+            peer_key = rexa::hash(&peer_key),
+            queued = messages.len(),
+            "flushing outbound queue"
+        );
+        let channel = self.clone();
+        let outbound = outbound.clone();
+        let outbox = outbox.clone();
+        tokio::spawn(async move {
+            let mut granted = Vec::new();
+            let mut flushes = Vec::with_capacity(messages.len());
+            for message in messages {
+                let epoch = message.sealed.as_ref().map(|sealed| sealed.epoch);
+                if let (Some(group), Some(epoch)) = (&channel.core.group, epoch) {
+                    // the member missed the key these were sealed with
+                    if !granted.contains(&epoch) {
+                        match group.grant_epoch(peer_key, epoch) {
+                            Ok(grant) => {
+                                drop(outbox.enqueue_when_ready(Delivery::SenderKey(grant)).await);
+                            }
+                            Err(error) => tracing::warn!(
+                                channel = %channel.core.id,
+                                epoch,
+                                %error,
+                                "could not grant sender key for queued messages"
+                            ),
+                        }
+                        granted.push(epoch);
+                    }
+                }
+                let id = message.id;
+                let delivery = outbox.enqueue_when_ready(Delivery::Message(message)).await;
+                flushes.push((id, delivery));
+            }
+            for (message, delivery) in flushes {
+                let res = delivery.await;
+                // a refused message would only be refused again
+                let rejected = res.as_ref().is_err_and(DeliveryError::is_rejection);
+                if res.is_ok() || rejected {
+                    outbound.remove(&peer_key, &message);
+                }
+                if let Some(mut state) = channel.core.deliveries.get_mut(&message) {
+                    state.pending = state.pending.saturating_sub(1);
+                    if res.is_ok() {
+                        state.delivered.insert(peer_key);
+                    } else if !rejected {
+                        state.queued.insert(peer_key);
+                    }
+                }
+                drop(channel.core.ev_sender.send(ChannelEvent::DeliveryUpdated {
+                    channel: channel.clone(),
+                    message,
+                }));
+            }
+        });
+    }
+
+    /// Verify and record a message that arrived over `session_key`. Answers whether it was new or
+    /// why it was refused for good; the error is a rejection worth retrying.
+    fn receive(&self, session_key: &RemoteKey, message: Message) -> Result<RecvAck, &'static str> {
+        if let Err(reason) = self.verify_incoming(session_key, &message) {
+            tracing::warn!(
+                channel = %self.core.id,
+                message = %message.id,
+                %reason,
+                "rejected incoming message"
+            );
+            let res = reason.reason();
+            let permanent = reason.is_permanent();
+            drop(self.core.ev_sender.send(ChannelEvent::RejectedMessage {
+                channel: self.clone(),
+                message,
+                reason,
+            }));
+            if !permanent {
+                return Err(res);
+            }
+            return Ok(RecvAck {
+                new: false,
+                rejected: Some(res.to_owned()),
+            });
+        }
+        match self.core.history.append(&self.core.id, message.clone()) {
+            Ok(true) => {}
+            Ok(false) => {
+                // already seen, either directly or through a relay
+                tracing::trace!(channel = %self.core.id, message = %message.id, "dropped duplicate message");
+                return Ok(RecvAck {
+                    new: false,
+                    rejected: None,
+                });
+            }
+            Err(error) => {
+                tracing::error!(channel = %self.core.id, %error, "failed to record received message");
+            }
+        }
+        if self.relays() {
+            self.relay(session_key, &message);
+        }
+        drop(self.core.ev_sender.send(ChannelEvent::RecvMessage {
+            channel: self.clone(),
+            message,
+        }));
+        Ok(RecvAck {
+            new: true,
+            rejected: None,
+        })
+    }
+
+    /// Forward a message that arrived over `from` to every other member.
+    fn relay(&self, from: &RemoteKey, message: &Message) {
+        for outbox in self
+            .core
+            .outboxes
+            .iter()
+            .filter(|entry| entry.key() != from && entry.peer_key != message.sender)
+        {
+            // the outbox reports its own failures
+            drop(outbox.enqueue(Delivery::Message(message.clone())));
+        }
+    }
+
+    /// Vouch for the member connected over `session_key` to every other member, and tell it about
+    /// them, with how to reach those that gave us a locator.
+    #[tracing::instrument(skip(self), fields(channel = %self.core.id, session = rexa::hash(session_key)))]
+    pub(crate) async fn introduce_members(
+        &self,
+        session_key: &RemoteKey,
+    ) -> Result<(), DeliveryError> {
+        let Some(newcomer) = self
+            .core
+            .outboxes
+            .get(session_key)
+            .map(|entry| entry.value().clone())
+        else {
+            return Ok(());
+        };
+        let others = self
+            .core
+            .outboxes
+            .iter()
+            .filter(|entry| entry.key() != session_key && entry.peer_key != newcomer.peer_key)
+            .map(|entry| entry.value().clone())
+            .collect::<Vec<_>>();
+        // vouch for the newcomer first, so the others let it in once it connects to them
+        for member in &others {
+            let admit = Delivery::Admit {
+                peer_key: newcomer.peer_key,
+            };
+            if let Err(error) = member.enqueue(admit).await {
+                tracing::warn!(
+                    peer_key = rexa::hash(&member.peer_key),
+                    %error,
+                    "failed to vouch for newcomer"
+                );
+            }
+        }
+        let count = others.len();
+        for member in others {
+            // members we can't introduce are still vouched for, so the newcomer grants them its
+            // sender key through us
+            let delivery = match member.locator.clone() {
+                Some(locator) => Delivery::Introduce {
+                    peer_key: member.peer_key,
+                    locator,
+                },
+                None => Delivery::Admit {
+                    peer_key: member.peer_key,
+                },
+            };
+            newcomer.enqueue(delivery).await?;
+        }
+        tracing::debug!(introduced = count, "introduced members to newcomer");
+        Ok(())
+    }
 }
 
 #[impl_object(tracing = ::tracing)]
 impl Channel {
     #[deliver_only(symbol = "send_msg")]
-    fn deliver_msg(&self, message: Message) -> Result<(), ObjectError> {
-        drop(self.core.ev_sender.send(ChannelEvent::RecvMessage {
+    #[allow(clippy::needless_pass_by_value)]
+    fn deliver_msg(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        message: Message,
+    ) -> Result<(), ObjectError> {
+        drop(self.receive(session.remote_vkey(), message));
+        Ok(())
+    }
+
+    /// Like `send_msg`, but answers once the message has been accepted or refused for good, so the
+    /// sender knows whether to try again.
+    #[deliver()]
+    #[allow(clippy::needless_pass_by_value)]
+    fn recv_msg(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        message: Message,
+    ) -> Result<RecvAck, &'static str> {
+        self.receive(session.remote_vkey(), message)
+    }
+
+    #[deliver()]
+    fn history(&self, request: HistoryRequest) -> Result<HistoryBatch, &'static str> {
+        match self.core.history.query(&self.core.id, &request.query()) {
+            Ok(page) => Ok(page.into()),
+            Err(HistoryError::UnknownCursor(_)) => Err("unknown history cursor"),
+            Err(error) => {
+                tracing::error!(%error, "failed to query channel history");
+                Err("could not read channel history")
+            }
+        }
+    }
+
+    #[deliver_only()]
+    #[allow(clippy::needless_pass_by_value)]
+    fn sender_key(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        grant: SenderKeyGrant,
+    ) -> Result<(), ObjectError> {
+        let Some(group) = &self.core.group else {
+            tracing::warn!(channel = %self.core.id, "received sender key for unencrypted channel");
+            return Ok(());
+        };
+        if !self.known_members().contains(&grant.sender) {
+            tracing::warn!(
+                channel = %self.core.id,
+                sender = rexa::hash(&grant.sender),
+                "ignoring sender key from a non-member"
+            );
+            return Ok(());
+        }
+        if !group.is_ours(&grant.recipient) {
+            // meant for a member we relay to, like the sender's messages
+            if !self.relays() {
+                return Ok(());
+            }
+            if let Err(error) = grant.verify() {
+                tracing::warn!(
+                    channel = %self.core.id,
+                    sender = rexa::hash(&grant.sender),
+                    %error,
+                    "refusing to relay sender key"
+                );
+                return Ok(());
+            }
+            if grant.channel == self.core.id {
+                for outbox in self.routes_to(Some(session.remote_vkey()), &grant.recipient) {
+                    drop(outbox.enqueue(Delivery::SenderKey(grant.clone())));
+                }
+            }
+            return Ok(());
+        }
+        if let Err(error) = group.receive(&grant) {
+            tracing::warn!(
+                channel = %self.core.id,
+                sender = rexa::hash(&grant.sender),
+                %error,
+                "rejected sender key"
+            );
+            return Ok(());
+        }
+        drop(self.core.ev_sender.send(ChannelEvent::SenderKeyReceived {
             channel: self.clone(),
-            message,
+            sender: grant.sender,
         }));
         Ok(())
     }
 
     #[deliver_only()]
-    fn introduce(&self, peer_key: PeerKey, locator: SturdyRefLocator) -> Result<(), ObjectError> {
-        drop(self.core.ev_sender.send(ChannelEvent::Introduce {
+    #[allow(clippy::needless_pass_by_value)]
+    fn read_receipt(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        receipt: ReadReceipt,
+    ) -> Result<(), ObjectError> {
+        if receipt.channel != self.core.id {
+            return Ok(());
+        }
+        if let Err(error) = receipt.verify() {
+            tracing::warn!(
+                channel = %self.core.id,
+                reader = rexa::hash(&receipt.reader),
+                %error,
+                "rejected read receipt"
+            );
+            return Ok(());
+        }
+        if !self.receipt_matches(&receipt) {
+            tracing::warn!(
+                channel = %self.core.id,
+                reader = rexa::hash(&receipt.reader),
+                message = %receipt.message,
+                "ignoring read receipt for a message it doesn't match"
+            );
+            return Ok(());
+        }
+        if let Some(mut state) = self.core.deliveries.get_mut(&receipt.message) {
+            state.delivered.insert(receipt.reader);
+            state.read.insert(receipt.reader);
+            drop(state);
+            drop(self.core.ev_sender.send(ChannelEvent::DeliveryUpdated {
+                channel: self.clone(),
+                message: receipt.message,
+            }));
+        } else if self.relays() {
+            self.route_receipt(Some(session.remote_vkey()), &receipt);
+        }
+        Ok(())
+    }
+
+    /// The host telling us about a member to connect to. The introduced member still has to
+    /// authenticate as `peer_key` before it's admitted.
+    #[deliver_only()]
+    #[allow(clippy::needless_pass_by_value)]
+    fn introduce(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        peer_key: PeerKey,
+        locator: SturdyRefLocator,
+    ) -> Result<(), ObjectError> {
+        if !self.is_host_session(session.remote_vkey()) {
+            tracing::warn!(
+                channel = %self.core.id,
+                peer_key = rexa::hash(&peer_key),
+                "ignoring introduction from a member other than the host"
+            );
+            return Ok(());
+        }
+        self.core.admitted.insert(peer_key);
+        if self.has_member(&peer_key) {
+            return Ok(());
+        }
+        let Some(network) = self.core.network.get() else {
+            tracing::warn!(
+                channel = %self.core.id,
+                peer_key = rexa::hash(&peer_key),
+                "dropping introduction to channel not managed by a ChatManager"
+            );
+            return Ok(());
+        };
+        drop(network.send(NetworkEvent::Introduce {
             channel: self.clone(),
             peer_key,
             locator,
@@ -241,6 +1580,30 @@ impl Channel {
         Ok(())
     }
 
+    /// The host vouching for a newcomer, which may then connect to us directly.
+    #[deliver_only()]
+    #[allow(clippy::needless_pass_by_value)]
+    fn admit(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        peer_key: PeerKey,
+    ) -> Result<(), ObjectError> {
+        if self.is_host_session(session.remote_vkey()) {
+            self.core.admitted.insert(peer_key);
+            if let Some(group) = &self.core.group {
+                // the newcomer gets a fresh key of ours, relayed by the host
+                group.forget_member(&peer_key);
+            }
+        } else {
+            tracing::warn!(
+                channel = %self.core.id,
+                peer_key = rexa::hash(&peer_key),
+                "ignoring admission from a member other than the host"
+            );
+        }
+        Ok(())
+    }
+
     #[exported()]
     #[tracing::instrument(fields(remote_key = rexa::hash(remote_key)))]
     fn exported(&self, remote_key: &RemoteKey, position: rexa::captp::msg::DescExport) {
@@ -248,3 +1611,51 @@ impl Channel {
         tracing::debug!("channel exported");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::OutboundQueue;
+
+    #[tokio::test]
+    async fn flushes_more_queued_messages_than_an_outbox_holds() {
+        let (ev_sender, _events) = mpsc::unbounded_channel();
+        let info = ChannelInfo {
+            name: "flush".to_owned(),
+            description: String::new(),
+            encrypted: false,
+        };
+        let id = ChannelId::new_v4();
+        let channel = Channel::new(id, info, Arc::new(HistoryStore::in_memory()), ev_sender);
+        let mut signing_key = SigningKey::generate(&mut OsRng);
+        let sender = signing_key.verifying_key();
+        let peer_key = SigningKey::generate(&mut OsRng).verifying_key();
+        let outbound = Arc::new(OutboundQueue::in_memory());
+        let mut sent = Vec::new();
+        for i in 0..OUTBOX_CAPACITY * 2 + 1 {
+            let message = Message::new(id, sender, format!("{i}"), &mut signing_key).unwrap();
+            assert!(outbound.push(peer_key, &message));
+            sent.push(message.id);
+        }
+        channel.attach_outbound(outbound.clone());
+
+        let (outbox, mut jobs) = Outbox::detached(peer_key);
+        channel.flush_outbound(&outbox);
+        let mut flushed = Vec::new();
+        while flushed.len() < sent.len() {
+            let job = jobs.recv().await.unwrap();
+            if let Delivery::Message(message) = &job.delivery {
+                flushed.push(message.id);
+            }
+            drop(job.done.send(Ok(())));
+        }
+        assert_eq!(flushed, sent);
+
+        // acknowledged messages no longer wait for the member
+        while !outbound.is_empty() {
+            tokio::task::yield_now().await;
+        }
+    }
+}
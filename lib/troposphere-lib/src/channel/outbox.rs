@@ -0,0 +1,274 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rexa::{captp::object::RemoteObject, locator::SturdyRefLocator};
+use syrup::FromSyrupItem;
+use tokio::sync::{mpsc, oneshot};
+
+use super::RecvAck;
+use crate::{Message, PeerKey, ReadReceipt, SenderKeyGrant};
+
+/// How many deliveries may wait for one peer before further sends to it fail immediately.
+pub const OUTBOX_CAPACITY: usize = 64;
+/// How many times a delivery is attempted before it's given up on.
+pub const OUTBOX_MAX_ATTEMPTS: u32 = 5;
+/// The delay before the first retry; doubled after every further failure.
+const OUTBOX_BACKOFF: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DeliveryError {
+    #[error("too many messages are already waiting for this peer")]
+    QueueFull,
+    #[error("peer disconnected before the message was delivered")]
+    Disconnected,
+    #[error("gave up after {attempts} attempts: {reason}")]
+    Exhausted { attempts: u32, reason: String },
+    #[error("peer refused the message: {reason}")]
+    Rejected { reason: String },
+}
+
+impl DeliveryError {
+    /// Whether the peer refused the delivery outright, so sending it again won't help.
+    pub fn is_rejection(&self) -> bool {
+        matches!(self, Self::Rejected { .. })
+    }
+}
+
+/// The outcome of sending a message to each member of a channel.
+#[derive(Debug, Default)]
+pub struct SendReport {
+    pub delivered: Vec<PeerKey>,
+    pub failed: Vec<(PeerKey, DeliveryError)>,
+    /// Members the message was queued for, to be sent when they reconnect. Includes members it
+    /// failed to reach, if the channel has an outbound queue.
+    pub queued: Vec<PeerKey>,
+}
+
+impl SendReport {
+    /// Whether every member received the message.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// A snapshot of the delivery queue for one member of a channel.
+#[derive(Debug, Clone)]
+pub struct PeerStatus {
+    pub peer_key: PeerKey,
+    /// Deliveries waiting to be sent.
+    pub queued: usize,
+    /// Deliveries given up on since the last successful one.
+    pub failures: u32,
+}
+
+pub(super) enum Delivery {
+    /// Sent on the acknowledged path, so it only counts as delivered once the member accepts it.
+    Message(Message),
+    SenderKey(SenderKeyGrant),
+    ReadReceipt(ReadReceipt),
+    Introduce {
+        peer_key: PeerKey,
+        locator: SturdyRefLocator,
+    },
+    /// Vouching for a newcomer the member is about to be introduced to.
+    Admit {
+        peer_key: PeerKey,
+    },
+}
+
+/// Why an attempt at a delivery failed.
+enum Failure {
+    /// Worth another try, e.g. because the connection hiccupped.
+    Transient(String),
+    /// The member refused it for good.
+    Rejected(String),
+}
+
+impl Delivery {
+    async fn send(&self, base: &RemoteObject) -> Result<(), Failure> {
+        match self {
+            Self::Message(message) => {
+                let mut res = base
+                    .call_and("recv_msg", &syrup::raw_syrup_unwrap![message])
+                    .await
+                    .map_err(|error| Failure::Transient(error.to_string()))?;
+                let ack = res
+                    .pop()
+                    .and_then(|ack| RecvAck::from_syrup_item(&ack).ok());
+                match ack.and_then(|ack| ack.rejected) {
+                    Some(reason) => Err(Failure::Rejected(reason)),
+                    None => Ok(()),
+                }
+            }
+            Self::SenderKey(grant) => base
+                .call_only("sender_key", [grant])
+                .await
+                .map_err(|error| Failure::Transient(error.to_string())),
+            Self::ReadReceipt(receipt) => base
+                .call_only("read_receipt", [receipt])
+                .await
+                .map_err(|error| Failure::Transient(error.to_string())),
+            Self::Introduce { peer_key, locator } => base
+                .call_only("introduce", &syrup::raw_syrup_unwrap![peer_key, locator])
+                .await
+                .map_err(|error| Failure::Transient(error.to_string())),
+            Self::Admit { peer_key } => base
+                .call_only("admit", [peer_key])
+                .await
+                .map_err(|error| Failure::Transient(error.to_string())),
+        }
+    }
+}
+
+pub(super) struct Job {
+    pub(super) delivery: Delivery,
+    pub(super) done: oneshot::Sender<Result<(), DeliveryError>>,
+}
+
+#[derive(Default)]
+struct OutboxHealth {
+    closed: AtomicBool,
+    failures: AtomicU32,
+}
+
+/// Our connection to one member of a channel. Deliveries are queued and sent in order by a task
+/// of the outbox's own, so a slow or unreachable member doesn't hold up the others.
+pub(super) struct Outbox {
+    pub(super) peer_key: PeerKey,
+    /// Where the member can be reached, if it told us; used to introduce it to newcomers.
+    pub(super) locator: Option<SturdyRefLocator>,
+    /// Whether the peer forwards other members' messages to us.
+    pub(super) relays: bool,
+    queue: mpsc::Sender<Job>,
+    health: Arc<OutboxHealth>,
+}
+
+impl Outbox {
+    pub(super) fn new(
+        base: RemoteObject,
+        peer_key: PeerKey,
+        locator: Option<SturdyRefLocator>,
+        relays: bool,
+    ) -> Arc<Self> {
+        let (queue, jobs) = mpsc::channel(OUTBOX_CAPACITY);
+        let health = Arc::new(OutboxHealth::default());
+        tokio::spawn(run_outbox(base, peer_key, jobs, health.clone()));
+        Arc::new(Self {
+            peer_key,
+            locator,
+            relays,
+            queue,
+            health,
+        })
+    }
+
+    /// An outbox whose deliveries are handed to the caller rather than sent, which plays the
+    /// member.
+    #[cfg(test)]
+    pub(super) fn detached(peer_key: PeerKey) -> (Arc<Self>, mpsc::Receiver<Job>) {
+        let (queue, jobs) = mpsc::channel(OUTBOX_CAPACITY);
+        let outbox = Arc::new(Self {
+            peer_key,
+            locator: None,
+            relays: false,
+            queue,
+            health: Arc::new(OutboxHealth::default()),
+        });
+        (outbox, jobs)
+    }
+
+    /// Queue `delivery`; the returned future resolves once it has been sent or given up on. The
+    /// delivery is queued whether or not the future is awaited.
+    pub(super) fn enqueue(
+        &self,
+        delivery: Delivery,
+    ) -> impl Future<Output = Result<(), DeliveryError>> + Send + 'static {
+        let (done, receipt) = oneshot::channel();
+        let queued = match self.queue.try_send(Job { delivery, done }) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => Err(DeliveryError::QueueFull),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(DeliveryError::Disconnected),
+        };
+        async move {
+            queued?;
+            receipt.await.unwrap_or(Err(DeliveryError::Disconnected))
+        }
+    }
+
+    /// Like [`Self::enqueue`], but waits for room in the queue instead of failing while it's
+    /// full. The delivery is queued once this resolves, whether or not the returned future is
+    /// awaited.
+    pub(super) async fn enqueue_when_ready(
+        &self,
+        delivery: Delivery,
+    ) -> impl Future<Output = Result<(), DeliveryError>> + Send + 'static {
+        let (done, receipt) = oneshot::channel();
+        let queued = self
+            .queue
+            .send(Job { delivery, done })
+            .await
+            .map_err(|_err| DeliveryError::Disconnected);
+        async move {
+            queued?;
+            receipt.await.unwrap_or(Err(DeliveryError::Disconnected))
+        }
+    }
+
+    /// Fail everything still queued; called when the member disconnects.
+    pub(super) fn close(&self) {
+        self.health.closed.store(true, Ordering::Release);
+    }
+
+    pub(super) fn status(&self) -> PeerStatus {
+        PeerStatus {
+            peer_key: self.peer_key,
+            queued: OUTBOX_CAPACITY - self.queue.capacity(),
+            failures: self.health.failures.load(Ordering::Acquire),
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(peer_key = rexa::hash(&peer_key)))]
+async fn run_outbox(
+    base: RemoteObject,
+    peer_key: PeerKey,
+    mut jobs: mpsc::Receiver<Job>,
+    health: Arc<OutboxHealth>,
+) {
+    while let Some(Job { delivery, done }) = jobs.recv().await {
+        let mut attempts = 0;
+        let res = loop {
+            if health.closed.load(Ordering::Acquire) {
+                break Err(DeliveryError::Disconnected);
+            }
+            attempts += 1;
+            match delivery.send(&base).await {
+                Ok(()) => break Ok(()),
+                Err(Failure::Rejected(reason)) => break Err(DeliveryError::Rejected { reason }),
+                Err(Failure::Transient(reason)) if attempts >= OUTBOX_MAX_ATTEMPTS => {
+                    break Err(DeliveryError::Exhausted { attempts, reason })
+                }
+                Err(Failure::Transient(reason)) => {
+                    let backoff = OUTBOX_BACKOFF * 2u32.pow(attempts - 1);
+                    tracing::debug!(attempts, ?backoff, %reason, "delivery failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                }
+            }
+        };
+        match &res {
+            Ok(()) => health.failures.store(0, Ordering::Release),
+            Err(error) => {
+                health.failures.fetch_add(1, Ordering::AcqRel);
+                tracing::warn!(%error, "gave up on delivery");
+            }
+        }
+        drop(done.send(res));
+    }
+    tracing::trace!("outbox closed");
+}
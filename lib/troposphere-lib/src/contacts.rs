@@ -0,0 +1,338 @@
+use std::path::PathBuf;
+
+use dashmap::DashMap;
+use rexa::locator::NodeLocator;
+
+use crate::{store, PeerKey, Timestamp};
+
+/// What we know about a peer, independent of what it says about itself.
+#[derive(Debug, Clone, Default)]
+pub struct Contact {
+    /// The name we gave the peer. Unlike [`crate::Profile::username`], no one else can set it.
+    pub petname: Option<String>,
+    /// Where we have reached the peer before.
+    pub locators: Vec<NodeLocator>,
+    pub notes: String,
+    pub last_seen: Option<Timestamp>,
+    /// When the user confirmed the peer's [`crate::SafetyNumber`], if they have.
+    pub verified_at: Option<Timestamp>,
+}
+
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "contact")]
+pub(crate) struct ContactRecord {
+    peer_key: PeerKey,
+    petname: Option<String>,
+    locators: Vec<NodeLocator>,
+    notes: String,
+    last_seen: Option<Timestamp>,
+    verified_at: Option<Timestamp>,
+}
+
+/// Persisted contacts, keyed by peer identity. Peers are added as we meet them; petnames and notes
+/// are up to the user.
+pub struct AddressBook {
+    path: Option<PathBuf>,
+    entries: DashMap<PeerKey, Contact>,
+}
+
+impl std::fmt::Debug for AddressBook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AddressBook")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Default for AddressBook {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl AddressBook {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let entries = DashMap::new();
+        for record in store::read_records::<ContactRecord>(&path)? {
+            let (peer_key, contact) = record.into_contact();
+            entries.insert(peer_key, contact);
+        }
+        Ok(Self {
+            path: Some(path),
+            entries,
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: DashMap::new(),
+        }
+    }
+
+    pub fn get(&self, peer_key: &PeerKey) -> Option<Contact> {
+        self.entries.get(peer_key).map(|entry| entry.clone())
+    }
+
+    pub fn petname(&self, peer_key: &PeerKey) -> Option<String> {
+        self.entries.get(peer_key)?.petname.clone()
+    }
+
+    /// The peer we gave `petname`, or a petname that looks just like it.
+    pub fn find_petname(&self, petname: &str) -> Option<PeerKey> {
+        let wanted = skeleton(petname);
+        self.entries
+            .iter()
+            .find(|entry| {
+                entry
+                    .petname
+                    .as_deref()
+                    .is_some_and(|name| skeleton(name) == wanted)
+            })
+            .map(|entry| *entry.key())
+    }
+
+    /// The peer whose petname `peer_key` claims as its username, or something that looks like it,
+    /// if that's someone else.
+    pub fn collision(&self, peer_key: &PeerKey, username: &str) -> Option<PeerKey> {
+        self.find_petname(username)
+            .filter(|owner| owner != peer_key)
+    }
+
+    /// Name the peer, or clear its name with `None`. Blank names count as `None`.
+    pub fn set_petname(
+        &self,
+        peer_key: PeerKey,
+        petname: Option<String>,
+    ) -> Result<(), std::io::Error> {
+        let petname = petname
+            .map(|name| name.trim().to_owned())
+            .filter(|name| !name.is_empty());
+        self.entries.entry(peer_key).or_default().petname = petname;
+        self.persist()
+    }
+
+    pub fn is_verified(&self, peer_key: &PeerKey) -> bool {
+        self.entries
+            .get(peer_key)
+            .is_some_and(|contact| contact.verified_at.is_some())
+    }
+
+    /// Mark the peer's safety number as confirmed, or withdraw that.
+    pub fn set_verified(&self, peer_key: PeerKey, verified: bool) -> Result<(), std::io::Error> {
+        self.entries.entry(peer_key).or_default().verified_at = verified.then(crate::unix_millis);
+        self.persist()
+    }
+
+    pub fn set_notes(&self, peer_key: PeerKey, notes: String) -> Result<(), std::io::Error> {
+        self.entries.entry(peer_key).or_default().notes = notes;
+        self.persist()
+    }
+
+    /// Remember that `peer_key` can be reached at `locator`.
+    pub fn add_locator(
+        &self,
+        peer_key: PeerKey,
+        locator: NodeLocator,
+    ) -> Result<(), std::io::Error> {
+        {
+            let mut contact = self.entries.entry(peer_key).or_default();
+            if contact.locators.contains(&locator) {
+                return Ok(());
+            }
+            contact.locators.push(locator);
+        }
+        self.persist()
+    }
+
+    /// Record that we're connected to `peer_key` right now.
+    pub fn seen(&self, peer_key: PeerKey) -> Result<(), std::io::Error> {
+        self.entries.entry(peer_key).or_default().last_seen = Some(crate::unix_millis());
+        self.persist()
+    }
+
+    /// Move what we know about `old_key` over to the key that succeeded it. The safety number
+    /// changes with the key, so the new one starts out unverified.
+    pub fn succeed(&self, old_key: &PeerKey, new_key: PeerKey) -> Result<(), std::io::Error> {
+        let Some((_, old)) = self.entries.remove(old_key) else {
+            return Ok(());
+        };
+        {
+            let mut contact = self.entries.entry(new_key).or_default();
+            if contact.petname.is_none() {
+                contact.petname = old.petname;
+            }
+            for locator in old.locators {
+                if !contact.locators.contains(&locator) {
+                    contact.locators.push(locator);
+                }
+            }
+            if contact.notes.is_empty() {
+                contact.notes = old.notes;
+            }
+            contact.last_seen = contact.last_seen.max(old.last_seen);
+        }
+        self.persist()
+    }
+
+    pub fn remove(&self, peer_key: &PeerKey) -> Result<(), std::io::Error> {
+        if self.entries.remove(peer_key).is_some() {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Vec<(PeerKey, Contact)> {
+        self.entries
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    pub(crate) fn records(&self) -> Vec<ContactRecord> {
+        self.entries
+            .iter()
+            .map(|entry| ContactRecord {
+                peer_key: *entry.key(),
+                petname: entry.petname.clone(),
+                locators: entry.locators.clone(),
+                notes: entry.notes.clone(),
+                last_seen: entry.last_seen,
+                verified_at: entry.verified_at,
+            })
+            .collect()
+    }
+
+    /// Add contacts from another copy of the address book, keeping ours where both know a peer.
+    /// Returns how many were added.
+    pub(crate) fn import(&self, records: &[ContactRecord]) -> Result<usize, std::io::Error> {
+        let mut added = 0;
+        for record in records {
+            if let dashmap::mapref::entry::Entry::Vacant(entry) =
+                self.entries.entry(record.peer_key)
+            {
+                entry.insert(record.clone().into_contact().1);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            self.persist()?;
+        }
+        Ok(added)
+    }
+
+    fn persist(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        store::write_records(path, &self.records())
+    }
+}
+
+impl ContactRecord {
+    fn into_contact(self) -> (PeerKey, Contact) {
+        (
+            self.peer_key,
+            Contact {
+                petname: self.petname,
+                locators: self.locators,
+                notes: self.notes,
+                last_seen: self.last_seen,
+                verified_at: self.verified_at,
+            },
+        )
+    }
+}
+
+/// Whether `c` is a Unicode default-ignorable code point: rendered as nothing at all, so it can be
+/// slipped into a name without changing how it looks.
+fn is_default_ignorable(c: char) -> bool {
+    matches!(
+        c,
+        '\u{ad}'
+            | '\u{34f}'
+            | '\u{61c}'
+            | '\u{115f}'..='\u{1160}'
+            | '\u{17b4}'..='\u{17b5}'
+            | '\u{180b}'..='\u{180f}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{206f}'
+            | '\u{3164}'
+            | '\u{fe00}'..='\u{fe0f}'
+            | '\u{feff}'
+            | '\u{ffa0}'
+            | '\u{fff0}'..='\u{fff8}'
+            | '\u{1bca0}'..='\u{1bca3}'
+            | '\u{1d173}'..='\u{1d17a}'
+            | '\u{e0000}'..='\u{e0fff}'
+    )
+}
+
+/// The plain letter or digit `c` is a look-alike or compatibility form of, if any. Covers the
+/// fullwidth and mathematical forms compatibility normalization folds away, and the Cyrillic and
+/// Greek letters that pass for Latin ones.
+fn fold_confusable(c: char) -> Option<char> {
+    let folded = match c {
+        '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0)?,
+        // mathematical alphanumerics: runs of A-Z then a-z in each style, then runs of digits
+        '\u{1d400}'..='\u{1d6a3}' => {
+            let index = (c as u32 - 0x1d400) % 52;
+            let base = if index < 26 { b'A' } else { b'a' - 26 };
+            char::from(base + u8::try_from(index).ok()?)
+        }
+        '\u{1d7ce}'..='\u{1d7ff}' => {
+            char::from(b'0' + u8::try_from((c as u32 - 0x1d7ce) % 10).ok()?)
+        }
+        '\u{410}' | '\u{430}' | '\u{391}' | '\u{3b1}' => 'a',
+        '\u{412}' | '\u{392}' => 'b',
+        '\u{421}' | '\u{441}' | '\u{3f2}' => 'c',
+        '\u{501}' => 'd',
+        '\u{415}' | '\u{435}' | '\u{395}' => 'e',
+        '\u{261}' => 'g',
+        '\u{41d}' | '\u{4bb}' | '\u{397}' => 'h',
+        '\u{406}' | '\u{456}' | '\u{399}' | '\u{3b9}' | '\u{4cf}' | '\u{131}' | 'I' | 'i' | '1'
+        | '|' => 'l',
+        '\u{408}' | '\u{458}' => 'j',
+        '\u{41a}' | '\u{39a}' | '\u{3ba}' => 'k',
+        '\u{41c}' | '\u{39c}' => 'm',
+        '\u{39d}' => 'n',
+        '\u{41e}' | '\u{43e}' | '\u{39f}' | '\u{3bf}' | '0' => 'o',
+        '\u{420}' | '\u{440}' | '\u{3a1}' | '\u{3c1}' => 'p',
+        '\u{51b}' => 'q',
+        '\u{405}' | '\u{455}' => 's',
+        '\u{422}' | '\u{3a4}' => 't',
+        '\u{3c5}' => 'u',
+        '\u{3bd}' => 'v',
+        '\u{51c}' | '\u{51d}' => 'w',
+        '\u{425}' | '\u{445}' | '\u{3a7}' | '\u{3c7}' => 'x',
+        '\u{423}' | '\u{443}' | '\u{3a5}' => 'y',
+        '\u{396}' => 'z',
+        _ => return None,
+    };
+    // folded forms can still be look-alikes themselves, e.g. a fullwidth 'I'
+    Some(fold_confusable(folded).unwrap_or(folded))
+}
+
+/// A rough confusable skeleton of `name`, after Unicode TS #39: names that look the same to a
+/// reader map to the same skeleton, whatever mix of scripts, invisible characters, case and
+/// spacing they're written in.
+fn skeleton(name: &str) -> String {
+    let folded = name
+        .chars()
+        .flat_map(|c| fold_confusable(c).unwrap_or(c).to_lowercase())
+        // combining marks stacked on a letter are easy to miss
+        .filter(|&c| !is_default_ignorable(c) && !('\u{300}'..='\u{36f}').contains(&c))
+        .collect::<String>();
+    folded
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("rn", "m")
+        .replace("vv", "w")
+}
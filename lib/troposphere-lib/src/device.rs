@@ -0,0 +1,352 @@
+use std::path::PathBuf;
+
+use dashmap::DashMap;
+use ed25519_dalek::{Signature, SignatureError, Signer, SigningKey};
+use syrup::{Deserialize, Serialize};
+
+use crate::{store, ChannelId, ConversationKey, PeerKey, SealError, SyrupUuid, Timestamp};
+
+const DEVICE_DOMAIN: &[u8] = b"troposphere/device-certificate/v1";
+const MAILBOX_KEY_DOMAIN: &[u8] = b"troposphere/mailbox-key/v1";
+/// How many device certificates the store holds before it refuses more.
+pub const MAX_LINKED_DEVICES: usize = 4096;
+
+/// A persona's signed statement that another device, with a key of its own, speaks for it. Peers
+/// that have seen the certificate treat the device as the persona: it's admitted and trusted the
+/// way the persona is. The device countersigns, so no one can claim someone else's device.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "device-certificate")]
+pub struct DeviceCertificate {
+    pub primary: PeerKey,
+    pub device: PeerKey,
+    pub issued_at: Timestamp,
+    pub signature: Signature,
+    pub device_signature: Signature,
+}
+
+impl DeviceCertificate {
+    fn payload(primary: &PeerKey, device: &PeerKey, issued_at: Timestamp) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(DEVICE_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(primary).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(device).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&issued_at).unwrap());
+        res
+    }
+
+    pub fn new(primary: &SigningKey, device: &SigningKey) -> Self {
+        let primary_key = primary.verifying_key();
+        let device_key = device.verifying_key();
+        let issued_at = crate::unix_millis();
+        let payload = Self::payload(&primary_key, &device_key, issued_at);
+        Self {
+            primary: primary_key,
+            device: device_key,
+            issued_at,
+            signature: primary.sign(&payload),
+            device_signature: device.sign(&payload),
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), SignatureError> {
+        let payload = Self::payload(&self.primary, &self.device, self.issued_at);
+        self.primary.verify_strict(&payload, &self.signature)?;
+        self.device.verify_strict(&payload, &self.device_signature)
+    }
+}
+
+/// The key one device of a persona shares with a peer for their direct-message mailbox, sealed to
+/// another device of the same persona. Direct messages synced between the devices are sealed to
+/// whichever device took part, so this is what lets the others read them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "mailbox-key")]
+pub struct MailboxKey {
+    #[syrup(as = SyrupUuid)]
+    pub channel: ChannelId,
+    /// The device whose end of the mailbox this is.
+    pub holder: PeerKey,
+    pub counterpart: PeerKey,
+    /// The device the key is sealed to.
+    pub recipient: PeerKey,
+    nonce: syrup::Bytes<Vec<u8>>,
+    ciphertext: syrup::Bytes<Vec<u8>>,
+}
+
+impl MailboxKey {
+    fn aad(channel: ChannelId, holder: &PeerKey, counterpart: &PeerKey) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(MAILBOX_KEY_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid::from(channel)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(holder).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(counterpart).unwrap());
+        res
+    }
+
+    pub(crate) fn issue(
+        holder: &SigningKey,
+        counterpart: PeerKey,
+        channel: ChannelId,
+        recipient: PeerKey,
+    ) -> Result<Self, SealError> {
+        let holder_key = holder.verifying_key();
+        let key = ConversationKey::derive(holder, &counterpart, &channel)?;
+        let (nonce, ciphertext) = ConversationKey::derive(holder, &recipient, &channel)?.seal(
+            &Self::aad(channel, &holder_key, &counterpart),
+            &key.to_bytes(),
+        )?;
+        Ok(Self {
+            channel,
+            holder: holder_key,
+            counterpart,
+            recipient,
+            nonce: syrup::Bytes(nonce),
+            ciphertext: syrup::Bytes(ciphertext),
+        })
+    }
+
+    /// The mailbox's conversation key, if `recipient` is the device this is sealed to.
+    pub(crate) fn open(&self, recipient: &SigningKey) -> Result<ConversationKey, SealError> {
+        if self.recipient != recipient.verifying_key() {
+            return Err(SealError::NotRecipient);
+        }
+        let key = ConversationKey::derive(recipient, &self.holder, &self.channel)?.open(
+            &Self::aad(self.channel, &self.holder, &self.counterpart),
+            &self.nonce.0,
+            &self.ciphertext.0,
+        )?;
+        let key = <[u8; 32]>::try_from(key).map_err(|_err| SealError::Decrypt)?;
+        Ok(ConversationKey::from_bytes(key))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeviceError {
+    #[error("device certificate is not signed by both its primary and the device")]
+    Signature(#[from] SignatureError),
+    #[error("device certificate names its own key as primary")]
+    SelfCertified,
+    /// Only devices of ourselves or of peers we know of are recorded, so strangers can't fill the
+    /// store.
+    #[error("{} isn't a key we know of", rexa::hash(.0))]
+    UnknownPrimary(PeerKey),
+    #[error("linked device store is full")]
+    Full,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Device certificates we've issued, been issued, or were shown by peers, keyed by device, and the
+/// mailbox keys our other devices shared with us.
+pub struct LinkedDevices {
+    path: Option<PathBuf>,
+    entries: DashMap<PeerKey, DeviceCertificate>,
+    mailbox_keys: DashMap<(ChannelId, PeerKey), MailboxKey>,
+}
+
+impl std::fmt::Debug for LinkedDevices {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LinkedDevices")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .field("mailbox_keys", &self.mailbox_keys.len())
+            .finish()
+    }
+}
+
+impl Default for LinkedDevices {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl LinkedDevices {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let entries = DashMap::new();
+        let mailbox_keys = DashMap::new();
+        for frame in store::read_frames(&path)? {
+            if let Ok(record) = syrup::de::from_bytes::<DeviceCertificate>(&frame) {
+                if let Err(error) = record.verify() {
+                    tracing::warn!(?path, %error, "skipping forged device certificate");
+                    continue;
+                }
+                entries.insert(record.device, record);
+            } else if let Ok(key) = syrup::de::from_bytes::<MailboxKey>(&frame) {
+                mailbox_keys.insert((key.channel, key.holder), key);
+            } else {
+                tracing::warn!(?path, "skipping unreadable linked device record");
+            }
+        }
+        Ok(Self {
+            path: Some(path),
+            entries,
+            mailbox_keys,
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: DashMap::new(),
+            mailbox_keys: DashMap::new(),
+        }
+    }
+
+    /// Check and remember `certificate`. Returns whether it's new to us. New devices are refused
+    /// once [`MAX_LINKED_DEVICES`] are known.
+    pub fn record(&self, certificate: DeviceCertificate) -> Result<bool, DeviceError> {
+        certificate.verify()?;
+        // a device speaking for itself would make it its own primary
+        if certificate.primary == certificate.device {
+            return Err(DeviceError::SelfCertified);
+        }
+        if self
+            .entries
+            .get(&certificate.device)
+            .is_some_and(|known| known.primary == certificate.primary)
+        {
+            return Ok(false);
+        }
+        if !self.entries.contains_key(&certificate.device)
+            && self.entries.len() >= MAX_LINKED_DEVICES
+        {
+            return Err(DeviceError::Full);
+        }
+        if let Some(path) = &self.path {
+            store::append_record(path, &certificate)?;
+        }
+        self.entries.insert(certificate.device, certificate);
+        Ok(true)
+    }
+
+    /// The persona `peer_key` speaks for: its primary if it's a certified device, itself
+    /// otherwise.
+    pub fn principal(&self, peer_key: &PeerKey) -> PeerKey {
+        self.entries
+            .get(peer_key)
+            .map_or(*peer_key, |certificate| certificate.primary)
+    }
+
+    /// The certificate that makes `device` speak for another key, if there is one.
+    pub fn certificate(&self, device: &PeerKey) -> Option<DeviceCertificate> {
+        self.entries
+            .get(device)
+            .map(|certificate| certificate.value().clone())
+    }
+
+    /// Whether `a` and `b` are the same persona, on the same or different devices.
+    pub fn same_principal(&self, a: &PeerKey, b: &PeerKey) -> bool {
+        self.principal(a) == self.principal(b)
+    }
+
+    /// Remember a mailbox key another device of ours shared. Returns whether it's new to us.
+    pub(crate) fn record_mailbox_key(&self, key: MailboxKey) -> Result<bool, DeviceError> {
+        let slot = (key.channel, key.holder);
+        if self.mailbox_keys.contains_key(&slot) {
+            return Ok(false);
+        }
+        if let Some(path) = &self.path {
+            store::append_record(path, &key)?;
+        }
+        self.mailbox_keys.insert(slot, key);
+        Ok(true)
+    }
+
+    /// The key `holder`, another device of ours, shared for its end of the mailbox `channel`.
+    pub(crate) fn mailbox_key(&self, channel: &ChannelId, holder: &PeerKey) -> Option<MailboxKey> {
+        self.mailbox_keys
+            .get(&(*channel, *holder))
+            .map(|key| key.value().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    #[test]
+    fn certificates_need_both_signatures() {
+        let primary = SigningKey::generate(&mut OsRng);
+        let device = SigningKey::generate(&mut OsRng);
+        let certificate = DeviceCertificate::new(&primary, &device);
+        certificate.verify().unwrap();
+
+        let mut claimed = certificate.clone();
+        claimed.device = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(claimed.verify().is_err());
+        let mut hijacked = certificate;
+        hijacked.primary = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(hijacked.verify().is_err());
+    }
+
+    #[test]
+    fn devices_speak_for_their_primary() {
+        let devices = LinkedDevices::in_memory();
+        let primary = SigningKey::generate(&mut OsRng);
+        let device = SigningKey::generate(&mut OsRng);
+        let stranger = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(devices
+            .record(DeviceCertificate::new(&primary, &device))
+            .unwrap());
+        assert_eq!(
+            devices.principal(&device.verifying_key()),
+            primary.verifying_key()
+        );
+        assert!(devices.same_principal(&device.verifying_key(), &primary.verifying_key()));
+        assert!(!devices.same_principal(&device.verifying_key(), &stranger));
+    }
+
+    #[test]
+    fn refuses_self_certified_devices() {
+        let key = SigningKey::generate(&mut OsRng);
+        assert!(matches!(
+            LinkedDevices::in_memory().record(DeviceCertificate::new(&key, &key)),
+            Err(DeviceError::SelfCertified)
+        ));
+    }
+
+    #[test]
+    fn refuses_new_devices_once_full() {
+        let devices = LinkedDevices::in_memory();
+        let primary = SigningKey::generate(&mut OsRng);
+        for _ in 0..MAX_LINKED_DEVICES {
+            let device = SigningKey::generate(&mut OsRng);
+            assert!(devices
+                .record(DeviceCertificate::new(&primary, &device))
+                .unwrap());
+        }
+        let device = SigningKey::generate(&mut OsRng);
+        assert!(matches!(
+            devices.record(DeviceCertificate::new(&primary, &device)),
+            Err(DeviceError::Full)
+        ));
+    }
+
+    #[test]
+    fn mailbox_keys_open_only_for_their_recipient() {
+        let holder = SigningKey::generate(&mut OsRng);
+        let counterpart = SigningKey::generate(&mut OsRng);
+        let device = SigningKey::generate(&mut OsRng);
+        let channel = ChannelId::new_v4();
+        let shared = MailboxKey::issue(
+            &holder,
+            counterpart.verifying_key(),
+            channel,
+            device.verifying_key(),
+        )
+        .unwrap();
+
+        let expected = ConversationKey::derive(&counterpart, &holder.verifying_key(), &channel)
+            .unwrap()
+            .to_bytes();
+        assert_eq!(shared.open(&device).unwrap().to_bytes(), expected);
+        assert!(shared.open(&counterpart).is_err());
+
+        let mut redirected = shared;
+        redirected.counterpart = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(redirected.open(&device).is_err());
+    }
+}
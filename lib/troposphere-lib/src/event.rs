@@ -1,15 +1,20 @@
 use std::sync::Arc;
 
 use ed25519_dalek::VerifyingKey;
-use rexa::captp::{object::Object, AbstractCapTpSession, GenericResolver};
+use rexa::{
+    captp::{object::Object, AbstractCapTpSession, GenericResolver},
+    locator::SturdyRefLocator,
+};
 use syrup::RawSyrup;
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 
-use crate::{ChatEvent, PeerKey};
+use crate::{Channel, ChannelEvent, ChatEvent, DeviceCertificate, PeerKey, Profile, Succession};
 
 pub enum NetworkEvent {
     PortalRequest {
         session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        /// The persona whose gateway the peer authenticated to.
+        persona: String,
         peer_vkey: PeerKey,
         resolver: GenericResolver,
     },
@@ -26,12 +31,31 @@ pub enum NetworkEvent {
     TaskFinished {
         result: Result<ChatEvent, Box<dyn std::error::Error + Send + Sync + 'static>>,
     },
+    MailboxOpened {
+        peer_key: PeerKey,
+        channel: Channel,
+        events: mpsc::UnboundedReceiver<ChannelEvent>,
+    },
+    /// A member of `channel` told us to connect to `peer_key`.
+    Introduce {
+        channel: Channel,
+        peer_key: PeerKey,
+        locator: SturdyRefLocator,
+    },
+    /// A peer presented a succession to one of our gateways; its signature has been checked.
+    Succession { succession: Succession },
+    /// A peer presented a device certificate to one of our gateways; both signatures have been
+    /// checked.
+    DeviceCertificate { certificate: DeviceCertificate },
+    /// A peer sent us its profile, because it changed or because we asked; it names the peer's
+    /// own key.
+    ProfileChanged { profile: Profile },
 }
 
 impl std::fmt::Debug for NetworkEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::PortalRequest { session, peer_vkey, resolver } => f.debug_struct("PortalRequest").field("peer_vkey", &rexa::hash(peer_vkey)).finish_non_exhaustive(),
+            Self::PortalRequest { persona, peer_vkey, .. } => f.debug_struct("PortalRequest").field("persona", persona).field("peer_vkey", &rexa::hash(peer_vkey)).finish_non_exhaustive(),
             Self::TaskFinished { .. } => f.debug_struct("TaskFinished").finish_non_exhaustive(),
             Self::Fetch {
                 session_key, swiss, ..
@@ -52,6 +76,38 @@ impl std::fmt::Debug for NetworkEvent {
                 .debug_tuple("NewSession")
                 .field(&rexa::hash(session.remote_vkey()))
                 .finish(),
+            Self::MailboxOpened {
+                peer_key, channel, ..
+            } => f
+                .debug_struct("MailboxOpened")
+                .field("peer_key", &rexa::hash(peer_key))
+                .field("channel", channel.id())
+                .finish_non_exhaustive(),
+            Self::Introduce {
+                channel,
+                peer_key,
+                locator,
+            } => f
+                .debug_struct("Introduce")
+                .field("channel", channel.id())
+                .field("peer_key", &rexa::hash(peer_key))
+                .field("locator", locator)
+                .finish(),
+            Self::Succession { succession } => f
+                .debug_struct("Succession")
+                .field("old_key", &rexa::hash(&succession.old_key))
+                .field("new_key", &rexa::hash(&succession.new_key))
+                .finish(),
+            Self::DeviceCertificate { certificate } => f
+                .debug_struct("DeviceCertificate")
+                .field("primary", &rexa::hash(&certificate.primary))
+                .field("device", &rexa::hash(&certificate.device))
+                .finish(),
+            Self::ProfileChanged { profile } => f
+                .debug_struct("ProfileChanged")
+                .field("vkey", &rexa::hash(&profile.vkey))
+                .field("username", &profile.username)
+                .finish(),
         }
     }
 }
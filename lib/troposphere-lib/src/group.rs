@@ -0,0 +1,666 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use dashmap::DashMap;
+use ed25519_dalek::{ed25519::signature::SignerMut, Signature, SigningKey};
+use syrup::{Deserialize, Serialize};
+
+use crate::{store, ChannelId, ConversationKey, PeerKey, SealError, SyrupUuid};
+
+const GRANT_DOMAIN: &[u8] = b"troposphere/sender-key-grant/v1";
+/// How many epochs of each member's sender key are kept; the oldest go as newer ones arrive.
+pub const MAX_SENDER_EPOCHS: usize = 32;
+
+/// A member's current sender key for an encrypted channel, sealed to one other member.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "sender-key-grant")]
+pub struct SenderKeyGrant {
+    #[syrup(as = SyrupUuid)]
+    pub channel: ChannelId,
+    pub sender: PeerKey,
+    pub recipient: PeerKey,
+    pub epoch: u64,
+    pub nonce: syrup::Bytes<Vec<u8>>,
+    pub ciphertext: syrup::Bytes<Vec<u8>>,
+    pub signature: Signature,
+}
+
+impl SenderKeyGrant {
+    fn aad(channel: ChannelId, sender: &PeerKey, recipient: &PeerKey, epoch: u64) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(GRANT_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid::from(channel)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(sender).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(recipient).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&epoch).unwrap());
+        res
+    }
+
+    fn payload(
+        channel: ChannelId,
+        sender: &PeerKey,
+        recipient: &PeerKey,
+        epoch: u64,
+        nonce: &[u8],
+        ciphertext: &[u8],
+    ) -> Vec<u8> {
+        let mut res = Self::aad(channel, sender, recipient, epoch);
+        res.extend_from_slice(&syrup::ser::to_bytes(&syrup::Bytes(nonce)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&syrup::Bytes(ciphertext)).unwrap());
+        res
+    }
+
+    fn issue(
+        channel: ChannelId,
+        recipient: PeerKey,
+        epoch: u64,
+        secret: &[u8; 32],
+        signing_key: &mut SigningKey,
+    ) -> Result<Self, SealError> {
+        let sender = signing_key.verifying_key();
+        let (nonce, ciphertext) = ConversationKey::derive(signing_key, &recipient, &channel)?
+            .seal(&Self::aad(channel, &sender, &recipient, epoch), secret)?;
+        let signature = signing_key.try_sign(&Self::payload(
+            channel,
+            &sender,
+            &recipient,
+            epoch,
+            &nonce,
+            &ciphertext,
+        ))?;
+        Ok(Self {
+            channel,
+            sender,
+            recipient,
+            epoch,
+            nonce: syrup::Bytes(nonce),
+            ciphertext: syrup::Bytes(ciphertext),
+            signature,
+        })
+    }
+
+    /// Check that the grant was signed by its sender, without opening it; enough for a member
+    /// that only relays it.
+    pub(crate) fn verify(&self) -> Result<(), SealError> {
+        self.sender.verify_strict(
+            &Self::payload(
+                self.channel,
+                &self.sender,
+                &self.recipient,
+                self.epoch,
+                &self.nonce.0,
+                &self.ciphertext.0,
+            ),
+            &self.signature,
+        )?;
+        Ok(())
+    }
+
+    /// Check the grant's signature and decrypt the sender key it carries.
+    fn open(&self, signing_key: &SigningKey) -> Result<[u8; 32], SealError> {
+        if self.recipient != signing_key.verifying_key() {
+            return Err(SealError::NotRecipient);
+        }
+        self.verify()?;
+        let secret = ConversationKey::derive(signing_key, &self.sender, &self.channel)?.open(
+            &Self::aad(self.channel, &self.sender, &self.recipient, self.epoch),
+            &self.nonce.0,
+            &self.ciphertext.0,
+        )?;
+        secret.try_into().map_err(|_err| SealError::Decrypt)
+    }
+}
+
+/// A sender key as kept on disk, sealed with a key derived from our identity.
+#[derive(Serialize, Deserialize, Clone)]
+#[syrup(name = "sealed-sender-key")]
+struct SenderKeyRecord {
+    sender: PeerKey,
+    epoch: u64,
+    nonce: syrup::Bytes<Vec<u8>>,
+    ciphertext: syrup::Bytes<Vec<u8>>,
+}
+
+impl SenderKeyRecord {
+    fn aad(channel: ChannelId, sender: &PeerKey, epoch: u64) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&SyrupUuid::from(channel)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(sender).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&epoch).unwrap());
+        res
+    }
+
+    fn seal(
+        key: &ConversationKey,
+        channel: ChannelId,
+        sender: PeerKey,
+        epoch: u64,
+        secret: &[u8; 32],
+    ) -> Result<Self, SealError> {
+        let (nonce, ciphertext) = key.seal(&Self::aad(channel, &sender, epoch), secret)?;
+        Ok(Self {
+            sender,
+            epoch,
+            nonce: syrup::Bytes(nonce),
+            ciphertext: syrup::Bytes(ciphertext),
+        })
+    }
+
+    fn open(&self, key: &ConversationKey, channel: ChannelId) -> Result<[u8; 32], SealError> {
+        key.open(
+            &Self::aad(channel, &self.sender, self.epoch),
+            &self.nonce.0,
+            &self.ciphertext.0,
+        )?
+        .try_into()
+        .map_err(|_err| SealError::Decrypt)
+    }
+}
+
+fn storage_key(signing_key: &SigningKey, channel: &ChannelId) -> ConversationKey {
+    ConversationKey::for_storage(signing_key, channel.as_bytes())
+}
+
+/// Sender-key state for one encrypted channel. Every member encrypts with a key of its own, which
+/// it hands to each other member individually; whenever membership changes, our key is replaced
+/// before the next message, so peers that join can't read earlier traffic and peers that leave
+/// can't read later traffic.
+pub(crate) struct GroupSession {
+    channel: ChannelId,
+    signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+    /// Keys we've rotated away from, which stored sender keys and late grants may be sealed to.
+    retired: OnceLock<Arc<[SigningKey]>>,
+    path: Option<PathBuf>,
+    /// Records in the key file, live or not; once they far outnumber the live ones it's rewritten.
+    records: parking_lot::Mutex<usize>,
+    /// Stored sender keys that didn't open with our key, likely sealed under a retired one. They're
+    /// stored again as they are until they're recovered.
+    unopened: parking_lot::Mutex<Vec<SenderKeyRecord>>,
+
+    /// Our current epoch, or `None` if the key must be rotated before it's used again.
+    current: parking_lot::Mutex<Option<u64>>,
+    keys: DashMap<(PeerKey, u64), [u8; 32]>,
+    /// The newest epoch of our key each member has been sent.
+    granted: DashMap<PeerKey, u64>,
+}
+
+impl GroupSession {
+    pub(crate) fn new(
+        channel: ChannelId,
+        signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+        path: Option<PathBuf>,
+    ) -> Self {
+        let session = Self {
+            channel,
+            signing_key,
+            retired: OnceLock::new(),
+            path,
+            records: parking_lot::Mutex::new(0),
+            unopened: parking_lot::Mutex::new(Vec::new()),
+            current: parking_lot::Mutex::new(None),
+            keys: DashMap::new(),
+            granted: DashMap::new(),
+        };
+        if let Some(path) = &session.path {
+            let storage_key = storage_key(&session.signing_key.read(), &channel);
+            match store::read_records::<SenderKeyRecord>(path) {
+                Ok(records) => {
+                    *session.records.lock() = records.len();
+                    let mut unopened = session.unopened.lock();
+                    for record in records {
+                        match record.open(&storage_key, channel) {
+                            Ok(secret) => {
+                                session.insert_key(record.sender, record.epoch, secret);
+                            }
+                            Err(_) => unopened.push(record),
+                        }
+                    }
+                }
+                Err(error) => {
+                    tracing::error!(%channel, ?path, %error, "failed to read channel sender keys");
+                }
+            }
+        }
+        session
+    }
+
+    /// Keep `secret` as the key of `sender` for `epoch`, dropping the oldest epochs of `sender`
+    /// past [`MAX_SENDER_EPOCHS`]. Returns whether the key is new and was kept.
+    fn insert_key(&self, sender: PeerKey, epoch: u64, secret: [u8; 32]) -> bool {
+        if self.keys.contains_key(&(sender, epoch)) {
+            return false;
+        }
+        self.keys.insert((sender, epoch), secret);
+        let mut epochs = self
+            .keys
+            .iter()
+            .filter(|entry| entry.key().0 == sender)
+            .map(|entry| entry.key().1)
+            .collect::<Vec<_>>();
+        if epochs.len() <= MAX_SENDER_EPOCHS {
+            return true;
+        }
+        epochs.sort_unstable();
+        let excess = epochs.len() - MAX_SENDER_EPOCHS;
+        for old in &epochs[..excess] {
+            self.keys.remove(&(sender, *old));
+        }
+        !epochs[..excess].contains(&epoch)
+    }
+
+    pub(crate) fn local_key(&self) -> PeerKey {
+        self.signing_key.read().verifying_key()
+    }
+
+    /// Whether `peer_key` is our key, current or retired.
+    pub(crate) fn is_ours(&self, peer_key: &PeerKey) -> bool {
+        *peer_key == self.local_key() || self.retired_key(peer_key).is_some()
+    }
+
+    fn retired_key(&self, peer_key: &PeerKey) -> Option<&SigningKey> {
+        self.retired
+            .get()?
+            .iter()
+            .find(|key| key.verifying_key() == *peer_key)
+    }
+
+    /// Recover the sender keys that were stored under `retired` keys, storing them again under the
+    /// current one, and accept grants still sealed to them.
+    pub(crate) fn attach_retired(&self, retired: Arc<[SigningKey]>) {
+        let storage_keys = retired
+            .iter()
+            .map(|key| storage_key(key, &self.channel))
+            .collect::<Vec<_>>();
+        let mut unopened = self.unopened.lock();
+        let before = unopened.len();
+        unopened.retain(|record| {
+            let Some(secret) = storage_keys
+                .iter()
+                .find_map(|key| record.open(key, self.channel).ok())
+            else {
+                return true;
+            };
+            self.insert_key(record.sender, record.epoch, secret);
+            false
+        });
+        let recovered = unopened.len() != before;
+        if !unopened.is_empty() {
+            tracing::warn!(
+                channel = %self.channel,
+                count = unopened.len(),
+                "some stored sender keys open with none of our keys"
+            );
+        }
+        drop(unopened);
+        if recovered {
+            self.compact(&mut self.records.lock());
+        }
+        drop(self.retired.set(retired));
+    }
+
+    /// Persist the key of `sender` for `epoch`, just added.
+    fn append(&self, sender: PeerKey, epoch: u64, secret: &[u8; 32]) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut records = self.records.lock();
+        *records += 1;
+        if *records > 2 * (self.keys.len() + self.unopened.lock().len()) + 64 {
+            return self.compact(&mut records);
+        }
+        let storage_key = storage_key(&self.signing_key.read(), &self.channel);
+        let record = match SenderKeyRecord::seal(&storage_key, self.channel, sender, epoch, secret)
+        {
+            Ok(record) => record,
+            Err(error) => {
+                tracing::error!(
+                    channel = %self.channel,
+                    %error,
+                    "failed to seal channel sender key"
+                );
+                return;
+            }
+        };
+        if let Err(error) = store::append_record(path, &record) {
+            tracing::error!(
+                channel = %self.channel,
+                %error,
+                "failed to persist channel sender key"
+            );
+        }
+    }
+
+    /// Rewrite the key file with only the keys we still have.
+    fn compact(&self, records: &mut usize) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let storage_key = storage_key(&self.signing_key.read(), &self.channel);
+        let sealed = self
+            .keys
+            .iter()
+            .map(|entry| {
+                let (sender, epoch) = *entry.key();
+                SenderKeyRecord::seal(&storage_key, self.channel, sender, epoch, entry.value())
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let mut sealed = match sealed {
+            Ok(sealed) => sealed,
+            Err(error) => {
+                tracing::error!(
+                    channel = %self.channel,
+                    %error,
+                    "failed to seal channel sender keys"
+                );
+                return;
+            }
+        };
+        sealed.extend(self.unopened.lock().iter().cloned());
+        match store::write_records(path, &sealed) {
+            Ok(()) => *records = sealed.len(),
+            Err(error) => {
+                tracing::error!(
+                    channel = %self.channel,
+                    %error,
+                    "failed to compact channel sender keys"
+                );
+            }
+        }
+    }
+
+    /// Discard our current key; a new one is generated for the next message.
+    pub(crate) fn rotate(&self) {
+        *self.current.lock() = None;
+    }
+
+    pub(crate) fn forget_member(&self, peer_key: &PeerKey) {
+        self.granted.remove(peer_key);
+        self.rotate();
+    }
+
+    /// Our current epoch and the key derived for it, generating a new key if needed.
+    pub(crate) fn current_key(&self) -> (u64, ConversationKey) {
+        let local_key = self.local_key();
+        let mut current = self.current.lock();
+        let epoch = match *current {
+            Some(epoch) => epoch,
+            None => {
+                let epoch = self
+                    .keys
+                    .iter()
+                    .filter(|entry| entry.key().0 == local_key)
+                    .map(|entry| entry.key().1 + 1)
+                    .max()
+                    .unwrap_or(0);
+                let secret = rand::random::<[u8; 32]>();
+                self.insert_key(local_key, epoch, secret);
+                self.append(local_key, epoch, &secret);
+                tracing::debug!(channel = %self.channel, epoch, "rotated sender key");
+                *current = Some(epoch);
+                epoch
+            }
+        };
+        let secret = *self.keys.get(&(local_key, epoch)).unwrap();
+        (
+            epoch,
+            ConversationKey::from_sender_key(&secret, &self.channel),
+        )
+    }
+
+    pub(crate) fn key_for(
+        &self,
+        sender: &PeerKey,
+        epoch: u64,
+    ) -> Result<ConversationKey, SealError> {
+        self.keys
+            .get(&(*sender, epoch))
+            .map(|secret| ConversationKey::from_sender_key(&secret, &self.channel))
+            .ok_or(SealError::UnknownSenderKey)
+    }
+
+    /// Grants of our current key for each of `members` that hasn't been sent it yet.
+    pub(crate) fn grants_due(
+        &self,
+        members: impl IntoIterator<Item = PeerKey>,
+    ) -> Result<Vec<SenderKeyGrant>, SealError> {
+        let (epoch, _) = self.current_key();
+        let mut signing_key = self.signing_key.read().clone();
+        let local_key = signing_key.verifying_key();
+        let secret = *self.keys.get(&(local_key, epoch)).unwrap();
+        let mut grants = Vec::new();
+        for member in members {
+            if member == local_key
+                || self
+                    .granted
+                    .get(&member)
+                    .is_some_and(|granted| *granted == epoch)
+            {
+                continue;
+            }
+            grants.push(SenderKeyGrant::issue(
+                self.channel,
+                member,
+                epoch,
+                &secret,
+                &mut signing_key,
+            )?);
+        }
+        Ok(grants)
+    }
+
+    pub(crate) fn mark_granted(&self, grant: &SenderKeyGrant) {
+        self.granted.insert(grant.recipient, grant.epoch);
+    }
+
+    /// A grant of our key for an earlier `epoch`, for a member that was offline when messages
+    /// sealed with it were sent.
+    pub(crate) fn grant_epoch(
+        &self,
+        member: PeerKey,
+        epoch: u64,
+    ) -> Result<SenderKeyGrant, SealError> {
+        let mut signing_key = self.signing_key.read().clone();
+        let local_key = signing_key.verifying_key();
+        let secret = *self
+            .keys
+            .get(&(local_key, epoch))
+            .ok_or(SealError::UnknownSenderKey)?;
+        SenderKeyGrant::issue(self.channel, member, epoch, &secret, &mut signing_key)
+    }
+
+    /// Undo [`Self::mark_granted`] for a grant that couldn't be delivered.
+    pub(crate) fn revoke_grant(&self, grant: &SenderKeyGrant) {
+        self.granted
+            .remove_if(&grant.recipient, |_, epoch| *epoch == grant.epoch);
+    }
+
+    /// Accept a sender key from another member.
+    pub(crate) fn receive(&self, grant: &SenderKeyGrant) -> Result<(), SealError> {
+        if grant.channel != self.channel {
+            return Err(SealError::NotRecipient);
+        }
+        let signing_key = match self.retired_key(&grant.recipient) {
+            Some(retired) => retired.clone(),
+            None => self.signing_key.read().clone(),
+        };
+        let secret = grant.open(&signing_key)?;
+        if self.insert_key(grant.sender, grant.epoch, secret) {
+            self.append(grant.sender, grant.epoch, &secret);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    fn session(channel: ChannelId) -> (GroupSession, PeerKey) {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let peer_key = signing_key.verifying_key();
+        let session = GroupSession::new(
+            channel,
+            Arc::new(parking_lot::RwLock::new(signing_key)),
+            None,
+        );
+        (session, peer_key)
+    }
+
+    #[test]
+    fn granted_keys_open_messages() {
+        let channel = ChannelId::new_v4();
+        let (alice, alice_key) = session(channel);
+        let (bob, bob_key) = session(channel);
+        let grants = alice.grants_due([bob_key]).unwrap();
+        assert_eq!(grants.len(), 1);
+        bob.receive(&grants[0]).unwrap();
+
+        let (epoch, key) = alice.current_key();
+        let (nonce, ciphertext) = key.seal(b"aad", b"hello").unwrap();
+        let opened = bob
+            .key_for(&alice_key, epoch)
+            .unwrap()
+            .open(b"aad", &nonce, &ciphertext)
+            .unwrap();
+        assert_eq!(opened, b"hello");
+    }
+
+    #[test]
+    fn stored_keys_open_only_with_our_identity() {
+        let channel = ChannelId::new_v4();
+        let path = std::env::temp_dir().join(format!("troposphere-test-{channel}.keys.syrup"));
+        let signing_key = Arc::new(parking_lot::RwLock::new(SigningKey::generate(&mut OsRng)));
+        let local_key = signing_key.read().verifying_key();
+        let (epoch, _) =
+            GroupSession::new(channel, signing_key.clone(), Some(path.clone())).current_key();
+
+        let reopened = GroupSession::new(channel, signing_key, Some(path.clone()));
+        assert!(reopened.key_for(&local_key, epoch).is_ok());
+
+        let stranger = Arc::new(parking_lot::RwLock::new(SigningKey::generate(&mut OsRng)));
+        let stolen = GroupSession::new(channel, stranger, Some(path.clone()));
+        assert!(stolen.key_for(&local_key, epoch).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stored_keys_survive_key_rotation() {
+        let channel = ChannelId::new_v4();
+        let path = std::env::temp_dir().join(format!("troposphere-test-{channel}.keys.syrup"));
+        let old = SigningKey::generate(&mut OsRng);
+        let old_key = old.verifying_key();
+        let (epoch, _) = GroupSession::new(
+            channel,
+            Arc::new(parking_lot::RwLock::new(old.clone())),
+            Some(path.clone()),
+        )
+        .current_key();
+
+        let new = Arc::new(parking_lot::RwLock::new(SigningKey::generate(&mut OsRng)));
+        let rotated = GroupSession::new(channel, new.clone(), Some(path.clone()));
+        assert!(rotated.key_for(&old_key, epoch).is_err());
+        rotated.attach_retired(Arc::new([old]));
+        assert!(rotated.key_for(&old_key, epoch).is_ok());
+        assert!(rotated.is_ours(&old_key));
+
+        // recovered keys are stored again under the current key
+        let reopened = GroupSession::new(channel, new, Some(path.clone()));
+        assert!(reopened.key_for(&old_key, epoch).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_only_the_newest_epochs_of_each_sender() {
+        let channel = ChannelId::new_v4();
+        let (alice, alice_key) = session(channel);
+        let (bob, bob_key) = session(channel);
+        let mut epochs = Vec::new();
+        for _ in 0..MAX_SENDER_EPOCHS + 4 {
+            alice.rotate();
+            let grant = alice.grants_due([bob_key]).unwrap().remove(0);
+            bob.receive(&grant).unwrap();
+            epochs.push(grant.epoch);
+        }
+        assert!(bob.key_for(&alice_key, epochs[0]).is_err());
+        assert!(bob.key_for(&alice_key, epochs[3]).is_err());
+        assert!(bob.key_for(&alice_key, epochs[4]).is_ok());
+        assert!(bob.key_for(&alice_key, *epochs.last().unwrap()).is_ok());
+
+        // a grant for an epoch older than those kept doesn't push out a newer one
+        let mut signing_key = alice.signing_key.read().clone();
+        let stale =
+            SenderKeyGrant::issue(channel, bob_key, epochs[0], &[7; 32], &mut signing_key).unwrap();
+        bob.receive(&stale).unwrap();
+        assert!(bob.key_for(&alice_key, epochs[0]).is_err());
+        assert!(bob.key_for(&alice_key, epochs[4]).is_ok());
+    }
+
+    #[test]
+    fn appended_keys_survive_reopening() {
+        let channel = ChannelId::new_v4();
+        let path = std::env::temp_dir().join(format!("troposphere-test-{channel}.keys.syrup"));
+        let signing_key = Arc::new(parking_lot::RwLock::new(SigningKey::generate(&mut OsRng)));
+        let (bob, bob_key) = session(channel);
+        let alice = GroupSession::new(channel, signing_key.clone(), Some(path.clone()));
+        let grant = bob.grants_due([alice.local_key()]).unwrap().remove(0);
+        alice.receive(&grant).unwrap();
+        alice.receive(&grant).unwrap();
+        let (epoch, _) = alice.current_key();
+
+        let reopened = GroupSession::new(channel, signing_key, Some(path.clone()));
+        assert!(reopened.key_for(&bob_key, grant.epoch).is_ok());
+        assert!(reopened.key_for(&alice.local_key(), epoch).is_ok());
+        assert_eq!(*reopened.records.lock(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rotation_starts_a_new_epoch() {
+        let (alice, _) = session(ChannelId::new_v4());
+        let (first, _) = alice.current_key();
+        alice.rotate();
+        let (second, _) = alice.current_key();
+        assert!(second > first);
+    }
+
+    #[test]
+    fn grants_open_only_for_their_recipient() {
+        let channel = ChannelId::new_v4();
+        let (alice, _) = session(channel);
+        let (_, bob_key) = session(channel);
+        let (eve, _) = session(channel);
+        let grant = alice.grants_due([bob_key]).unwrap().remove(0);
+        assert!(matches!(eve.receive(&grant), Err(SealError::NotRecipient)));
+    }
+
+    #[test]
+    fn grants_are_bound_to_their_channel() {
+        let channel = ChannelId::new_v4();
+        let (alice, _) = session(channel);
+        let (bob, bob_key) = session(ChannelId::new_v4());
+        let grant = alice.grants_due([bob_key]).unwrap().remove(0);
+        assert!(bob.receive(&grant).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_grants() {
+        let channel = ChannelId::new_v4();
+        let (alice, _) = session(channel);
+        let (bob, bob_key) = session(channel);
+        let grant = alice.grants_due([bob_key]).unwrap().remove(0);
+
+        assert!(grant.verify().is_ok());
+
+        let mut reepoched = grant.clone();
+        reepoched.epoch += 1;
+        assert!(reepoched.verify().is_err());
+        assert!(bob.receive(&reepoched).is_err());
+
+        let mut corrupted = grant;
+        corrupted.ciphertext.0[0] ^= 1;
+        assert!(corrupted.verify().is_err());
+        assert!(bob.receive(&corrupted).is_err());
+    }
+}
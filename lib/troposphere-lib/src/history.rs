@@ -0,0 +1,543 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use dashmap::{mapref::one::RefMut, DashMap};
+
+use crate::{store, ChannelId, LegacyMessage, Message, MessageId, SyrupUuid, Timestamp};
+
+/// The largest page a channel host will serve in response to a [`HistoryRequest`].
+pub const MAX_HISTORY_BATCH: usize = 100;
+
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "stored-message")]
+pub struct StoredMessage {
+    pub received_at: Timestamp,
+    pub message: Message,
+}
+
+#[derive(syrup::Deserialize)]
+#[syrup(name = "stored-message")]
+struct LegacyStoredMessage {
+    received_at: Timestamp,
+    message: LegacyMessage,
+}
+
+impl StoredMessage {
+    fn decode(channel: &ChannelId, frame: &[u8]) -> Option<Self> {
+        if let Ok(stored) = syrup::de::from_bytes::<Self>(frame) {
+            return Some(stored);
+        }
+        syrup::de::from_bytes::<LegacyStoredMessage>(frame)
+            .ok()
+            .map(|legacy| Self {
+                received_at: legacy.received_at,
+                message: legacy.message.upgrade(*channel, legacy.received_at),
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryCursor {
+    Message(MessageId),
+    Time(Timestamp),
+}
+
+/// A window into a channel's history. Results are returned oldest-first; if `after` is set the
+/// page starts right after it, otherwise the page ends right before `before` (or at the newest
+/// message).
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryQuery {
+    pub after: Option<HistoryCursor>,
+    pub before: Option<HistoryCursor>,
+    pub limit: usize,
+}
+
+impl HistoryQuery {
+    pub fn latest(limit: usize) -> Self {
+        Self {
+            after: None,
+            before: None,
+            limit,
+        }
+    }
+
+    pub fn before(cursor: HistoryCursor, limit: usize) -> Self {
+        Self {
+            after: None,
+            before: Some(cursor),
+            limit,
+        }
+    }
+
+    pub fn after(cursor: HistoryCursor, limit: usize) -> Self {
+        Self {
+            after: Some(cursor),
+            before: None,
+            limit,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HistoryPage {
+    pub messages: Vec<StoredMessage>,
+    /// Whether there are further messages past the end of this page in the direction of the
+    /// query.
+    pub has_more: bool,
+}
+
+/// Wire form of a forward [`HistoryQuery`], sent by a peer catching up on a channel. If neither
+/// cursor is set, history is served from the beginning.
+#[derive(syrup::Serialize, syrup::Deserialize, Clone, Copy, Debug)]
+#[syrup(name = "history-request")]
+pub struct HistoryRequest {
+    pub after_id: Option<SyrupUuid>,
+    pub after_time: Option<Timestamp>,
+    pub limit: u64,
+}
+
+impl HistoryRequest {
+    pub fn since(cursor: Option<HistoryCursor>) -> Self {
+        let (after_id, after_time) = match cursor {
+            Some(HistoryCursor::Message(id)) => (Some(id.into()), None),
+            Some(HistoryCursor::Time(time)) => (None, Some(time)),
+            None => (None, None),
+        };
+        Self {
+            after_id,
+            after_time,
+            limit: MAX_HISTORY_BATCH as u64,
+        }
+    }
+
+    pub fn query(&self) -> HistoryQuery {
+        let cursor = match (self.after_id, self.after_time) {
+            (Some(id), _) => HistoryCursor::Message(id.into()),
+            (None, Some(time)) => HistoryCursor::Time(time),
+            (None, None) => HistoryCursor::Time(0),
+        };
+        HistoryQuery::after(
+            cursor,
+            usize::try_from(self.limit)
+                .map_or(MAX_HISTORY_BATCH, |limit| limit.min(MAX_HISTORY_BATCH)),
+        )
+    }
+}
+
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "history-batch")]
+pub struct HistoryBatch {
+    pub messages: Vec<Message>,
+    pub has_more: bool,
+}
+
+impl From<HistoryPage> for HistoryBatch {
+    fn from(page: HistoryPage) -> Self {
+        Self {
+            messages: page
+                .messages
+                .into_iter()
+                .map(|stored| stored.message)
+                .collect(),
+            has_more: page.has_more,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("unknown history cursor: {0}")]
+    UnknownCursor(MessageId),
+}
+
+#[derive(Default)]
+struct ChannelLog {
+    messages: Vec<StoredMessage>,
+    index: HashMap<MessageId, usize>,
+}
+
+impl ChannelLog {
+    fn push(&mut self, stored: StoredMessage) {
+        self.index.insert(stored.message.id, self.messages.len());
+        self.messages.push(stored);
+    }
+
+    fn resolve(&self, cursor: HistoryCursor, inclusive: bool) -> Result<usize, HistoryError> {
+        match cursor {
+            HistoryCursor::Message(id) => match self.index.get(&id) {
+                Some(&pos) if inclusive => Ok(pos + 1),
+                Some(&pos) => Ok(pos),
+                None => Err(HistoryError::UnknownCursor(id)),
+            },
+            HistoryCursor::Time(time) => Ok(self.messages.partition_point(|stored| {
+                if inclusive {
+                    stored.received_at <= time
+                } else {
+                    stored.received_at < time
+                }
+            })),
+        }
+    }
+}
+
+/// Durable, append-only message history, stored as one record file per channel.
+pub struct HistoryStore {
+    root: Option<PathBuf>,
+    logs: DashMap<ChannelId, ChannelLog>,
+}
+
+impl std::fmt::Debug for HistoryStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HistoryStore")
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for HistoryStore {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl HistoryStore {
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self, HistoryError> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self {
+            root: Some(root),
+            logs: DashMap::new(),
+        })
+    }
+
+    /// A store that is never written to disk.
+    pub fn in_memory() -> Self {
+        Self {
+            root: None,
+            logs: DashMap::new(),
+        }
+    }
+
+    fn log_path(&self, channel: &ChannelId) -> Option<PathBuf> {
+        self.root
+            .as_ref()
+            .map(|root| root.join(format!("{channel}.syrup")))
+    }
+
+    /// Where an encrypted channel keeps its members' sender keys, next to its history.
+    pub(crate) fn key_path(&self, channel: &ChannelId) -> Option<PathBuf> {
+        self.root
+            .as_ref()
+            .map(|root| root.join(format!("{channel}.keys.syrup")))
+    }
+
+    fn read_log(&self, channel: &ChannelId) -> Result<ChannelLog, HistoryError> {
+        let mut log = ChannelLog::default();
+        if let Some(path) = self.log_path(channel) {
+            for frame in store::read_frames(&path)? {
+                let Some(stored) = StoredMessage::decode(channel, &frame) else {
+                    tracing::warn!(?path, "skipping unreadable history record");
+                    continue;
+                };
+                if !log.index.contains_key(&stored.message.id) {
+                    log.push(stored);
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    fn log(&self, channel: &ChannelId) -> Result<RefMut<'_, ChannelId, ChannelLog>, HistoryError> {
+        if let Some(log) = self.logs.get_mut(channel) {
+            return Ok(log);
+        }
+        let log = self.read_log(channel)?;
+        Ok(self.logs.entry(*channel).or_insert(log))
+    }
+
+    /// (Re)read a channel's history from disk, returning the number of stored messages.
+    #[tracing::instrument(skip(self))]
+    pub fn load(&self, channel: &ChannelId) -> Result<usize, HistoryError> {
+        if self.root.is_none() {
+            return Ok(self.logs.get(channel).map_or(0, |log| log.messages.len()));
+        }
+        let log = self.read_log(channel)?;
+        let len = log.messages.len();
+        tracing::debug!(messages = len, "loaded channel history");
+        self.logs.insert(*channel, log);
+        Ok(len)
+    }
+
+    /// Durably record a message. Returns `false` if a message with the same id was already stored.
+    pub fn append(&self, channel: &ChannelId, message: Message) -> Result<bool, HistoryError> {
+        let mut log = self.log(channel)?;
+        if log.index.contains_key(&message.id) {
+            return Ok(false);
+        }
+        let stored = StoredMessage {
+            received_at: crate::unix_millis(),
+            message,
+        };
+        let file = match self.log_path(channel) {
+            Some(path) => Some(store::write_record(&path, &stored)?),
+            None => None,
+        };
+        log.push(stored);
+        // appends are ordered by the lock, but other channels in its shard shouldn't wait on the disk
+        drop(log);
+        if let Some(file) = file {
+            file.sync_data()?;
+        }
+        Ok(true)
+    }
+
+    pub fn newest(&self, channel: &ChannelId) -> Result<Option<MessageId>, HistoryError> {
+        Ok(self
+            .log(channel)?
+            .messages
+            .last()
+            .map(|stored| stored.message.id))
+    }
+
+    pub fn get(
+        &self,
+        channel: &ChannelId,
+        id: &MessageId,
+    ) -> Result<Option<Message>, HistoryError> {
+        let log = self.log(channel)?;
+        Ok(log
+            .index
+            .get(id)
+            .map(|&pos| log.messages[pos].message.clone()))
+    }
+
+    pub fn contains(&self, channel: &ChannelId, id: &MessageId) -> bool {
+        self.logs
+            .get(channel)
+            .is_some_and(|log| log.index.contains_key(id))
+    }
+
+    /// Every channel we have history for.
+    pub fn channels(&self) -> Result<Vec<ChannelId>, HistoryError> {
+        let mut res = self
+            .logs
+            .iter()
+            .map(|entry| *entry.key())
+            .collect::<Vec<_>>();
+        if let Some(root) = &self.root {
+            for entry in std::fs::read_dir(root)? {
+                let name = entry?.file_name();
+                let Some(id) = name
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(".syrup"))
+                    .and_then(|stem| ChannelId::parse_str(stem).ok())
+                else {
+                    continue;
+                };
+                if !res.contains(&id) {
+                    res.push(id);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    pub fn len(&self, channel: &ChannelId) -> Result<usize, HistoryError> {
+        Ok(self.log(channel)?.messages.len())
+    }
+
+    pub fn is_empty(&self, channel: &ChannelId) -> Result<bool, HistoryError> {
+        Ok(self.len(channel)? == 0)
+    }
+
+    pub fn query(
+        &self,
+        channel: &ChannelId,
+        query: &HistoryQuery,
+    ) -> Result<HistoryPage, HistoryError> {
+        let log = self.log(channel)?;
+        let start = match query.after {
+            Some(cursor) => log.resolve(cursor, true)?,
+            None => 0,
+        };
+        let end = match query.before {
+            Some(cursor) => log.resolve(cursor, false)?,
+            None => log.messages.len(),
+        }
+        .max(start);
+        let (range, has_more) = if query.after.is_some() {
+            let page_end = end.min(start.saturating_add(query.limit));
+            (start..page_end, page_end < end)
+        } else {
+            let page_start = end.saturating_sub(query.limit).max(start);
+            (page_start..end, page_start > start)
+        };
+        Ok(HistoryPage {
+            messages: log.messages[range].to_vec(),
+            has_more,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Signer, SigningKey};
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::{PeerKey, LEGACY_MESSAGE_VERSION};
+
+    #[derive(syrup::Serialize)]
+    #[syrup(name = "message")]
+    struct OldMessage {
+        #[syrup(as = SyrupUuid)]
+        id: MessageId,
+        sender: PeerKey,
+        msg: String,
+        signature: Signature,
+    }
+
+    #[derive(syrup::Serialize)]
+    #[syrup(name = "stored-message")]
+    struct OldStoredMessage {
+        received_at: Timestamp,
+        message: OldMessage,
+    }
+
+    fn temp_root() -> PathBuf {
+        std::env::temp_dir().join(format!("troposphere-test-{}.history", uuid::Uuid::new_v4()))
+    }
+
+    fn messages(store: &HistoryStore, channel: ChannelId, count: usize) -> Vec<MessageId> {
+        let mut signing_key = SigningKey::generate(&mut OsRng);
+        (0..count)
+            .map(|i| {
+                let sender = signing_key.verifying_key();
+                let message =
+                    Message::new(channel, sender, format!("{i}"), &mut signing_key).unwrap();
+                let id = message.id;
+                assert!(store.append(&channel, message).unwrap());
+                id
+            })
+            .collect()
+    }
+
+    fn ids(page: &HistoryPage) -> Vec<MessageId> {
+        page.messages
+            .iter()
+            .map(|stored| stored.message.id)
+            .collect()
+    }
+
+    #[test]
+    fn pages_backwards_from_the_newest() {
+        let store = HistoryStore::in_memory();
+        let channel = ChannelId::new_v4();
+        let sent = messages(&store, channel, 5);
+        let page = store.query(&channel, &HistoryQuery::latest(2)).unwrap();
+        assert_eq!(ids(&page), sent[3..]);
+        assert!(page.has_more);
+        let cursor = HistoryCursor::Message(sent[3]);
+        let page = store
+            .query(&channel, &HistoryQuery::before(cursor, 2))
+            .unwrap();
+        assert_eq!(ids(&page), sent[1..3]);
+        assert!(page.has_more);
+        let cursor = HistoryCursor::Message(sent[1]);
+        let page = store
+            .query(&channel, &HistoryQuery::before(cursor, 2))
+            .unwrap();
+        assert_eq!(ids(&page), sent[..1]);
+        assert!(!page.has_more);
+    }
+
+    #[test]
+    fn pages_forwards_from_a_cursor() {
+        let store = HistoryStore::in_memory();
+        let channel = ChannelId::new_v4();
+        let sent = messages(&store, channel, 5);
+        let page = store
+            .query(&channel, &HistoryQuery::after(HistoryCursor::Time(0), 2))
+            .unwrap();
+        assert_eq!(ids(&page), sent[..2]);
+        assert!(page.has_more);
+        let cursor = HistoryCursor::Message(sent[1]);
+        let page = store
+            .query(&channel, &HistoryQuery::after(cursor, 2))
+            .unwrap();
+        assert_eq!(ids(&page), sent[2..4]);
+        assert!(page.has_more);
+        let cursor = HistoryCursor::Message(sent[3]);
+        let page = store
+            .query(&channel, &HistoryQuery::after(cursor, 2))
+            .unwrap();
+        assert_eq!(ids(&page), sent[4..]);
+        assert!(!page.has_more);
+    }
+
+    #[test]
+    fn refuses_unknown_cursors() {
+        let store = HistoryStore::in_memory();
+        let channel = ChannelId::new_v4();
+        messages(&store, channel, 2);
+        let unknown = MessageId::new_v4();
+        let query = HistoryQuery::after(HistoryCursor::Message(unknown), 10);
+        assert!(matches!(
+            store.query(&channel, &query),
+            Err(HistoryError::UnknownCursor(id)) if id == unknown
+        ));
+    }
+
+    #[test]
+    fn appends_each_message_once() {
+        let root = temp_root();
+        let channel = ChannelId::new_v4();
+        let mut signing_key = SigningKey::generate(&mut OsRng);
+        let sender = signing_key.verifying_key();
+        let message = Message::new(channel, sender, "hi".to_owned(), &mut signing_key).unwrap();
+        let store = HistoryStore::open(&root).unwrap();
+        assert!(store.append(&channel, message.clone()).unwrap());
+        assert!(!store.append(&channel, message.clone()).unwrap());
+        let reopened = HistoryStore::open(&root).unwrap();
+        assert!(!reopened.append(&channel, message).unwrap());
+        assert_eq!(reopened.len(&channel).unwrap(), 1);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn reads_legacy_records_alongside_current_ones() {
+        let root = temp_root();
+        let channel = ChannelId::new_v4();
+        let store = HistoryStore::open(&root).unwrap();
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let (id, sender, msg) = (
+            MessageId::new_v4(),
+            signing_key.verifying_key(),
+            "before channels",
+        );
+        let mut payload = syrup::ser::to_bytes(&SyrupUuid(id)).unwrap();
+        payload.extend_from_slice(&syrup::ser::to_bytes(&sender).unwrap());
+        payload.extend_from_slice(&syrup::ser::to_bytes(msg).unwrap());
+        let old = OldStoredMessage {
+            received_at: 1,
+            message: OldMessage {
+                id,
+                sender,
+                msg: msg.to_owned(),
+                signature: signing_key.sign(&payload),
+            },
+        };
+        store::append_record(&store.log_path(&channel).unwrap(), &old).unwrap();
+        let sent = messages(&store, channel, 1);
+        let reopened = HistoryStore::open(&root).unwrap();
+        let page = reopened.query(&channel, &HistoryQuery::latest(10)).unwrap();
+        assert_eq!(ids(&page), [old.message.id, sent[0]]);
+        let legacy = &page.messages[0].message;
+        assert_eq!(legacy.version, LEGACY_MESSAGE_VERSION);
+        assert_eq!(legacy.channel, channel);
+        assert_eq!(legacy.msg, "before channels");
+        assert!(legacy.verify_strict(&sender).is_ok());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
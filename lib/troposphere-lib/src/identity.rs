@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use ed25519_dalek::SigningKey;
+
+use crate::{Channel, ChannelId, EventSender, Gateway, Mailboxes, PeerKey, Persona, Swiss};
+
+/// The name of the persona a [`crate::ChatManager`] is built with.
+pub const DEFAULT_PERSONA: &str = "default";
+
+/// One of the personas a node presents: a key and profile of its own, the channels it hosts, and
+/// the gateway peers authenticate to it through. A peer only learns about the persona it was
+/// invited to.
+pub struct Identity {
+    name: String,
+    pub persona: Arc<Persona>,
+    pub signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+    /// Keys this persona rotated away from, kept to read what was sealed with them.
+    pub(crate) retired: Arc<[SigningKey]>,
+    swiss: Swiss,
+    pub(crate) gateway: Arc<Gateway>,
+    pub(crate) channels: Arc<DashMap<ChannelId, Channel>>,
+    /// Channels hosted elsewhere that we joined as this persona.
+    pub(crate) joined: Arc<DashMap<ChannelId, Channel>>,
+    pub(crate) mailboxes: Arc<Mailboxes>,
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Identity")
+            .field("name", &self.name)
+            .field("key", &rexa::hash(&self.key()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Identity {
+    pub(crate) fn new(
+        name: String,
+        swiss: Swiss,
+        signing_key: SigningKey,
+        retired: Arc<[SigningKey]>,
+        persona: Persona,
+        mailboxes: Mailboxes,
+        ev_sender: EventSender,
+    ) -> Self {
+        let signing_key = Arc::new(parking_lot::RwLock::new(signing_key));
+        Self {
+            swiss,
+            gateway: Arc::new(Gateway::new(
+                name.clone(),
+                ev_sender,
+                signing_key.clone(),
+            )),
+            mailboxes: Arc::new(mailboxes),
+            channels: Default::default(),
+            joined: Default::default(),
+            persona: Arc::new(persona),
+            signing_key,
+            retired,
+            name,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn key(&self) -> PeerKey {
+        self.signing_key.read().verifying_key()
+    }
+
+    /// The swiss number of this persona's gateway; hand out a locator with it to invite peers.
+    pub fn gateway_swiss(&self) -> &[u8] {
+        &self.swiss
+    }
+
+    pub fn mailboxes(&self) -> &Arc<Mailboxes> {
+        &self.mailboxes
+    }
+
+    pub fn hosts(&self, channel: &ChannelId) -> bool {
+        self.channels.contains_key(channel) || self.mailboxes.contains(channel)
+    }
+}
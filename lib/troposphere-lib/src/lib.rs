@@ -26,11 +26,76 @@ pub use portal::*;
 mod manager;
 pub use manager::*;
 
+mod history;
+pub use history::*;
+
+mod access;
+pub use access::*;
+
+mod identity;
+pub use identity::*;
+
+mod mailbox;
+pub use mailbox::*;
+
+mod seal;
+pub use seal::*;
+
+mod group;
+pub use group::SenderKeyGrant;
+
+mod receipt;
+pub use receipt::*;
+
+mod outbound;
+pub use outbound::*;
+
+mod contacts;
+pub use contacts::*;
+
+mod profiles;
+pub use profiles::*;
+
+mod avatar;
+pub use avatar::*;
+
+mod safety;
+pub use safety::*;
+
+mod succession;
+pub use succession::*;
+
+mod device;
+pub use device::*;
+
+mod bundle;
+pub use bundle::*;
+
+mod reconnect;
+pub use reconnect::{ReconnectError, ReconnectPolicy};
+
+mod store;
+
 pub type EventSender = tokio::sync::mpsc::UnboundedSender<NetworkEvent>;
 pub type EventReceiver = tokio::sync::mpsc::UnboundedReceiver<NetworkEvent>;
 pub type Promise<V> = tokio::sync::oneshot::Receiver<V>;
 pub type Resolver<V> = tokio::sync::oneshot::Sender<V>;
 pub type Swiss = Vec<u8>;
+/// Milliseconds since the unix epoch.
+pub type Timestamp = u64;
+
+#[cfg(not(target_family = "wasm"))]
+pub fn unix_millis() -> Timestamp {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |dur| u64::try_from(dur.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(target_family = "wasm")]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn unix_millis() -> Timestamp {
+    js_sys::Date::now() as Timestamp
+}
 
 #[derive(Debug, Clone, Copy)]
 #[repr(transparent)]
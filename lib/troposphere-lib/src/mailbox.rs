@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use ed25519_dalek::SigningKey;
+use rexa::captp::RemoteKey;
+use tokio::sync::mpsc;
+
+use crate::{
+    Channel, ChannelId, ChannelInfo, EventSender, HistoryStore, LinkedDevices, NetworkEvent,
+    OutboundQueue, PeerKey, SuccessionLog,
+};
+
+const MAILBOX_NAMESPACE: uuid::Uuid =
+    uuid::Uuid::from_u128(0x5f0e_2c1b_8d4a_4e6f_9b3c_7a21_d8e4_f610);
+
+/// The id of the mailbox shared by two peers. Both sides derive the same id, so a conversation
+/// keeps one history regardless of who opened it. Pass the keys each peer's succession chain
+/// started with, so the id survives key rotation.
+pub fn mailbox_id(a: &PeerKey, b: &PeerKey) -> ChannelId {
+    let (low, high) = if a.as_bytes() <= b.as_bytes() {
+        (a, b)
+    } else {
+        (b, a)
+    };
+    let mut name = [0; 64];
+    name[..32].copy_from_slice(low.as_bytes());
+    name[32..].copy_from_slice(high.as_bytes());
+    uuid::Uuid::new_v5(&MAILBOX_NAMESPACE, &name)
+}
+
+/// Personal inboxes for one-to-one conversations, one per peer. Mailboxes are ordinary
+/// [`Channel`]s; they just aren't listed by the portal and only admit the peer they belong to.
+pub struct Mailboxes {
+    local_key: PeerKey,
+    /// Keys we've rotated away from, which older direct messages are sealed with.
+    retired: Arc<[SigningKey]>,
+    successions: Arc<SuccessionLog>,
+    devices: Arc<LinkedDevices>,
+    history: Arc<HistoryStore>,
+    outbound: Arc<OutboundQueue>,
+    ev_sender: EventSender,
+    inboxes: DashMap<PeerKey, Channel>,
+}
+
+impl std::fmt::Debug for Mailboxes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mailboxes")
+            .field("inboxes", &self.inboxes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mailboxes {
+    pub(crate) fn new(
+        local_key: PeerKey,
+        retired: Arc<[SigningKey]>,
+        successions: Arc<SuccessionLog>,
+        devices: Arc<LinkedDevices>,
+        history: Arc<HistoryStore>,
+        outbound: Arc<OutboundQueue>,
+        ev_sender: EventSender,
+    ) -> Self {
+        Self {
+            local_key,
+            retired,
+            successions,
+            devices,
+            history,
+            outbound,
+            ev_sender,
+            inboxes: DashMap::new(),
+        }
+    }
+
+    pub fn get(&self, peer_key: &PeerKey) -> Option<Channel> {
+        self.inboxes
+            .get(peer_key)
+            .map(|entry| entry.value().clone())
+    }
+
+    /// The id of the mailbox shared with `peer_key`, whether or not it's open. It stays the same
+    /// when either of us rotates keys, and it's the same on every device of a persona, so their
+    /// histories can be synced.
+    pub fn id_for(&self, peer_key: &PeerKey) -> ChannelId {
+        let root = |key: &PeerKey| self.successions.root(&self.devices.principal(key));
+        mailbox_id(&root(&self.local_key), &root(peer_key))
+    }
+
+    /// Whether `channel` is one of the open mailboxes.
+    pub fn contains(&self, channel: &ChannelId) -> bool {
+        self.inboxes.iter().any(|entry| entry.id() == channel)
+    }
+
+    pub fn peers(&self) -> Vec<PeerKey> {
+        self.inboxes.iter().map(|entry| *entry.key()).collect()
+    }
+
+    /// Detach whoever was connected over `session_key` from their mailbox.
+    pub(crate) fn disconnect_session(&self, session_key: &RemoteKey) {
+        for entry in &self.inboxes {
+            entry.value().disconnect_peer(session_key);
+        }
+    }
+
+    /// Fetch the mailbox for `peer_key`, creating it if necessary. New mailboxes are announced
+    /// with [`crate::ChatEvent::MailboxOpened`].
+    #[tracing::instrument(skip(self), fields(peer_key = rexa::hash(&peer_key)))]
+    pub(crate) fn open(&self, peer_key: PeerKey) -> Channel {
+        let entry = self.inboxes.entry(peer_key);
+        if let dashmap::mapref::entry::Entry::Occupied(inbox) = &entry {
+            return inbox.get().clone();
+        }
+        let id = self.id_for(&peer_key);
+        if let Err(error) = self.history.load(&id) {
+            tracing::error!(mailbox = %id, %error, "failed to load mailbox history");
+        }
+        let (ev_sender, events) = mpsc::unbounded_channel();
+        let channel = Channel::new_direct(
+            id,
+            ChannelInfo {
+                name: rexa::hash(&peer_key).to_string(),
+                description: "Direct messages".to_owned(),
+                encrypted: true,
+            },
+            self.local_key,
+            peer_key,
+            self.history.clone(),
+            ev_sender,
+        );
+        channel.attach_outbound(self.outbound.clone());
+        channel.attach_retired_keys(self.retired.clone());
+        channel.attach_linked_devices(self.devices.clone());
+        entry.insert(channel.clone());
+        tracing::debug!(mailbox = %id, "opened mailbox");
+        drop(self.ev_sender.send(NetworkEvent::MailboxOpened {
+            peer_key,
+            channel: channel.clone(),
+            events,
+        }));
+        channel
+    }
+}
@@ -1,23 +1,44 @@
 use std::future::Future;
 use std::sync::Arc;
 
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
 use ed25519_dalek::{SigningKey, VerifyingKey};
-use rexa::captp::{AbstractCapTpSession, RemoteKey};
+use rexa::captp::object::ObjectError;
+use rexa::captp::{AbstractCapTpSession, GenericResolver, RemoteKey};
+use rexa::locator::{NodeLocator, SturdyRefLocator};
 use syrup::RawSyrup;
 use tokio::{
-    sync::{watch, Mutex},
+    sync::{mpsc, watch, Mutex},
     task::JoinSet,
 };
 
 use crate::{
-    Channel, ChannelId, EventReceiver, EventSender, Gateway, NetlayerManager, NetworkEvent,
-    PeerKey, Persona, Portal, GATEWAY_SWISS,
+    is_avatar_blob, reconnect::Reconnector, AccessDecision, AccessPolicy, AddressBook, AvatarError,
+    AvatarStore, Channel, ChannelEvent, ChannelId, ChannelListing, ConnectError, DeviceCertificate,
+    DeviceError, EventReceiver, EventSender, HistoryStore, Identity, LinkedDevices, LinkedSync,
+    Mailboxes, NetlayerManager, NetworkEvent, OutboundQueue, PeerKey, Persona, Portal, Profile,
+    ProfileCache, ReconnectError, RemotePortal, RemotePortalError, Succession, SuccessionError,
+    SuccessionLog, TrustLevel, TrustStore, DEFAULT_PERSONA,
 };
 
 mod builder;
 pub use builder::*;
 
+/// A channel's events, for whichever copy of the [`ChatEvent`] carrying them is handled first.
+#[derive(Clone)]
+pub struct ChannelEvents(Arc<parking_lot::Mutex<Option<mpsc::UnboundedReceiver<ChannelEvent>>>>);
+
+impl ChannelEvents {
+    pub(crate) fn new(events: mpsc::UnboundedReceiver<ChannelEvent>) -> Self {
+        Self(Arc::new(parking_lot::Mutex::new(Some(events))))
+    }
+
+    /// The events, unless another copy took them already.
+    pub fn take(&self) -> Option<mpsc::UnboundedReceiver<ChannelEvent>> {
+        self.0.lock().take()
+    }
+}
+
 #[derive(Clone)]
 pub enum ChatEvent {
     SessionStarted {
@@ -27,6 +48,29 @@ pub enum ChatEvent {
         session_key: RemoteKey,
         reason: String,
     },
+    /// The access policy wants the user to decide whether `peer_key` may open a portal; answer
+    /// with [`ChatManager::resolve_access`].
+    AccessRequested { peer_key: PeerKey },
+    /// A peer opened the mailbox it shares with us.
+    MailboxOpened {
+        peer_key: PeerKey,
+        channel: Channel,
+        events: ChannelEvents,
+    },
+    /// We connected to a member of `channel` we were introduced to.
+    PeerIntroduced {
+        channel: ChannelId,
+        peer_key: PeerKey,
+    },
+    /// A peer whose session dropped was redialed. The new session arrives as
+    /// [`ChatEvent::SessionStarted`] like any other.
+    Reconnected { peer_key: PeerKey, attempts: u32 },
+    /// A peer we knew moved to a new key; its contact and trust now belong to `new_key`.
+    KeySucceeded { old_key: PeerKey, new_key: PeerKey },
+    /// We learned a peer's profile, or it changed; it's in [`ChatManager::profiles`] already.
+    ProfileUpdated { profile: Profile },
+    /// The avatar `peer_key`'s profile names finished downloading into [`ChatManager::avatars`].
+    AvatarFetched { peer_key: PeerKey, name: String },
 }
 
 impl std::fmt::Debug for ChatEvent {
@@ -44,10 +88,86 @@ impl std::fmt::Debug for ChatEvent {
                 .field("session_key", &rexa::hash(session_key))
                 .field("reason", reason)
                 .finish(),
+            Self::AccessRequested { peer_key } => f
+                .debug_struct("AccessRequested")
+                .field("peer_key", &rexa::hash(peer_key))
+                .finish(),
+            Self::MailboxOpened {
+                peer_key, channel, ..
+            } => f
+                .debug_struct("MailboxOpened")
+                .field("peer_key", &rexa::hash(peer_key))
+                .field("channel", channel.id())
+                .finish_non_exhaustive(),
+            Self::PeerIntroduced { channel, peer_key } => f
+                .debug_struct("PeerIntroduced")
+                .field("channel", channel)
+                .field("peer_key", &rexa::hash(peer_key))
+                .finish(),
+            Self::Reconnected { peer_key, attempts } => f
+                .debug_struct("Reconnected")
+                .field("peer_key", &rexa::hash(peer_key))
+                .field("attempts", attempts)
+                .finish(),
+            Self::KeySucceeded { old_key, new_key } => f
+                .debug_struct("KeySucceeded")
+                .field("old_key", &rexa::hash(old_key))
+                .field("new_key", &rexa::hash(new_key))
+                .finish(),
+            Self::ProfileUpdated { profile } => f
+                .debug_struct("ProfileUpdated")
+                .field("vkey", &rexa::hash(&profile.vkey))
+                .field("username", &profile.username)
+                .finish(),
+            Self::AvatarFetched { peer_key, name } => f
+                .debug_struct("AvatarFetched")
+                .field("peer_key", &rexa::hash(peer_key))
+                .field("name", name)
+                .finish(),
         }
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum OpenDirectError {
+    #[error("no portal is open to peer {}", rexa::hash(.0))]
+    NoPortal(PeerKey),
+    #[error(transparent)]
+    Connect(#[from] ObjectError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OpenPortalError {
+    #[error(transparent)]
+    Portal(#[from] RemotePortalError),
+    #[error("already connected to {} over another session", rexa::hash(.0))]
+    DuplicateSession(PeerKey),
+    #[error("no persona named {0:?}")]
+    UnknownPersona(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IntroductionError {
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    Portal(#[from] RemotePortalError),
+    #[error(
+        "introduced peer authenticated as {} rather than {}",
+        rexa::hash(.got),
+        rexa::hash(.expected)
+    )]
+    WrongPeer { expected: PeerKey, got: PeerKey },
+    #[error(transparent)]
+    Join(#[from] ObjectError),
+}
+
+struct PendingAccess {
+    session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+    persona: String,
+    resolver: GenericResolver,
+}
+
 #[derive(Default)]
 pub struct ChatData {
     sessions: DashMap<VerifyingKey, Arc<dyn AbstractCapTpSession + Send + Sync + 'static>>,
@@ -81,8 +201,11 @@ impl ChatData {
 }
 
 pub struct ChatManager {
+    /// The default persona's profile and key; see [`Self::identities`] for the others.
     pub persona: Arc<Persona>,
     pub signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+    identity: Arc<Identity>,
+    identities: Arc<DashMap<String, Arc<Identity>>>,
 
     layers: Arc<NetlayerManager>,
     ev_sender: EventSender,
@@ -93,10 +216,26 @@ pub struct ChatManager {
         Mutex<JoinSet<Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>>>,
 
     data: Arc<ChatData>,
+    history: Arc<HistoryStore>,
+    outbound: Arc<OutboundQueue>,
+
+    portals: Arc<DashMap<(String, PeerKey), Arc<Portal>>>,
+    joined: Arc<DashMap<ChannelId, Channel>>,
+    /// The persona we joined each of `joined` as.
+    joined_as: Arc<DashMap<ChannelId, Arc<Identity>>>,
+    /// Portals to peers, by the key of the persona we opened them as and the peer's key. A portal
+    /// is only ever used for its own persona's channels, so peers can't link our personas.
+    remote_portals: Arc<DashMap<(PeerKey, PeerKey), Arc<RemotePortal>>>,
+    reconnect: Arc<Reconnector>,
 
-    gateway: Arc<Gateway>,
-    portals: Arc<DashMap<PeerKey, Arc<Portal>>>,
-    channels: Arc<DashMap<ChannelId, Channel>>,
+    access_policy: Box<dyn AccessPolicy>,
+    trust: Arc<TrustStore>,
+    address_book: Arc<AddressBook>,
+    successions: Arc<SuccessionLog>,
+    devices: Arc<LinkedDevices>,
+    profiles: Arc<ProfileCache>,
+    avatars: Arc<AvatarStore>,
+    pending_access: DashMap<PeerKey, PendingAccess>,
 }
 
 impl std::fmt::Debug for ChatManager {
@@ -135,8 +274,610 @@ impl ChatManager {
         });
     }
 
+    pub fn history(&self) -> &Arc<HistoryStore> {
+        &self.history
+    }
+
     pub fn register_channel(&self, channel: Channel) -> Option<Channel> {
-        self.channels.insert(*channel.id(), channel)
+        self.register_channel_as(&self.identity, channel)
+    }
+
+    /// Host `channel` as `persona`; it's listed only to peers that authenticate to that persona.
+    pub fn register_channel_as(&self, persona: &Identity, channel: Channel) -> Option<Channel> {
+        if let Err(error) = self.history.load(channel.id()) {
+            tracing::error!(channel = %channel.id(), %error, "failed to load channel history");
+        }
+        channel.attach_network(self.ev_sender.clone());
+        channel.attach_outbound(self.outbound.clone());
+        channel.attach_retired_keys(persona.retired.clone());
+        persona.channels.insert(*channel.id(), channel)
+    }
+
+    pub fn identity(&self, persona: &str) -> Option<Arc<Identity>> {
+        self.identities
+            .get(persona)
+            .map(|entry| entry.value().clone())
+    }
+
+    /// Every persona, the default one first.
+    pub fn identities(&self) -> Vec<Arc<Identity>> {
+        let mut res = vec![self.identity.clone()];
+        res.extend(
+            self.identities
+                .iter()
+                .filter(|entry| entry.key() != DEFAULT_PERSONA)
+                .map(|entry| entry.value().clone()),
+        );
+        res
+    }
+
+    /// The persona `key` belongs to, if it's one of ours.
+    pub fn identity_for(&self, key: &PeerKey) -> Option<Arc<Identity>> {
+        identity_for(&self.identities, key)
+    }
+
+    /// The persona we host or joined `channel` as; the default one if we don't know the channel.
+    pub fn identity_of(&self, channel: &ChannelId) -> Arc<Identity> {
+        if let Some(entry) = self.joined_as.get(channel) {
+            return entry.value().clone();
+        }
+        self.identities
+            .iter()
+            .find(|entry| entry.hosts(channel))
+            .map_or_else(|| self.identity.clone(), |entry| entry.value().clone())
+    }
+
+    /// Messages waiting for members of our channels to come back online.
+    pub fn outbound(&self) -> &Arc<OutboundQueue> {
+        &self.outbound
+    }
+
+    /// Where other members of our channels can reach us, as advertised to channel hosts.
+    pub fn sturdy_locator(&self) -> Option<SturdyRefLocator> {
+        self.sturdy_locator_for(&self.identity)
+    }
+
+    /// Where peers can reach `persona`; this is what to hand out to invite someone to it.
+    pub fn sturdy_locator_for(&self, persona: &Identity) -> Option<SturdyRefLocator> {
+        self.layers
+            .locators()
+            .next()
+            .map(|node_locator| SturdyRefLocator {
+                node_locator: node_locator.clone(),
+                swiss_num: persona.gateway_swiss().to_vec(),
+            })
+    }
+
+    /// Join a channel listed by `portal`, as the persona the portal was opened as. Members the host
+    /// introduces us to are connected to automatically.
+    pub fn connect_channel(
+        &self,
+        portal: Arc<RemotePortal>,
+        listing: ChannelListing,
+        ev_sender: mpsc::UnboundedSender<ChannelEvent>,
+    ) -> impl Future<Output = Result<Channel, ObjectError>> + Send + 'static {
+        let identity = self
+            .identity_for(portal.local_key())
+            .unwrap_or_else(|| self.identity.clone());
+        let history = self.history.clone();
+        let signing_key = identity.signing_key.clone();
+        let locator = self.sturdy_locator_for(&identity);
+        let network = self.ev_sender.clone();
+        let outbound = self.outbound.clone();
+        let joined = self.joined.clone();
+        let joined_as = self.joined_as.clone();
+        async move {
+            let channel = portal
+                .connect(
+                    listing.id,
+                    listing.info,
+                    history,
+                    signing_key,
+                    locator.as_ref(),
+                    ev_sender,
+                )
+                .await?;
+            channel.attach_network(network);
+            channel.attach_outbound(outbound);
+            channel.attach_retired_keys(identity.retired.clone());
+            joined.insert(listing.id, channel.clone());
+            identity.joined.insert(listing.id, channel.clone());
+            joined_as.insert(listing.id, identity);
+            Ok(channel)
+        }
+    }
+
+    pub fn trust(&self) -> &Arc<TrustStore> {
+        &self.trust
+    }
+
+    pub fn address_book(&self) -> &Arc<AddressBook> {
+        &self.address_book
+    }
+
+    pub fn successions(&self) -> &Arc<SuccessionLog> {
+        &self.successions
+    }
+
+    /// Record that `succession.old_key` now goes by `succession.new_key`, and carry its contact
+    /// and trust over. Returns whether we hadn't heard of it yet. Successions of keys that are
+    /// neither in the address book nor trusted are refused; there's nothing to carry over.
+    pub fn apply_succession(&self, succession: Succession) -> Result<bool, SuccessionError> {
+        let (old_key, new_key) = (succession.old_key, succession.new_key);
+        let known = self.successions.contains(&old_key)
+            || self.address_book.get(&old_key).is_some()
+            || self.trust.get(&old_key).is_some();
+        if !known {
+            return Err(SuccessionError::UnknownKey(old_key));
+        }
+        if !self.successions.record(succession)? {
+            return Ok(false);
+        }
+        tracing::info!(
+            old_key = rexa::hash(&old_key),
+            new_key = rexa::hash(&new_key),
+            "peer rotated its key"
+        );
+        self.address_book.succeed(&old_key, new_key)?;
+        self.trust.succeed(&old_key, new_key)?;
+        Ok(true)
+    }
+
+    pub fn devices(&self) -> &Arc<LinkedDevices> {
+        &self.devices
+    }
+
+    /// Remember that `certificate.device` speaks for `certificate.primary`, which must be one of
+    /// our personas or a key we already know of. Returns whether we hadn't seen the certificate
+    /// yet.
+    pub fn link_device(&self, certificate: DeviceCertificate) -> Result<bool, DeviceError> {
+        let (primary, device) = (certificate.primary, certificate.device);
+        let known = identity_for(&self.identities, &primary).is_some()
+            || self.successions.contains(&primary)
+            || self.address_book.get(&primary).is_some()
+            || self.trust.get(&primary).is_some();
+        if !known {
+            return Err(DeviceError::UnknownPrimary(primary));
+        }
+        if !self.devices.record(certificate)? {
+            return Ok(false);
+        }
+        tracing::info!(
+            primary = rexa::hash(&primary),
+            device = rexa::hash(&device),
+            "learned of linked device"
+        );
+        Ok(true)
+    }
+
+    /// The profiles peers last told us about.
+    pub fn profiles(&self) -> &Arc<ProfileCache> {
+        &self.profiles
+    }
+
+    /// Change the username and avatar of `identity`, and tell every peer we're connected to as it.
+    pub async fn set_profile(
+        &self,
+        identity: &Identity,
+        username: String,
+        avatar: Option<String>,
+    ) -> Profile {
+        let profile = identity.persona.update(username, avatar).await;
+        let key = identity.key();
+        for portal in self.remote_portals.iter() {
+            if *portal.local_key() != key {
+                continue;
+            }
+            let portal = portal.value().clone();
+            let profile = profile.clone();
+            tokio::spawn(async move {
+                if let Err(error) = portal.profile_changed(&profile).await {
+                    tracing::warn!(
+                        peer_key = rexa::hash(portal.peer_key()),
+                        %error,
+                        "could not announce profile change"
+                    );
+                }
+            });
+        }
+        profile
+    }
+
+    pub fn avatars(&self) -> &Arc<AvatarStore> {
+        &self.avatars
+    }
+
+    /// Make the image in `bytes` the avatar of `identity`, and tell every peer we're connected to
+    /// as it.
+    pub async fn set_avatar(
+        &self,
+        identity: &Identity,
+        bytes: Vec<u8>,
+    ) -> Result<Profile, AvatarError> {
+        let name = self.avatars.insert(bytes)?;
+        let username = identity.persona.profile.read().await.username.clone();
+        Ok(self.set_profile(identity, username, Some(name)).await)
+    }
+
+    /// Download the avatar `profile` names from its peer, unless we have it already. It arrives as
+    /// [`ChatEvent::AvatarFetched`].
+    async fn fetch_avatar(&self, profile: &Profile) {
+        let Some(name) = profile
+            .avatar
+            .clone()
+            .filter(|name| is_avatar_blob(name) && !self.avatars.contains(name))
+        else {
+            return;
+        };
+        let Some(portal) = self
+            .remote_portals
+            .iter()
+            .find(|entry| entry.key().1 == profile.vkey)
+            .map(|entry| entry.value().clone())
+        else {
+            return;
+        };
+        let peer_key = profile.vkey;
+        let avatars = self.avatars.clone();
+        self.spawn_subtask(async move {
+            let fetch = async {
+                let bytes = portal.avatar(&name).await?;
+                avatars.insert_named(&name, bytes)?;
+                Ok::<_, Box<dyn std::error::Error + Send + Sync + 'static>>(
+                    ChatEvent::AvatarFetched { peer_key, name },
+                )
+            };
+            Ok(NetworkEvent::TaskFinished {
+                result: fetch.await,
+            })
+        })
+        .await;
+    }
+
+    /// The persona of ours that `peer_key` is, or is a linked device of.
+    fn own_principal(&self, peer_key: &PeerKey) -> Option<Arc<Identity>> {
+        let principal = self.devices.principal(peer_key);
+        self.identities
+            .iter()
+            .find(|entry| self.devices.principal(&entry.value().key()) == principal)
+            .map(|entry| entry.value().clone())
+    }
+
+    /// The default persona's direct conversations.
+    pub fn mailboxes(&self) -> &Arc<Mailboxes> {
+        &self.identity.mailboxes
+    }
+
+    /// Remember an opened portal so its host can be messaged directly.
+    pub fn register_portal(&self, portal: Arc<RemotePortal>) -> Option<Arc<RemotePortal>> {
+        let locator = self
+            .reconnect
+            .identify(&portal.session_key(), *portal.peer_key());
+        meet(&self.address_book, *portal.peer_key(), locator);
+        self.remote_portals
+            .insert((*portal.local_key(), *portal.peer_key()), portal)
+    }
+
+    /// Dial `locator`. The session arrives as [`ChatEvent::SessionStarted`]; should it drop once
+    /// its portal is open, the peer is redialed according to the reconnect policy.
+    pub fn connect(&self, locator: NodeLocator) -> Result<(), ConnectError> {
+        let connect = self.layers.request_connect(locator.clone())?;
+        let reconnect = self.reconnect.clone();
+        tokio::spawn(async move {
+            match connect.await {
+                Ok(session) => reconnect.dialed(*session.remote_vkey(), locator),
+                Err(error) => tracing::warn!(?locator, %error, "failed to connect to node"),
+            }
+        });
+        Ok(())
+    }
+
+    /// Open a portal over a new session, register it, and rejoin any channels we lost its host
+    /// from. If we already have a session with the peer, only one of them is kept; the other is
+    /// aborted.
+    pub fn open_portal(
+        &self,
+        session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+    ) -> impl Future<Output = Result<Arc<RemotePortal>, OpenPortalError>> + Send + 'static {
+        self.open_portal_as(session, DEFAULT_PERSONA)
+    }
+
+    /// Like [`Self::open_portal`], but authenticating as `persona`. Only the channels joined as
+    /// `persona` are rejoined over it.
+    pub fn open_portal_as(
+        &self,
+        session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        persona: &str,
+    ) -> impl Future<Output = Result<Arc<RemotePortal>, OpenPortalError>> + Send + 'static {
+        let identity = self.identity(persona);
+        let persona = persona.to_owned();
+        let locator = identity
+            .as_ref()
+            .and_then(|identity| self.sturdy_locator_for(identity));
+        let layers = self.layers.clone();
+        let remote_portals = self.remote_portals.clone();
+        let reconnect = self.reconnect.clone();
+        let address_book = self.address_book.clone();
+        let successions = self.successions.clone();
+        let devices = self.devices.clone();
+        let history = self.history.clone();
+        let ev_sender = self.ev_sender.clone();
+        let outbound = self.outbound.clone();
+        async move {
+            let Some(identity) = identity else {
+                return Err(OpenPortalError::UnknownPersona(persona));
+            };
+            let signing_key = identity.signing_key.read().clone();
+            let local_key = signing_key.verifying_key();
+            let session_key = *session.remote_vkey();
+            let portal = Arc::new(
+                RemotePortal::open_presenting(
+                    &session.into_remote_bootstrap(),
+                    &signing_key,
+                    &successions.chain_to(&signing_key.verifying_key()),
+                    devices.certificate(&signing_key.verifying_key()).as_ref(),
+                )
+                .await?,
+            );
+            let peer_key = *portal.peer_key();
+            let dialed = reconnect.identify(&session_key, peer_key);
+            meet(&address_book, peer_key, dialed);
+            // two nodes that dial each other at once end up with two sessions
+            let loser = match remote_portals.entry((local_key, peer_key)) {
+                Entry::Occupied(mut entry) if entry.get().session_key() != session_key => {
+                    let existing = entry.get().session_key();
+                    if prefer_session(&layers, &local_key, &peer_key, &session_key, &existing) {
+                        entry.insert(portal.clone());
+                        Some(existing)
+                    } else {
+                        Some(session_key)
+                    }
+                }
+                Entry::Occupied(mut entry) => {
+                    entry.insert(portal.clone());
+                    None
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(portal.clone());
+                    None
+                }
+            };
+            if let Some(loser) = loser {
+                tracing::info!(
+                    peer_key = rexa::hash(&peer_key),
+                    session = rexa::hash(&loser),
+                    "dropping duplicate session"
+                );
+                layers.abort_session(&loser, "duplicate session");
+                if loser == session_key {
+                    return Err(OpenPortalError::DuplicateSession(peer_key));
+                }
+            }
+            tokio::spawn(fetch_profile(portal.clone(), ev_sender));
+            if devices.same_principal(&peer_key, &local_key) {
+                tokio::spawn(sync_linked_history(portal.clone(), history, devices));
+            }
+            let resumed = resume_channels(
+                &identity.joined,
+                &identity.mailboxes,
+                &outbound,
+                portal.clone(),
+                locator,
+            )
+            .await;
+            if resumed > 0 {
+                tracing::info!(
+                    peer_key = rexa::hash(portal.peer_key()),
+                    resumed,
+                    "rejoined channels"
+                );
+            }
+            Ok(portal)
+        }
+    }
+
+    /// Redial `peer_key` with backoff until it answers, it reconnects to us on its own, or the
+    /// reconnect policy gives up.
+    fn redial(&self, peer_key: PeerKey, locator: NodeLocator) {
+        tokio::spawn(run_redial(
+            peer_key,
+            locator,
+            self.layers.clone(),
+            self.reconnect.clone(),
+            self.remote_portals.clone(),
+            self.ev_sender.clone(),
+            self.end_notifier.subscribe(),
+        ));
+    }
+
+    /// Open (or reconnect) the direct-message mailbox shared with `peer_key`. Requires a portal to
+    /// the peer registered through [`Self::register_portal`]; the mailbox belongs to the persona
+    /// the portal was opened as, the default one if there are several.
+    pub fn open_direct(
+        &self,
+        peer_key: PeerKey,
+    ) -> impl Future<Output = Result<Channel, OpenDirectError>> + Send + 'static {
+        let portal = match self.remote_portals.get(&(self.identity.key(), peer_key)) {
+            Some(entry) => Some(entry.value().clone()),
+            None => self
+                .remote_portals
+                .iter()
+                .find(|entry| entry.key().1 == peer_key)
+                .map(|entry| entry.value().clone()),
+        };
+        let identities = self.identities.clone();
+        async move {
+            let Some(portal) = portal else {
+                return Err(OpenDirectError::NoPortal(peer_key));
+            };
+            let Some(identity) = identity_for(&identities, portal.local_key()) else {
+                return Err(OpenDirectError::NoPortal(peer_key));
+            };
+            let mailbox = identity.mailboxes.open(peer_key);
+            portal.open_mailbox(&mailbox).await?;
+            Ok(mailbox)
+        }
+    }
+
+    /// Rejoin the channels we lost the host of `portal` from when its session ended, along with our
+    /// mailbox with it, which flushes anything queued for it in the meantime. Returns how many were
+    /// rejoined.
+    pub fn resume_channels(
+        &self,
+        portal: Arc<RemotePortal>,
+    ) -> impl Future<Output = usize> + Send + 'static {
+        let identity = self
+            .identity_for(portal.local_key())
+            .unwrap_or_else(|| self.identity.clone());
+        resume_channels(
+            &identity.joined,
+            &identity.mailboxes,
+            &self.outbound,
+            portal,
+            self.sturdy_locator_for(&identity),
+        )
+    }
+
+    /// Connect to a member of `channel` that another member introduced us to.
+    fn accept_introduction(
+        &self,
+        channel: Channel,
+        peer_key: PeerKey,
+        locator: SturdyRefLocator,
+    ) -> impl Future<Output = Result<(), IntroductionError>> + Send + 'static {
+        let identity = self.identity_of(channel.id());
+        let local_key = identity.key();
+        let layers = self.layers.clone();
+        let remote_portals = self.remote_portals.clone();
+        let reconnect = self.reconnect.clone();
+        let address_book = self.address_book.clone();
+        let signing_key = identity.signing_key.clone();
+        let own_locator = self.sturdy_locator_for(&identity);
+        async move {
+            // a portal opened as another persona would tell the peer both are us
+            let existing = remote_portals
+                .get(&(local_key, peer_key))
+                .map(|entry| entry.value().clone());
+            let portal = match existing {
+                Some(portal) => portal,
+                None => {
+                    let session = layers
+                        .request_connect(locator.node_locator.clone())?
+                        .await?;
+                    let skey = signing_key.read().clone();
+                    let portal = RemotePortal::open_at(
+                        &session.into_remote_bootstrap(),
+                        &locator.swiss_num,
+                        &skey,
+                    )
+                    .await?;
+                    if portal.peer_key() != &peer_key {
+                        return Err(IntroductionError::WrongPeer {
+                            expected: peer_key,
+                            got: *portal.peer_key(),
+                        });
+                    }
+                    let portal = Arc::new(portal);
+                    remote_portals.insert((local_key, peer_key), portal.clone());
+                    reconnect.remember(peer_key, locator.node_locator.clone());
+                    meet(&address_book, peer_key, Some(locator.node_locator.clone()));
+                    portal
+                }
+            };
+            portal.join_channel(&channel, own_locator.as_ref()).await?;
+            Ok(())
+        }
+    }
+
+    #[tracing::instrument(skip(self, session, resolver), fields(peer_key = rexa::hash(&peer_key)))]
+    async fn grant_portal(
+        &self,
+        session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        persona: String,
+        peer_key: PeerKey,
+        resolver: GenericResolver,
+    ) {
+        let Some(identity) = self.identity(&persona) else {
+            tracing::warn!(persona, "portal requested for unknown persona");
+            return Self::deny_portal(resolver).await;
+        };
+        // our other devices get to copy the persona's history
+        let linked = self
+            .devices
+            .same_principal(&peer_key, &identity.key())
+            .then(|| LinkedSync {
+                history: self.history.clone(),
+                mailboxes: self
+                    .address_book
+                    .entries()
+                    .iter()
+                    .map(|(contact, _)| identity.mailboxes.id_for(contact))
+                    .collect(),
+                signing_key: identity.signing_key.clone(),
+            });
+        let pos = session.exports().export(
+            self.portals
+                .entry((persona, peer_key))
+                .or_insert_with(|| {
+                    Arc::new(Portal::new(
+                        peer_key,
+                        identity.channels.clone(),
+                        identity.joined.clone(),
+                        identity.mailboxes.clone(),
+                        linked,
+                        identity.persona.clone(),
+                        self.ev_sender.clone(),
+                    ))
+                })
+                .clone(),
+        );
+        if let Err(error) = resolver.fulfill([&pos], None, Default::default()).await {
+            tracing::error!(%error, "could not fulfill portal request");
+        };
+    }
+
+    #[tracing::instrument(skip(resolver))]
+    async fn deny_portal(resolver: GenericResolver) {
+        if let Err(error) = resolver.break_promise("access denied").await {
+            tracing::error!(%error, "could not refuse portal request");
+        }
+    }
+
+    /// Answer an [`ChatEvent::AccessRequested`]. If `remember` is set, the decision is stored in
+    /// the trust database so the peer isn't asked about again.
+    #[tracing::instrument(skip(self), fields(peer_key = rexa::hash(peer_key)))]
+    pub async fn resolve_access(&self, peer_key: &PeerKey, allow: bool, remember: bool) {
+        if remember {
+            let level = if allow {
+                TrustLevel::Trusted
+            } else {
+                TrustLevel::Blocked
+            };
+            if let Err(error) = self.trust.set(*peer_key, level) {
+                tracing::error!(%error, "failed to persist trust decision");
+            }
+        }
+        let Some((_, pending)) = self.pending_access.remove(peer_key) else {
+            tracing::warn!("no pending access request for peer");
+            return;
+        };
+        if allow {
+            self.grant_portal(
+                pending.session,
+                pending.persona,
+                *peer_key,
+                pending.resolver,
+            )
+            .await;
+        } else {
+            Self::deny_portal(pending.resolver).await;
+        }
+    }
+
+    /// Whether `peer_key` is still waiting on [`Self::resolve_access`]. Requests are dropped when
+    /// the session they came over ends.
+    pub fn is_access_pending(&self, peer_key: &PeerKey) -> bool {
+        self.pending_access.contains_key(peer_key)
     }
 
     pub async fn recv_event(
@@ -154,8 +895,13 @@ impl ChatManager {
                     swiss,
                     resolver,
                 } => {
-                    if swiss == GATEWAY_SWISS {
-                        drop(resolver.send(Ok(self.gateway.clone())));
+                    let gateway = self
+                        .identities
+                        .iter()
+                        .find(|entry| entry.gateway_swiss() == swiss)
+                        .map(|entry| entry.gateway.clone());
+                    if let Some(gateway) = gateway {
+                        drop(resolver.send(Ok(gateway)));
                     } else if let Some(channel) = self.data.fetch_channel(&swiss) {
                         drop(resolver.send(Ok(channel.clone())));
                     } else {
@@ -172,31 +918,162 @@ impl ChatManager {
                 } => {
                     tracing::info!(session_key = rexa::hash(&session_key), %reason, "session aborted");
                     self.data.remove_session(&session_key);
+                    for identity in self.identities.iter() {
+                        identity.gateway.forget_session(&session_key);
+                        for channel in identity.channels.iter() {
+                            channel.disconnect_peer(&session_key);
+                        }
+                        identity.mailboxes.disconnect_session(&session_key);
+                    }
+                    for channel in self.joined.iter() {
+                        channel.disconnect_peer(&session_key);
+                    }
+                    self.pending_access
+                        .retain(|_, pending| pending.session.remote_vkey() != &session_key);
+                    let lost = self
+                        .remote_portals
+                        .iter()
+                        .filter(|entry| entry.session_key() == session_key)
+                        .map(|entry| entry.key().1)
+                        .collect::<Vec<_>>();
+                    self.remote_portals
+                        .retain(|_, portal| portal.session_key() != session_key);
+                    self.reconnect.forget_session(&session_key);
+                    for peer_key in lost {
+                        if let Err(error) = self.address_book.seen(peer_key) {
+                            tracing::error!(%error, "failed to update address book");
+                        }
+                        if let Some(locator) = self.reconnect.begin(peer_key) {
+                            self.redial(peer_key, locator);
+                        }
+                    }
                     break Ok(ChatEvent::SessionAborted {
                         session_key,
                         reason,
                     });
                 }
+                NetworkEvent::MailboxOpened {
+                    peer_key,
+                    channel,
+                    events,
+                } => {
+                    break Ok(ChatEvent::MailboxOpened {
+                        peer_key,
+                        channel,
+                        events: ChannelEvents::new(events),
+                    });
+                }
+                NetworkEvent::Introduce {
+                    channel,
+                    peer_key,
+                    locator,
+                } => {
+                    if self.identity_for(&peer_key).is_some() || channel.has_member(&peer_key) {
+                        continue;
+                    }
+                    tracing::debug!(
+                        channel = %channel.id(),
+                        peer_key = rexa::hash(&peer_key),
+                        "accepting introduction"
+                    );
+                    let channel_id = *channel.id();
+                    let introduction = self.accept_introduction(channel, peer_key, locator);
+                    self.spawn_subtask(async move {
+                        Ok(NetworkEvent::TaskFinished {
+                            result: introduction
+                                .await
+                                .map(|()| ChatEvent::PeerIntroduced {
+                                    channel: channel_id,
+                                    peer_key,
+                                })
+                                .map_err(From::from),
+                        })
+                    })
+                    .await;
+                }
+                NetworkEvent::Succession { succession } => {
+                    let (old_key, new_key) = (succession.old_key, succession.new_key);
+                    match self.apply_succession(succession) {
+                        Ok(true) => break Ok(ChatEvent::KeySucceeded { old_key, new_key }),
+                        Ok(false) => {}
+                        Err(error) => tracing::warn!(
+                            old_key = rexa::hash(&old_key),
+                            %error,
+                            "ignoring succession"
+                        ),
+                    }
+                }
+                NetworkEvent::DeviceCertificate { certificate } => {
+                    let device = certificate.device;
+                    if let Err(error) = self.link_device(certificate) {
+                        tracing::warn!(
+                            device = rexa::hash(&device),
+                            %error,
+                            "ignoring device certificate"
+                        );
+                    }
+                }
+                NetworkEvent::ProfileChanged { profile } => {
+                    match self.profiles.insert(profile.clone()) {
+                        Ok(changed) => {
+                            self.fetch_avatar(&profile).await;
+                            if changed {
+                                break Ok(ChatEvent::ProfileUpdated { profile });
+                            }
+                        }
+                        Err(error) => tracing::warn!(
+                            peer_key = rexa::hash(&profile.vkey),
+                            %error,
+                            "ignoring profile"
+                        ),
+                    }
+                }
                 NetworkEvent::PortalRequest {
                     session,
+                    persona,
                     peer_vkey,
                     resolver,
                 } => {
                     tracing::debug!(
+                        persona,
                         peer_vkey = rexa::hash(&peer_vkey),
                         "received portal request"
                     );
-                    let pos = session.exports().export(
-                        self.portals
-                            .entry(peer_vkey)
-                            .or_insert_with(|| {
-                                Arc::new(Portal::new(peer_vkey, self.channels.clone()))
-                            })
-                            .clone(),
-                    );
-                    if let Err(error) = resolver.fulfill([&pos], None, Default::default()).await {
-                        tracing::error!(%error, "could not fulfill portal request");
+                    // a linked device is admitted the way its persona is, and our own always are
+                    let decision = if self.own_principal(&peer_vkey).is_some() {
+                        AccessDecision::Allow
+                    } else {
+                        let principal = self.devices.principal(&peer_vkey);
+                        self.access_policy.check(&principal, &self.trust)
                     };
+                    match decision {
+                        AccessDecision::Allow => {
+                            self.grant_portal(session, persona, peer_vkey, resolver)
+                                .await;
+                        }
+                        AccessDecision::Deny => {
+                            tracing::info!(
+                                peer_vkey = rexa::hash(&peer_vkey),
+                                "refused portal request"
+                            );
+                            Self::deny_portal(resolver).await;
+                        }
+                        AccessDecision::Ask => {
+                            if let Some(stale) = self.pending_access.insert(
+                                peer_vkey,
+                                PendingAccess {
+                                    session,
+                                    persona,
+                                    resolver,
+                                },
+                            ) {
+                                Self::deny_portal(stale.resolver).await;
+                            }
+                            break Ok(ChatEvent::AccessRequested {
+                                peer_key: peer_vkey,
+                            });
+                        }
+                    }
                 }
             }
         }
@@ -211,3 +1088,188 @@ impl ChatManager {
         Ok(())
     }
 }
+
+fn identity_for(
+    identities: &DashMap<String, Arc<Identity>>,
+    key: &PeerKey,
+) -> Option<Arc<Identity>> {
+    identities
+        .iter()
+        .find(|entry| entry.value().key() == *key)
+        .map(|entry| entry.value().clone())
+}
+
+/// Ask the host of `portal` for its profile, and hand it to the manager like a pushed change.
+async fn fetch_profile(portal: Arc<RemotePortal>, ev_sender: EventSender) {
+    match portal.profile().await {
+        Ok(profile) if profile.vkey == *portal.peer_key() => {
+            drop(ev_sender.send(NetworkEvent::ProfileChanged { profile }));
+        }
+        Ok(_) => tracing::warn!(
+            peer_key = rexa::hash(portal.peer_key()),
+            "peer presented the profile of another key"
+        ),
+        Err(error) => tracing::warn!(
+            peer_key = rexa::hash(portal.peer_key()),
+            %error,
+            "could not fetch profile"
+        ),
+    }
+}
+
+/// Copy the history of another device of ours, over a portal it hosts.
+async fn sync_linked_history(
+    portal: Arc<RemotePortal>,
+    history: Arc<HistoryStore>,
+    devices: Arc<LinkedDevices>,
+) {
+    match portal.sync_all_history(&history, &devices).await {
+        Ok(received) => tracing::info!(
+            peer_key = rexa::hash(portal.peer_key()),
+            received,
+            "synced history from linked device"
+        ),
+        Err(error) => tracing::warn!(
+            peer_key = rexa::hash(portal.peer_key()),
+            %error,
+            "failed to sync history from linked device"
+        ),
+    }
+}
+
+/// Note in the address book that we're connected to `peer_key`, and where it was reached if we
+/// dialed it.
+fn meet(address_book: &AddressBook, peer_key: PeerKey, locator: Option<NodeLocator>) {
+    let res = match locator {
+        Some(locator) => address_book
+            .add_locator(peer_key, locator)
+            .and_then(|()| address_book.seen(peer_key)),
+        None => address_book.seen(peer_key),
+    };
+    if let Err(error) = res {
+        tracing::error!(%error, "failed to update address book");
+    }
+}
+
+/// Of two sessions with `peer_key`, whether to keep `candidate` over `existing`. The session dialed
+/// by whichever node has the lower key wins, so both ends settle on the same one without talking it
+/// over; a session that has already ended always loses.
+fn prefer_session(
+    layers: &NetlayerManager,
+    local_key: &PeerKey,
+    peer_key: &PeerKey,
+    candidate: &RemoteKey,
+    existing: &RemoteKey,
+) -> bool {
+    let initiator = |session_key| {
+        layers
+            .is_outbound(session_key)
+            .map(|outbound| if outbound { local_key } else { peer_key })
+    };
+    match (initiator(candidate), initiator(existing)) {
+        (Some(candidate), Some(existing)) if candidate != existing => {
+            candidate.as_bytes() < existing.as_bytes()
+        }
+        (None, Some(_)) => false,
+        // the same side dialed both, e.g. a reconnect racing the old session's teardown
+        _ => true,
+    }
+}
+
+fn resume_channels(
+    joined: &DashMap<ChannelId, Channel>,
+    mailboxes: &Mailboxes,
+    outbound: &OutboundQueue,
+    portal: Arc<RemotePortal>,
+    locator: Option<SturdyRefLocator>,
+) -> impl Future<Output = usize> + Send + 'static {
+    let peer_key = *portal.peer_key();
+    let channels = joined
+        .iter()
+        .filter(|entry| entry.absent_members().contains(&peer_key))
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<_>>();
+    let mailbox_id = mailboxes.id_for(&peer_key);
+    let mailbox = match mailboxes.get(&peer_key) {
+        Some(mailbox) if !mailbox.has_member(&peer_key) => Some(mailbox),
+        Some(_) => None,
+        // messages queued before a restart
+        None if !outbound.waiting(&mailbox_id, &peer_key).is_empty() => {
+            Some(mailboxes.open(peer_key))
+        }
+        None => None,
+    };
+    async move {
+        let mut resumed = 0;
+        for channel in &channels {
+            match portal.join_channel(channel, locator.as_ref()).await {
+                Ok(()) => resumed += 1,
+                Err(error) => tracing::warn!(
+                    channel = %channel.id(),
+                    peer_key = rexa::hash(&peer_key),
+                    %error,
+                    "failed to rejoin channel"
+                ),
+            }
+        }
+        if let Some(mailbox) = mailbox {
+            match portal.open_mailbox(&mailbox).await {
+                Ok(()) => resumed += 1,
+                Err(error) => tracing::warn!(
+                    peer_key = rexa::hash(&peer_key),
+                    %error,
+                    "failed to reopen mailbox"
+                ),
+            }
+        }
+        resumed
+    }
+}
+
+#[tracing::instrument(skip_all, fields(peer_key = rexa::hash(&peer_key)))]
+async fn run_redial(
+    peer_key: PeerKey,
+    locator: NodeLocator,
+    layers: Arc<NetlayerManager>,
+    reconnect: Arc<Reconnector>,
+    remote_portals: Arc<DashMap<(PeerKey, PeerKey), Arc<RemotePortal>>>,
+    ev_sender: EventSender,
+    mut end_flag: watch::Receiver<bool>,
+) {
+    tracing::info!("redialing peer");
+    let mut attempts = 0;
+    let res = loop {
+        attempts += 1;
+        tokio::select! {
+            () = tokio::time::sleep(reconnect.policy.delay(attempts)) => {}
+            _ = end_flag.changed() => break None,
+        }
+        if remote_portals.iter().any(|entry| entry.key().1 == peer_key) {
+            tracing::debug!("peer reconnected on its own");
+            break None;
+        }
+        let res = match layers.request_connect(locator.clone()) {
+            Ok(connect) => connect.await,
+            Err(error) => Err(error),
+        };
+        match res {
+            Ok(session) => {
+                reconnect.dialed(*session.remote_vkey(), locator);
+                break Some(Ok(ChatEvent::Reconnected { peer_key, attempts }));
+            }
+            Err(last) if reconnect.policy.gives_up_after(attempts) => {
+                break Some(Err(ReconnectError::GaveUp {
+                    peer_key,
+                    attempts,
+                    last,
+                }
+                .into()));
+            }
+            Err(error) => tracing::debug!(attempts, %error, "reconnect attempt failed"),
+        }
+    };
+    reconnect.finish(&peer_key);
+    if let Some(result) = res {
+        drop(ev_sender.send(NetworkEvent::TaskFinished { result }));
+    }
+}
@@ -1,15 +1,30 @@
 use std::{future::Future, sync::Arc};
 
+use dashmap::DashMap;
 use ed25519_dalek::SigningKey;
 use rexa::netlayer::Netlayer;
 use tokio::{sync::watch, task::JoinSet};
 
 use crate::{
-    ChatData, ChatManager, EventReceiver, EventSender, Gateway, NetlayerManager, Persona, Profile,
+    reconnect::Reconnector, AccessPolicy, AddressBook, AllowAll, AvatarStore, ChatData,
+    ChatManager, EventReceiver, EventSender, HistoryStore, Identity, LinkedDevices, Mailboxes,
+    NetlayerManager, OutboundQueue, Persona, Profile, ProfileCache, ReconnectPolicy, SuccessionLog,
+    Swiss, TrustStore, DEFAULT_PERSONA, GATEWAY_SWISS,
 };
 
+/// A persona to present besides the default one.
+struct PersonaSpec {
+    name: String,
+    swiss: Swiss,
+    signing_key: SigningKey,
+    retired: Vec<SigningKey>,
+    username: String,
+    avatar: Option<String>,
+}
+
 pub struct ChatManagerBuilder {
     signing_key: SigningKey,
+    retired: Vec<SigningKey>,
     layers: NetlayerManager,
     ev_sender: EventSender,
     ev_receiver: EventReceiver,
@@ -18,6 +33,17 @@ pub struct ChatManagerBuilder {
     subscription_tasks: JoinSet<Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>>,
     username: Option<String>,
     avatar: Option<String>,
+    history: Option<HistoryStore>,
+    access_policy: Box<dyn AccessPolicy>,
+    trust: Option<TrustStore>,
+    address_book: Option<AddressBook>,
+    successions: Option<SuccessionLog>,
+    devices: Option<LinkedDevices>,
+    profiles: Option<ProfileCache>,
+    avatars: Option<AvatarStore>,
+    outbound: Option<OutboundQueue>,
+    reconnect: ReconnectPolicy,
+    personas: Vec<PersonaSpec>,
 }
 
 impl ChatManagerBuilder {
@@ -28,6 +54,7 @@ impl ChatManagerBuilder {
 
         Self {
             signing_key,
+            retired: Vec::new(),
             layers: NetlayerManager::new(),
             ev_sender,
             ev_receiver,
@@ -36,9 +63,26 @@ impl ChatManagerBuilder {
             subscription_tasks: JoinSet::new(),
             username: None,
             avatar: None,
+            history: None,
+            access_policy: Box::new(AllowAll),
+            trust: None,
+            address_book: None,
+            successions: None,
+            devices: None,
+            profiles: None,
+            avatars: None,
+            outbound: None,
+            reconnect: ReconnectPolicy::default(),
+            personas: Vec::new(),
         }
     }
 
+    /// Keys the default persona rotated away from; messages sealed with them stay readable.
+    pub fn with_retired_keys(mut self, retired: Vec<SigningKey>) -> Self {
+        self.retired = retired;
+        self
+    }
+
     pub fn with_username(mut self, username: String) -> Self {
         self.username = Some(username);
         self
@@ -49,6 +93,80 @@ impl ChatManagerBuilder {
         self
     }
 
+    pub fn with_history(mut self, history: HistoryStore) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn with_access_policy(mut self, policy: impl AccessPolicy + 'static) -> Self {
+        self.access_policy = Box::new(policy);
+        self
+    }
+
+    pub fn with_trust_store(mut self, trust: TrustStore) -> Self {
+        self.trust = Some(trust);
+        self
+    }
+
+    pub fn with_address_book(mut self, address_book: AddressBook) -> Self {
+        self.address_book = Some(address_book);
+        self
+    }
+
+    pub fn with_succession_log(mut self, successions: SuccessionLog) -> Self {
+        self.successions = Some(successions);
+        self
+    }
+
+    pub fn with_linked_devices(mut self, devices: LinkedDevices) -> Self {
+        self.devices = Some(devices);
+        self
+    }
+
+    pub fn with_profile_cache(mut self, profiles: ProfileCache) -> Self {
+        self.profiles = Some(profiles);
+        self
+    }
+
+    pub fn with_avatar_store(mut self, avatars: AvatarStore) -> Self {
+        self.avatars = Some(avatars);
+        self
+    }
+
+    pub fn with_outbound_queue(mut self, outbound: OutboundQueue) -> Self {
+        self.outbound = Some(outbound);
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Present another persona besides the default one, with its own key and profile. Peers reach
+    /// it through a gateway at `swiss`, which has to be unguessable, or anyone could tell which
+    /// personas a node presents; keep it across restarts so invites stay valid. `retired` are the
+    /// keys the persona rotated away from.
+    pub fn with_persona(
+        mut self,
+        name: String,
+        swiss: Swiss,
+        signing_key: SigningKey,
+        retired: Vec<SigningKey>,
+        username: String,
+        avatar: Option<String>,
+    ) -> Self {
+        self.personas.push(PersonaSpec {
+            name,
+            swiss,
+            signing_key,
+            retired,
+            username,
+            avatar,
+        });
+        self
+    }
+
     pub fn with_netlayer<Nl>(mut self, transport: String, netlayer: Nl) -> Self
     where
         Nl: Netlayer + Send + 'static,
@@ -90,10 +208,70 @@ impl ChatManagerBuilder {
             .username
             .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
         let data = Arc::new(ChatData::default());
-        let persona = Arc::new(Persona::new(Profile::new(vkey, username, self.avatar)));
+        let history = Arc::new(self.history.unwrap_or_default());
+        let outbound = Arc::new(self.outbound.unwrap_or_default());
+        let avatars = Arc::new(self.avatars.unwrap_or_default());
+        let successions = Arc::new(self.successions.unwrap_or_default());
+        let devices = Arc::new(self.devices.unwrap_or_default());
+        let new_identity = |name: String,
+                            swiss: Swiss,
+                            skey: SigningKey,
+                            retired: Vec<SigningKey>,
+                            profile: Profile| {
+            let retired = Arc::<[SigningKey]>::from(retired);
+            let mailboxes = Mailboxes::new(
+                skey.verifying_key(),
+                retired.clone(),
+                successions.clone(),
+                devices.clone(),
+                history.clone(),
+                outbound.clone(),
+                self.ev_sender.clone(),
+            );
+            Identity::new(
+                name,
+                swiss,
+                skey,
+                retired,
+                Persona::new(profile, avatars.clone()),
+                mailboxes,
+                self.ev_sender.clone(),
+            )
+        };
+        let identity = Arc::new(new_identity(
+            DEFAULT_PERSONA.to_owned(),
+            GATEWAY_SWISS.to_vec(),
+            skey,
+            self.retired,
+            Profile::new(vkey, username, self.avatar),
+        ));
+        let identities = DashMap::new();
+        identities.insert(DEFAULT_PERSONA.to_owned(), identity.clone());
+        for spec in self.personas {
+            if identities.contains_key(&spec.name)
+                || identities
+                    .iter()
+                    .any(|entry| entry.gateway_swiss() == spec.swiss.as_slice())
+            {
+                tracing::warn!(persona = spec.name, "ignoring duplicate persona");
+                continue;
+            }
+            let profile =
+                Profile::new(spec.signing_key.verifying_key(), spec.username, spec.avatar);
+            let persona = new_identity(
+                spec.name.clone(),
+                spec.swiss,
+                spec.signing_key,
+                spec.retired,
+                profile,
+            );
+            identities.insert(spec.name, Arc::new(persona));
+        }
         ChatManager {
-            signing_key: Arc::new(parking_lot::RwLock::new(skey)),
-            persona,
+            signing_key: identity.signing_key.clone(),
+            persona: identity.persona.clone(),
+            identities: Arc::new(identities),
+            identity,
 
             layers: Arc::new(self.layers),
             ev_sender: self.ev_sender.clone(),
@@ -102,10 +280,23 @@ impl ChatManagerBuilder {
             subscription_tasks: self.subscription_tasks.into(),
 
             data,
+            history,
+            outbound,
 
-            gateway: Arc::new(Gateway::new(self.ev_sender)),
             portals: Default::default(),
-            channels: Default::default(),
+            joined: Default::default(),
+            joined_as: Default::default(),
+            remote_portals: Default::default(),
+            reconnect: Arc::new(Reconnector::new(self.reconnect)),
+
+            access_policy: self.access_policy,
+            trust: Arc::new(self.trust.unwrap_or_default()),
+            address_book: Arc::new(self.address_book.unwrap_or_default()),
+            successions,
+            devices,
+            profiles: Arc::new(self.profiles.unwrap_or_default()),
+            avatars,
+            pending_access: Default::default(),
         }
     }
 }
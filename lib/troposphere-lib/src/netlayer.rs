@@ -1,8 +1,9 @@
 use crate::{manage_session, EventSender, NetworkEvent};
+use dashmap::DashMap;
 use futures::{FutureExt, TryFutureExt};
 use rexa::{
     async_compat::{AsyncRead, AsyncWrite},
-    captp::AbstractCapTpSession,
+    captp::{AbstractCapTpSession, RemoteKey},
 };
 use rexa::{locator::NodeLocator, netlayer::Netlayer};
 use std::future::Future;
@@ -28,10 +29,17 @@ pub enum ConnectError {
     Netlayer(#[from] Box<dyn std::error::Error + Send + Sync + 'static>),
 }
 
+struct SessionHandle {
+    /// Whether we dialed the session, rather than accepting it.
+    outbound: bool,
+    abort: oneshot::Sender<String>,
+}
+
 pub struct NetlayerManager {
     tasks: JoinSet<Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>>,
     layers: HashMap<String, mpsc::UnboundedSender<ConnectRequest>>,
     locators: HashMap<String, Vec<NodeLocator>>,
+    sessions: Arc<DashMap<RemoteKey, SessionHandle>>,
 }
 
 impl NetlayerManager {
@@ -40,6 +48,7 @@ impl NetlayerManager {
             tasks: JoinSet::new(),
             layers: HashMap::new(),
             locators: HashMap::new(),
+            sessions: Arc::default(),
         }
     }
 
@@ -60,8 +69,10 @@ impl NetlayerManager {
         let (sender, receiver) = mpsc::unbounded_channel();
         self.layers.insert(transport.clone(), sender.clone());
         let locators = nl.locators();
-        self.tasks
-            .spawn(manage_netlayer(nl, event_pipe, end_flag, receiver).map_err(From::from));
+        self.tasks.spawn(
+            manage_netlayer(nl, event_pipe, end_flag, receiver, self.sessions.clone())
+                .map_err(From::from),
+        );
         tracing::info!(%transport, ?locators, "registered netlayer");
         self.locators.insert(transport, locators);
         sender
@@ -86,6 +97,20 @@ impl NetlayerManager {
     pub fn locators(&self) -> impl Iterator<Item = &NodeLocator> {
         self.locators.values().flatten()
     }
+
+    /// Whether we dialed the live session `session_key`, or `None` if it has ended.
+    pub fn is_outbound(&self, session_key: &RemoteKey) -> Option<bool> {
+        self.sessions.get(session_key).map(|handle| handle.outbound)
+    }
+
+    /// Abort a live session, telling the other side why. It's reported as
+    /// [`NetworkEvent::SessionAborted`] like any other. Returns whether the session was live.
+    pub fn abort_session(&self, session_key: &RemoteKey, reason: impl Into<String>) -> bool {
+        match self.sessions.remove(session_key) {
+            Some((_, handle)) => handle.abort.send(reason.into()).is_ok(),
+            None => false,
+        }
+    }
 }
 
 impl Default for NetlayerManager {
@@ -94,12 +119,13 @@ impl Default for NetlayerManager {
     }
 }
 
-#[tracing::instrument(fields(nl = ?nl.locators()), skip(event_pipe, end_flag, connect_reqs))]
+#[tracing::instrument(fields(nl = ?nl.locators()), skip(event_pipe, end_flag, connect_reqs, sessions))]
 async fn manage_netlayer<Nl: Netlayer>(
     nl: Nl,
     event_pipe: EventSender,
     mut end_flag: tokio::sync::watch::Receiver<bool>,
     mut connect_reqs: mpsc::UnboundedReceiver<ConnectRequest>,
+    sessions: Arc<DashMap<RemoteKey, SessionHandle>>,
 ) -> Result<(), Nl::Error>
 where
     Nl::Reader: AsyncRead + Unpin + Send + 'static,
@@ -109,11 +135,11 @@ where
     tracing::debug!("managing netlayer");
     let mut session_tasks = JoinSet::new();
     loop {
-        let session = tokio::select! {
+        let (session, outbound) = tokio::select! {
             session = nl.accept() => {
                 let session = session?;
                 tracing::debug!(?session, "accepted connection");
-                session
+                (session, false)
             },
             Some((locator, res_pipe)) = connect_reqs.recv() => {
                 let session = match nl.connect(&locator).await {
@@ -128,20 +154,25 @@ where
                     }
                 };
                 tracing::debug!(?locator, ?session, "connected to node");
-                session
+                (session, true)
             },
             _ = end_flag.changed() => break,
         };
+        let session_key = *session.remote_vkey();
+        let (abort, abort_req) = oneshot::channel();
+        sessions.insert(session_key, SessionHandle { outbound, abort });
         drop(event_pipe.send(NetworkEvent::SessionStarted(session.as_dyn())));
+        let managed = manage_session(session, event_pipe.clone(), end_flag.clone(), abort_req);
+        let sessions = sessions.clone();
         // let task_name = format!("manage_session: {session:?}");
         session_tasks
             // .build_task()
             // .name(&task_name)
-            .spawn(manage_session(
-                session,
-                event_pipe.clone(),
-                end_flag.clone(),
-            ));
+            .spawn(async move {
+                let res = managed.await;
+                sessions.remove(&session_key);
+                res
+            });
         // .unwrap();
     }
     session_tasks.abort_all();
@@ -0,0 +1,285 @@
+use std::{collections::HashSet, path::PathBuf, time::Duration};
+
+use crate::{store, unix_millis, ChannelId, Message, MessageId, PeerKey, SyrupUuid};
+
+/// How many messages may wait for one member before further ones are refused.
+pub const OUTBOUND_MAX_PER_PEER: usize = 1024;
+/// How long a message waits for a member before it's given up on, counted from when it was sent.
+pub const OUTBOUND_TTL: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "queued-message")]
+struct QueuedMessage {
+    recipient: PeerKey,
+    message: Message,
+}
+
+/// Appended once a queued message no longer waits for its recipient.
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "dequeued-message")]
+struct DequeuedMessage {
+    recipient: PeerKey,
+    #[syrup(as = SyrupUuid)]
+    message: MessageId,
+}
+
+/// Appended whenever a member of a channel goes away or comes back.
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "member-presence")]
+struct MemberPresence {
+    #[syrup(as = SyrupUuid)]
+    channel: ChannelId,
+    peer_key: PeerKey,
+    absent: bool,
+}
+
+#[derive(Default)]
+struct QueueState {
+    entries: Vec<QueuedMessage>,
+    absent: HashSet<(ChannelId, PeerKey)>,
+    /// Records in the file, live or not; once they far outnumber the live ones it's rewritten.
+    records: usize,
+}
+
+impl QueueState {
+    fn live_records(&self) -> usize {
+        self.entries.len() + self.absent.len()
+    }
+
+    fn expire(&mut self, now: u64) -> bool {
+        let ttl = u64::try_from(OUTBOUND_TTL.as_millis()).unwrap_or(u64::MAX);
+        let len = self.entries.len();
+        self.entries
+            .retain(|entry| entry.message.timestamp.saturating_add(ttl) > now);
+        self.entries.len() != len
+    }
+}
+
+/// Messages we sent while some members of their channel were offline, kept until each of those
+/// members reconnects, along with which members are offline. Entries are flushed in the order they
+/// were queued. Changes are appended to the file, which is compacted when it's mostly stale.
+pub struct OutboundQueue {
+    path: Option<PathBuf>,
+    state: parking_lot::Mutex<QueueState>,
+}
+
+impl std::fmt::Debug for OutboundQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutboundQueue")
+            .field("path", &self.path)
+            .field("entries", &self.state.lock().entries.len())
+            .finish()
+    }
+}
+
+impl Default for OutboundQueue {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl OutboundQueue {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut state = QueueState::default();
+        for frame in store::read_frames(&path)? {
+            state.records += 1;
+            if let Ok(queued) = syrup::de::from_bytes::<QueuedMessage>(&frame) {
+                state.entries.push(queued);
+            } else if let Ok(dequeued) = syrup::de::from_bytes::<DequeuedMessage>(&frame) {
+                state.entries.retain(|entry| {
+                    entry.recipient != dequeued.recipient || entry.message.id != dequeued.message
+                });
+            } else if let Ok(presence) = syrup::de::from_bytes::<MemberPresence>(&frame) {
+                if presence.absent {
+                    state.absent.insert((presence.channel, presence.peer_key));
+                } else {
+                    state.absent.remove(&(presence.channel, presence.peer_key));
+                }
+            } else {
+                tracing::warn!(?path, "skipping unreadable outbound queue record");
+            }
+        }
+        let stale = state.expire(unix_millis()) || state.records > state.live_records();
+        let queue = Self {
+            path: Some(path),
+            state: parking_lot::Mutex::new(state),
+        };
+        if stale {
+            queue.compact(&mut queue.state.lock());
+        }
+        Ok(queue)
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            state: parking_lot::Mutex::new(QueueState::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().entries.is_empty()
+    }
+
+    /// The members `message` is still waiting for.
+    pub fn recipients(&self, message: &MessageId) -> Vec<PeerKey> {
+        self.state
+            .lock()
+            .entries
+            .iter()
+            .filter(|entry| &entry.message.id == message)
+            .map(|entry| entry.recipient)
+            .collect()
+    }
+
+    /// Every queued message of `channel`, with the member it's waiting for.
+    pub(crate) fn queued_in(&self, channel: &ChannelId) -> Vec<(PeerKey, MessageId)> {
+        self.state
+            .lock()
+            .entries
+            .iter()
+            .filter(|entry| &entry.message.channel == channel)
+            .map(|entry| (entry.recipient, entry.message.id))
+            .collect()
+    }
+
+    /// The messages of `channel` waiting for `recipient`, oldest first. They stay queued until
+    /// [`Self::remove`]d or they expire.
+    pub(crate) fn waiting(&self, channel: &ChannelId, recipient: &PeerKey) -> Vec<Message> {
+        let mut state = self.state.lock();
+        if state.expire(unix_millis()) {
+            self.compact(&mut state);
+        }
+        state
+            .entries
+            .iter()
+            .filter(|entry| &entry.message.channel == channel && &entry.recipient == recipient)
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    /// Queue `message` for `recipient`. Returns whether it's queued: a member that already has
+    /// [`OUTBOUND_MAX_PER_PEER`] messages waiting gets no more.
+    pub(crate) fn push(&self, recipient: PeerKey, message: &Message) -> bool {
+        let mut state = self.state.lock();
+        if state
+            .entries
+            .iter()
+            .any(|entry| entry.recipient == recipient && entry.message.id == message.id)
+        {
+            return true;
+        }
+        let waiting = state
+            .entries
+            .iter()
+            .filter(|entry| entry.recipient == recipient)
+            .count();
+        if waiting >= OUTBOUND_MAX_PER_PEER {
+            tracing::warn!(
+                recipient = rexa::hash(&recipient),
+                message = %message.id,
+                "outbound queue for member is full"
+            );
+            return false;
+        }
+        let queued = QueuedMessage {
+            recipient,
+            message: message.clone(),
+        };
+        state.entries.push(queued.clone());
+        self.append(&mut state, &queued);
+        true
+    }
+
+    pub(crate) fn remove(&self, recipient: &PeerKey, message: &MessageId) {
+        let mut state = self.state.lock();
+        let len = state.entries.len();
+        state
+            .entries
+            .retain(|entry| &entry.recipient != recipient || &entry.message.id != message);
+        if state.entries.len() != len {
+            let dequeued = DequeuedMessage {
+                recipient: *recipient,
+                message: *message,
+            };
+            self.append(&mut state, &dequeued);
+        }
+    }
+
+    /// The members of `channel` that were offline when we last saw it.
+    pub(crate) fn absent_from(&self, channel: &ChannelId) -> Vec<PeerKey> {
+        self.state
+            .lock()
+            .absent
+            .iter()
+            .filter(|(absent_from, _)| absent_from == channel)
+            .map(|(_, peer_key)| *peer_key)
+            .collect()
+    }
+
+    /// Record whether `peer_key` is offline from `channel`, so messages are still queued for it
+    /// after a restart.
+    pub(crate) fn set_absent(&self, channel: ChannelId, peer_key: PeerKey, absent: bool) {
+        let mut state = self.state.lock();
+        let changed = if absent {
+            state.absent.insert((channel, peer_key))
+        } else {
+            state.absent.remove(&(channel, peer_key))
+        };
+        if changed {
+            let presence = MemberPresence {
+                channel,
+                peer_key,
+                absent,
+            };
+            self.append(&mut state, &presence);
+        }
+    }
+
+    /// Persist a change already made to `state`.
+    fn append<T: syrup::Serialize>(&self, state: &mut QueueState, record: &T) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        state.records += 1;
+        if state.records > 2 * state.live_records() + 64 {
+            return self.compact(state);
+        }
+        if let Err(error) = store::append_record(path, record) {
+            tracing::error!(?path, %error, "failed to persist outbound queue");
+        }
+    }
+
+    /// Rewrite the file with only what's still live.
+    fn compact(&self, state: &mut QueueState) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let absent = state
+            .absent
+            .iter()
+            .map(|&(channel, peer_key)| MemberPresence {
+                channel,
+                peer_key,
+                absent: true,
+            });
+        let frames = state
+            .entries
+            .iter()
+            .map(store::encode)
+            .chain(absent.map(|presence| store::encode(&presence)));
+        let res = store::write_frames(path, frames);
+        match res {
+            Ok(()) => state.records = state.live_records(),
+            Err(error) => tracing::error!(?path, %error, "failed to compact outbound queue"),
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use std::{ops::Deref, sync::Arc};
+use std::{collections::HashSet, ops::Deref, sync::Arc};
 
 use dashmap::DashMap;
-use ed25519_dalek::{ed25519::signature::SignerMut, Signature, SigningKey, VerifyingKey};
+use ed25519_dalek::{Signature, SignatureError, Signer, SigningKey, VerifyingKey};
 use rexa::{
     captp::{
         msg::DescExport,
@@ -12,52 +12,274 @@ use rexa::{
         AbstractCapTpSession, GenericResolver, RemoteKey,
     },
     impl_object,
+    locator::SturdyRefLocator,
 };
 use syrup::{Deserialize, FromSyrupItem, Serialize};
 use tokio::sync::{mpsc, oneshot, RwLock as AsyncRwLock};
 
 use crate::{
-    Channel, ChannelEvent, ChannelId, ChannelInfo, ChannelListing, NetworkEvent, PeerKey, SyrupUuid,
+    BackfillError, Channel, ChannelEvent, ChannelId, ChannelInfo, ChannelListing,
+    DeviceCertificate, HistoryBatch, HistoryCursor, HistoryError, HistoryRequest, HistoryStore,
+    LinkedDevices, MailboxKey, Mailboxes, Message, NetworkEvent, PeerKey, Persona, Profile,
+    RemoteChannel, Succession, SyrupUuid, Timestamp, MAX_AVATAR_SIZE,
 };
 
 pub const GATEWAY_SWISS: &[u8] = b"gateway";
+/// How long a peer has to answer an [`AuthChallenge`], in milliseconds.
+pub const CHALLENGE_TTL: u64 = 30_000;
+/// How many successions one session may present to a gateway; more than a peer would rotate
+/// through in practice.
+pub const MAX_PRESENTED_SUCCESSIONS: usize = 16;
+/// How many device certificates one session may present to a gateway.
+pub const MAX_PRESENTED_CERTIFICATES: usize = 16;
 
+const CHALLENGE_DOMAIN: &[u8] = b"troposphere/gateway-challenge/v1";
+const RESPONSE_DOMAIN: &[u8] = b"troposphere/gateway-response/v1";
+
+/// A single-use nonce issued by a [`Gateway`] and bound to the session it was issued over. The
+/// gateway signs it with its own identity so the client also learns who it's talking to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[syrup(name = "auth-challenge")]
+pub struct AuthChallenge {
+    pub nonce: syrup::Bytes<Vec<u8>>,
+    /// The client's session key, as seen by the gateway.
+    pub session_key: RemoteKey,
+    pub issued_at: Timestamp,
+    pub gateway_key: PeerKey,
+    pub gateway_signature: Signature,
+}
+
+impl AuthChallenge {
+    fn challenge_payload(
+        nonce: &[u8],
+        session_key: &RemoteKey,
+        issued_at: Timestamp,
+        gateway_key: &PeerKey,
+    ) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(CHALLENGE_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&syrup::Bytes(nonce)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(session_key).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&issued_at).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(gateway_key).unwrap());
+        res
+    }
+
+    fn issue(session_key: RemoteKey, signing_key: &SigningKey) -> Self {
+        let nonce = rand::random::<[u8; 32]>().to_vec();
+        let issued_at = crate::unix_millis();
+        let gateway_key = signing_key.verifying_key();
+        let gateway_signature = signing_key.sign(&Self::challenge_payload(
+            &nonce,
+            &session_key,
+            issued_at,
+            &gateway_key,
+        ));
+        Self {
+            nonce: syrup::Bytes(nonce),
+            session_key,
+            issued_at,
+            gateway_key,
+            gateway_signature,
+        }
+    }
+
+    /// Check that the challenge was signed by the gateway it names.
+    pub fn verify(&self) -> Result<(), SignatureError> {
+        self.gateway_key.verify_strict(
+            &Self::challenge_payload(
+                &self.nonce.0,
+                &self.session_key,
+                self.issued_at,
+                &self.gateway_key,
+            ),
+            &self.gateway_signature,
+        )
+    }
+
+    /// The bytes a client signs to answer this challenge as `peer_key`.
+    pub fn response_payload(&self, peer_key: &PeerKey) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(RESPONSE_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&self.nonce).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&self.session_key).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&self.gateway_key).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(peer_key).unwrap());
+        res
+    }
+
+    fn is_stale(&self, now: Timestamp) -> bool {
+        now.saturating_sub(self.issued_at) > CHALLENGE_TTL
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum AuthError {
+    #[error(
+        "no outstanding challenge for this session; it was never issued or has already been used"
+    )]
+    ReplayedChallenge,
+    #[error("challenge expired before it was answered")]
+    StaleChallenge,
+    #[error("could not verify signature")]
+    BadSignature,
+}
+
+impl AuthError {
+    /// The reason sent to the peer when its authentication is refused.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::ReplayedChallenge => "replayed-challenge",
+            Self::StaleChallenge => "stale-challenge",
+            Self::BadSignature => "bad-signature",
+        }
+    }
+}
+
+/// Where peers authenticate to one of our personas.
 pub struct Gateway {
+    persona: String,
     ev_sender: mpsc::UnboundedSender<NetworkEvent>,
+    signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+    challenges: DashMap<RemoteKey, AuthChallenge>,
+    /// How many successions each session has presented.
+    presented: DashMap<RemoteKey, usize>,
+    /// How many device certificates each session has presented.
+    certified: DashMap<RemoteKey, usize>,
 }
 
 impl Gateway {
-    pub fn new(ev_sender: mpsc::UnboundedSender<NetworkEvent>) -> Self {
-        Self { ev_sender }
+    pub fn new(
+        persona: String,
+        ev_sender: mpsc::UnboundedSender<NetworkEvent>,
+        signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+    ) -> Self {
+        Self {
+            persona,
+            ev_sender,
+            signing_key,
+            challenges: DashMap::new(),
+            presented: DashMap::new(),
+            certified: DashMap::new(),
+        }
+    }
+
+    /// Drop any outstanding challenge issued over a session that has ended.
+    pub(crate) fn forget_session(&self, session_key: &RemoteKey) {
+        self.challenges.remove(session_key);
+        self.presented.remove(session_key);
+        self.certified.remove(session_key);
+    }
+
+    fn check_response(
+        &self,
+        session_key: &RemoteKey,
+        peer_vkey: &PeerKey,
+        signature: &Signature,
+    ) -> Result<(), AuthError> {
+        // challenges are single-use, so take it out whether or not the response is valid
+        let Some((_, challenge)) = self.challenges.remove(session_key) else {
+            return Err(AuthError::ReplayedChallenge);
+        };
+        if challenge.is_stale(crate::unix_millis()) {
+            return Err(AuthError::StaleChallenge);
+        }
+        peer_vkey
+            .verify_strict(&challenge.response_payload(peer_vkey), signature)
+            .map_err(|_err| AuthError::BadSignature)
     }
 }
 
 #[impl_object(tracing = ::tracing)]
 impl Gateway {
+    #[deliver(always_fulfill)]
+    #[allow(clippy::needless_pass_by_value)]
+    fn challenge(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+    ) -> AuthChallenge {
+        let session_key = *session.remote_vkey();
+        let challenge = AuthChallenge::issue(session_key, &self.signing_key.read());
+        self.challenges.insert(session_key, challenge.clone());
+        challenge
+    }
+
     #[deliver()]
     #[allow(clippy::needless_pass_by_value)]
     async fn authenticate(
         &self,
         #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
         peer_vkey: PeerKey,
-        #[arg(syrup_from = syrup::Bytes<Vec<u8>>)] message: Vec<u8>,
         signature: Signature,
         #[arg(resolver)] resolver: GenericResolver,
     ) -> Result<(), ObjectError> {
         tracing::debug!("received authentication request");
-        if peer_vkey.verify_strict(&message, &signature).is_err() {
+        if let Err(error) = self.check_response(session.remote_vkey(), &peer_vkey, &signature) {
+            tracing::warn!(
+                session = rexa::hash(session.remote_vkey()),
+                peer_vkey = rexa::hash(&peer_vkey),
+                %error,
+                "refused authentication"
+            );
             return resolver
-                .break_promise("could not verify signature")
+                .break_promise(error.reason())
                 .await
                 .map_err(From::from);
         }
         drop(self.ev_sender.send(NetworkEvent::PortalRequest {
             session,
+            persona: self.persona.clone(),
             peer_vkey,
             resolver,
         }));
         Ok(())
     }
+
+    /// Tell us a key was retired, before authenticating with its successor, so the successor is
+    /// admitted the way the old key was. A session may present at most
+    /// [`MAX_PRESENTED_SUCCESSIONS`].
+    #[deliver()]
+    #[allow(clippy::needless_pass_by_value)]
+    fn present_succession(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        succession: Succession,
+    ) -> Result<(), &'static str> {
+        let mut presented = self.presented.entry(*session.remote_vkey()).or_default();
+        if *presented >= MAX_PRESENTED_SUCCESSIONS {
+            return Err("too-many-successions");
+        }
+        *presented += 1;
+        drop(presented);
+        if succession.verify().is_err() {
+            return Err("bad-signature");
+        }
+        drop(self.ev_sender.send(NetworkEvent::Succession { succession }));
+        Ok(())
+    }
+
+    /// Show that the key about to authenticate is a device of another persona's. A session may
+    /// present at most [`MAX_PRESENTED_CERTIFICATES`].
+    #[deliver()]
+    #[allow(clippy::needless_pass_by_value)]
+    fn present_certificate(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        certificate: DeviceCertificate,
+    ) -> Result<(), &'static str> {
+        let mut certified = self.certified.entry(*session.remote_vkey()).or_default();
+        if *certified >= MAX_PRESENTED_CERTIFICATES {
+            return Err("too-many-certificates");
+        }
+        *certified += 1;
+        drop(certified);
+        if certificate.verify().is_err() {
+            return Err("bad-signature");
+        }
+        drop(
+            self.ev_sender
+                .send(NetworkEvent::DeviceCertificate { certificate }),
+        );
+        Ok(())
+    }
 }
 
 pub struct RemoteGateway {
@@ -79,30 +301,76 @@ impl Fetch for RemoteGateway {
 }
 
 impl RemoteGateway {
-    #[tracing::instrument(skip(self, skey), fields(message = %String::from_utf8_lossy(message)))]
+    #[tracing::instrument(skip(self))]
+    pub async fn challenge(&self) -> Result<AuthChallenge, RemoteError> {
+        let Some(challenge) = self
+            .base
+            .deliver_and([&syrup::Symbol("challenge")])
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "AuthChallenge"));
+        };
+        AuthChallenge::from_syrup_item(&challenge)
+            .map_err(|_err| RemoteError::unexpected("AuthChallenge", 0, challenge))
+    }
+
+    #[tracing::instrument(skip_all, fields(old_key = rexa::hash(&succession.old_key)))]
+    pub async fn present_succession(&self, succession: &Succession) -> Result<(), RemoteError> {
+        self.base
+            .call_and("present_succession", &syrup::raw_syrup_unwrap![succession])
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(device = rexa::hash(&certificate.device)))]
+    pub async fn present_certificate(
+        &self,
+        certificate: &DeviceCertificate,
+    ) -> Result<(), RemoteError> {
+        self.base
+            .call_and(
+                "present_certificate",
+                &syrup::raw_syrup_unwrap![certificate],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Request a challenge, check the gateway's signature on it and that it was issued for this
+    /// session, and answer it as `skey`.
+    #[tracing::instrument(skip_all, fields(vkey = rexa::hash(&skey.verifying_key())))]
     pub async fn authenticate_with(
         &self,
-        skey: &mut SigningKey,
-        message: &[u8],
-    ) -> Result<RemotePortal, RemoteError> {
-        self.authenticate(&skey.verifying_key(), message, &skey.sign(message))
+        skey: &SigningKey,
+    ) -> Result<RemotePortal, RemotePortalError> {
+        let challenge = self.challenge().await?;
+        challenge
+            .verify()
+            .map_err(RemotePortalError::GatewaySignature)?;
+        // a challenge for another session means someone is relaying ours to another gateway
+        let session_key = self.base.session().signing_key().verifying_key();
+        if challenge.session_key != session_key {
+            return Err(RemotePortalError::SessionMismatch);
+        }
+        let vkey = skey.verifying_key();
+        let signature = skey.sign(&challenge.response_payload(&vkey));
+        self.authenticate(&vkey, &signature, challenge.gateway_key)
             .await
+            .map_err(From::from)
     }
 
-    #[tracing::instrument(fields(vkey = rexa::hash(vkey), message = %String::from_utf8_lossy(message)), skip(self, signature))]
+    #[tracing::instrument(fields(vkey = rexa::hash(vkey), gateway_key = rexa::hash(&gateway_key)), skip(self, signature))]
     pub async fn authenticate(
         &self,
         vkey: &VerifyingKey,
-        message: &[u8],
         signature: &Signature,
+        gateway_key: PeerKey,
     ) -> Result<RemotePortal, RemoteError> {
         tracing::trace!("authenticating gateway");
         match self
             .base
-            .call_and(
-                "authenticate",
-                &syrup::raw_syrup_unwrap![vkey, &syrup::Bytes(message), signature],
-            )
+            .call_and("authenticate", &syrup::raw_syrup_unwrap![vkey, signature])
             .await?
             .pop()
         {
@@ -114,6 +382,8 @@ impl RemoteGateway {
                             .clone()
                             .into_remote_object_unchecked(pos)
                     },
+                    peer_key: gateway_key,
+                    local_key: *vkey,
                 }),
                 Err(_) => Err(RemoteError::unexpected("DescExport", 0, position)),
             },
@@ -126,11 +396,30 @@ impl RemoteGateway {
 #[syrup(name = "connect-result")]
 pub(crate) struct ConnectResult {
     position: DescExport,
+    /// Whether the host forwards other members' messages over this connection.
+    relay: bool,
+}
+
+/// What a portal serves another device of the persona it was opened to.
+pub(crate) struct LinkedSync {
+    pub(crate) history: Arc<HistoryStore>,
+    /// The persona's mailboxes with the peers it knows, whether or not they're open.
+    pub(crate) mailboxes: HashSet<ChannelId>,
+    pub(crate) signing_key: Arc<parking_lot::RwLock<SigningKey>>,
 }
 
 pub struct Portal {
     remote_key: PeerKey,
     channels: Arc<DashMap<ChannelId, Channel>>,
+    /// Channels hosted elsewhere that we're a member of; other members may connect to us directly,
+    /// but they aren't listed.
+    joined: Arc<DashMap<ChannelId, Channel>>,
+    mailboxes: Arc<Mailboxes>,
+    /// If the peer is another device of the same persona, the persona's history to sync.
+    linked: Option<LinkedSync>,
+    /// The persona the peer authenticated to.
+    persona: Arc<Persona>,
+    ev_sender: mpsc::UnboundedSender<NetworkEvent>,
 }
 
 impl std::fmt::Debug for Portal {
@@ -140,10 +429,59 @@ impl std::fmt::Debug for Portal {
 }
 
 impl Portal {
-    pub(crate) fn new(remote_key: PeerKey, channels: Arc<DashMap<ChannelId, Channel>>) -> Self {
+    pub(crate) fn new(
+        remote_key: PeerKey,
+        channels: Arc<DashMap<ChannelId, Channel>>,
+        joined: Arc<DashMap<ChannelId, Channel>>,
+        mailboxes: Arc<Mailboxes>,
+        linked: Option<LinkedSync>,
+        persona: Arc<Persona>,
+        ev_sender: mpsc::UnboundedSender<NetworkEvent>,
+    ) -> Self {
         Self {
             remote_key,
             channels,
+            joined,
+            mailboxes,
+            linked,
+            persona,
+            ev_sender,
+        }
+    }
+
+    /// Whether `channel` is one the persona hosts, joined or has a mailbox for, and so is synced
+    /// to its other devices.
+    fn syncs(&self, channel: &ChannelId, linked: &LinkedSync) -> bool {
+        self.channels.contains_key(channel)
+            || self.joined.contains_key(channel)
+            || self.mailboxes.contains(channel)
+            || linked.mailboxes.contains(channel)
+    }
+
+    fn join(
+        &self,
+        session: &Arc<dyn AbstractCapTpSession + Send + Sync>,
+        channel: &Channel,
+        outbox: DescExport,
+        locator: Option<SturdyRefLocator>,
+    ) -> ConnectResult {
+        let position = match channel.exported_position(session.remote_vkey()) {
+            Some(pos) => (*pos).into(),
+            // FIX :: eugh
+            None => session.exports().export(Arc::new(channel.clone())),
+        };
+
+        channel.connect_peer(
+            *session.remote_vkey(),
+            self.remote_key,
+            unsafe { session.clone().into_remote_object_unchecked(outbox) },
+            locator,
+            false,
+        );
+
+        ConnectResult {
+            position,
+            relay: channel.relays(),
         }
     }
 }
@@ -158,30 +496,145 @@ impl Portal {
             .collect()
     }
 
+    /// Join a channel. `locator` is where the peer can be reached by other members; if we host
+    /// the channel, the peer is introduced to every member that gave one.
     #[deliver()]
     fn connect(
         &self,
         #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
         #[arg(syrup_from = SyrupUuid)] channel_id: ChannelId,
         outbox: DescExport,
+        locator: Option<SturdyRefLocator>,
     ) -> Result<ConnectResult, &'static str> {
-        let Some(channel) = self.channels.get(&channel_id) else {
+        if let Some(channel) = self.channels.get(&channel_id) {
+            let channel = channel.clone();
+            let res = self.join(&session, &channel, outbox, locator);
+            let session_key = *session.remote_vkey();
+            tokio::spawn(async move {
+                if let Err(error) = channel.introduce_members(&session_key).await {
+                    tracing::error!(channel = %channel.id(), %error, "failed to introduce channel members");
+                }
+            });
+            return Ok(res);
+        }
+        // members of a channel hosted elsewhere only meet through the host's introductions, and
+        // anyone else shouldn't learn that we're in it
+        let Some(channel) = self
+            .joined
+            .get(&channel_id)
+            .filter(|channel| channel.admits(&self.remote_key))
+        else {
             return Err("unrecognized channel id");
         };
+        Ok(self.join(&session, &channel, outbox, locator))
+    }
 
-        // let peers = channel;
+    /// Open the personal mailbox this node keeps for the authenticated peer.
+    #[deliver(always_fulfill)]
+    #[allow(clippy::needless_pass_by_value)]
+    fn open_mailbox(
+        &self,
+        #[arg(session)] session: Arc<dyn AbstractCapTpSession + Send + Sync>,
+        outbox: DescExport,
+    ) -> ConnectResult {
+        let mailbox = self.mailboxes.open(self.remote_key);
+        self.join(&session, &mailbox, outbox, None)
+    }
 
-        let position = match channel.exported_position(session.remote_vkey()) {
-            Some(pos) => (*pos).into(),
-            // FIX :: eugh
-            None => session.exports().export(Arc::new(channel.clone())),
+    /// The profile of the persona the peer authenticated to.
+    #[allow(clippy::needless_lifetimes)]
+    #[deliver(always_fulfill = [&*__res])]
+    async fn profile<'s>(&'s self) -> impl Deref<Target = Profile> + 's {
+        self.persona.profile.read().await
+    }
+
+    /// Download the avatar the persona's profile names.
+    #[deliver()]
+    #[allow(clippy::needless_pass_by_value)]
+    async fn avatar(&self, name: String) -> Result<syrup::Bytes<Vec<u8>>, &'static str> {
+        self.persona.avatar_bytes(&name).await.map(syrup::Bytes)
+    }
+
+    /// The peer changed its username or avatar.
+    #[deliver()]
+    fn profile_changed(&self, profile: Profile) -> Result<(), &'static str> {
+        if profile.vkey != self.remote_key {
+            return Err("profile of another peer");
+        }
+        if profile.check().is_err() {
+            return Err("profile too large");
+        }
+        drop(
+            self.ev_sender
+                .send(NetworkEvent::ProfileChanged { profile }),
+        );
+        Ok(())
+    }
+
+    /// The persona's channels we have history for. Only served to our other devices.
+    #[deliver()]
+    fn synced_channels(&self) -> Result<Vec<SyrupUuid>, &'static str> {
+        let Some(linked) = &self.linked else {
+            return Err("not a linked device");
         };
+        match linked.history.channels() {
+            Ok(channels) => Ok(channels
+                .into_iter()
+                .filter(|channel| self.syncs(channel, linked))
+                .map(SyrupUuid::from)
+                .collect()),
+            Err(error) => {
+                tracing::error!(%error, "failed to list channel history");
+                Err("could not read channel history")
+            }
+        }
+    }
 
-        channel.connect_peer(*session.remote_vkey(), self.remote_key, unsafe {
-            session.into_remote_object_unchecked(outbox)
-        });
+    /// Like a channel's own `history`, for any of the persona's channels we have history for. Only
+    /// served to our other devices.
+    #[deliver()]
+    fn sync_history(
+        &self,
+        #[arg(syrup_from = SyrupUuid)] channel_id: ChannelId,
+        request: HistoryRequest,
+    ) -> Result<HistoryBatch, &'static str> {
+        let Some(linked) = &self.linked else {
+            return Err("not a linked device");
+        };
+        if !self.syncs(&channel_id, linked) {
+            return Err("unrecognized channel id");
+        }
+        match linked.history.query(&channel_id, &request.query()) {
+            Ok(page) => Ok(page.into()),
+            Err(HistoryError::UnknownCursor(_)) => Err("unknown history cursor"),
+            Err(error) => {
+                tracing::error!(%error, "failed to query channel history");
+                Err("could not read channel history")
+            }
+        }
+    }
 
-        Ok(ConnectResult { position })
+    /// Our key for the mailbox with `counterpart`, sealed to the peer, so it can read the direct
+    /// messages it syncs from us. Only served to our other devices.
+    #[deliver()]
+    fn mailbox_key(
+        &self,
+        #[arg(syrup_from = SyrupUuid)] channel_id: ChannelId,
+        counterpart: PeerKey,
+    ) -> Result<MailboxKey, &'static str> {
+        let Some(linked) = &self.linked else {
+            return Err("not a linked device");
+        };
+        if self.mailboxes.id_for(&counterpart) != channel_id {
+            return Err("unrecognized mailbox");
+        }
+        MailboxKey::issue(
+            &linked.signing_key.read(),
+            counterpart,
+            channel_id,
+            self.remote_key,
+        )
+        .map_err(|_err| "could not seal mailbox key")
     }
 
     #[exported()]
@@ -196,38 +649,281 @@ pub enum RemotePortalError {
     Fetch(#[from] FetchError),
     #[error(transparent)]
     Gateway(#[from] RemoteError),
+    #[error("gateway challenge has an invalid signature: {0}")]
+    GatewaySignature(SignatureError),
+    #[error("gateway challenge was issued for another session")]
+    SessionMismatch,
+    #[error("avatar is {0} bytes; at most {MAX_AVATAR_SIZE} are allowed")]
+    AvatarTooLarge(usize),
 }
 
 pub struct RemotePortal {
     base: RemoteObject,
+    peer_key: PeerKey,
+    local_key: PeerKey,
 }
 
 impl RemotePortal {
-    pub fn open_with<'result>(
-        bootstrap: &'result RemoteBootstrap,
-        skey: &mut SigningKey,
-        message: &'result [u8],
-    ) -> impl std::future::Future<Output = Result<Self, RemotePortalError>> + 'result {
-        let signature = skey.sign(message);
-        let vkey = skey.verifying_key();
-        async move { Self::open(bootstrap, &vkey, message, &signature).await }
+    /// The authenticated identity of the peer hosting this portal.
+    pub fn peer_key(&self) -> &PeerKey {
+        &self.peer_key
     }
 
-    #[tracing::instrument(fields(vkey = rexa::hash(vkey), message = %String::from_utf8_lossy(message)), skip(bootstrap, signature))]
+    /// The identity we authenticated to the peer as.
+    pub fn local_key(&self) -> &PeerKey {
+        &self.local_key
+    }
+
+    #[tracing::instrument(fields(vkey = rexa::hash(&skey.verifying_key())), skip_all)]
     pub async fn open(
         bootstrap: &RemoteBootstrap,
-        vkey: &VerifyingKey,
-        message: &[u8],
-        signature: &Signature,
+        skey: &SigningKey,
     ) -> Result<Self, RemotePortalError> {
         // TODO :: this could probably be improved with promise pipelining
         tracing::trace!("opening portal");
         bootstrap
             .fetch_with::<RemoteGateway>(())
             .await?
-            .authenticate(vkey, message, signature)
+            .authenticate_with(skey)
             .await
-            .map_err(From::from)
+    }
+
+    /// Like [`Self::open`], but first present the successions that led to `skey` and the
+    /// certificate making it a device of another key, so the peer recognizes us by those.
+    #[tracing::instrument(fields(vkey = rexa::hash(&skey.verifying_key())), skip_all)]
+    pub async fn open_presenting(
+        bootstrap: &RemoteBootstrap,
+        skey: &SigningKey,
+        successions: &[Succession],
+        certificate: Option<&DeviceCertificate>,
+    ) -> Result<Self, RemotePortalError> {
+        tracing::trace!("opening portal");
+        let gateway = bootstrap.fetch_with::<RemoteGateway>(()).await?;
+        // an older peer won't know these methods; that only costs us being recognized
+        for succession in successions {
+            if let Err(error) = gateway.present_succession(succession).await {
+                tracing::warn!(%error, "gateway did not accept succession");
+            }
+        }
+        if let Some(certificate) = certificate {
+            if let Err(error) = gateway.present_certificate(certificate).await {
+                tracing::warn!(%error, "gateway did not accept device certificate");
+            }
+        }
+        gateway.authenticate_with(skey).await
+    }
+
+    /// Like [`Self::open`], but through the gateway at `swiss` rather than the default one.
+    #[tracing::instrument(fields(vkey = rexa::hash(&skey.verifying_key()), swiss = rexa::hash(swiss)), skip_all)]
+    pub async fn open_at(
+        bootstrap: &RemoteBootstrap,
+        swiss: &[u8],
+        skey: &SigningKey,
+    ) -> Result<Self, RemotePortalError> {
+        tracing::trace!("opening portal");
+        RemoteGateway {
+            base: bootstrap.fetch(swiss).await?,
+        }
+        .authenticate_with(skey)
+        .await
+    }
+
+    /// The profile the portal's host presents to us.
+    #[tracing::instrument(skip(self))]
+    pub async fn profile(&self) -> Result<Profile, RemoteError> {
+        let Some(profile) = self
+            .base
+            .deliver_and([&syrup::Symbol("profile")])
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "Profile"));
+        };
+        Profile::from_syrup_item(&profile)
+            .map_err(|_err| RemoteError::unexpected("Profile", 0, profile))
+    }
+
+    /// Download the avatar named in the host's profile. The reply has been received and decoded by
+    /// the time its size is checked, so an avatar over [`MAX_AVATAR_SIZE`] is only kept from
+    /// being returned and stored, not from being transferred. The bytes aren't checked against the
+    /// name; [`crate::AvatarStore::insert_named`] does that.
+    #[tracing::instrument(skip(self))]
+    pub async fn avatar(&self, name: &str) -> Result<Vec<u8>, RemotePortalError> {
+        let Some(bytes) = self
+            .base
+            .call_and("avatar", &syrup::raw_syrup_unwrap![name])
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "Bytes").into());
+        };
+        match bytes {
+            syrup::Item::Bytes(bytes) if bytes.len() > MAX_AVATAR_SIZE => {
+                Err(RemotePortalError::AvatarTooLarge(bytes.len()))
+            }
+            syrup::Item::Bytes(bytes) => Ok(bytes),
+            item => Err(RemoteError::unexpected("Bytes", 0, item).into()),
+        }
+    }
+
+    /// Tell the portal's host that our profile changed.
+    #[tracing::instrument(skip_all)]
+    pub async fn profile_changed(&self, profile: &Profile) -> Result<(), RemoteError> {
+        self.base
+            .call_and("profile_changed", &syrup::raw_syrup_unwrap![profile])
+            .await?;
+        Ok(())
+    }
+
+    /// The channels the portal's host has history for. Only answered for linked devices.
+    #[tracing::instrument(skip(self))]
+    pub async fn synced_channels(&self) -> Result<Vec<ChannelId>, RemoteError> {
+        let Some(channels) = self
+            .base
+            .deliver_and([&syrup::Symbol("synced_channels")])
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "Vec<SyrupUuid>"));
+        };
+        let channels = Vec::<SyrupUuid>::from_syrup_item(&channels)
+            .map_err(|_err| RemoteError::unexpected("Vec<SyrupUuid>", 0, channels))?;
+        Ok(channels.into_iter().map(ChannelId::from).collect())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn sync_history(
+        &self,
+        channel_id: ChannelId,
+        request: &HistoryRequest,
+    ) -> Result<HistoryBatch, RemoteError> {
+        let Some(batch) = self
+            .base
+            .call_and(
+                "sync_history",
+                &syrup::raw_syrup_unwrap![&SyrupUuid(channel_id), request],
+            )
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "HistoryBatch"));
+        };
+        HistoryBatch::from_syrup_item(&batch)
+            .map_err(|_err| RemoteError::unexpected("HistoryBatch", 0, batch))
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn mailbox_key(
+        &self,
+        channel_id: ChannelId,
+        counterpart: &PeerKey,
+    ) -> Result<MailboxKey, RemoteError> {
+        let Some(key) = self
+            .base
+            .call_and(
+                "mailbox_key",
+                &syrup::raw_syrup_unwrap![&SyrupUuid(channel_id), counterpart],
+            )
+            .await?
+            .pop()
+        else {
+            return Err(RemoteError::missing(0, "MailboxKey"));
+        };
+        MailboxKey::from_syrup_item(&key)
+            .map_err(|_err| RemoteError::unexpected("MailboxKey", 0, key))
+    }
+
+    /// Copy the history of every channel of ours the portal's host has into `history`, the way
+    /// [`Channel::backfill`] does for a single channel. The host must be another device of ours.
+    /// For direct messages it took part in, the host's mailbox keys are fetched into `devices`.
+    /// Returns the number of newly recorded messages.
+    #[tracing::instrument(skip_all)]
+    pub async fn sync_all_history(
+        &self,
+        history: &HistoryStore,
+        devices: &LinkedDevices,
+    ) -> Result<usize, BackfillError> {
+        let mut received = 0;
+        let mut mailboxes = HashSet::new();
+        for channel_id in self.synced_channels().await? {
+            let since = history.newest(&channel_id)?.map(HistoryCursor::Message);
+            let mut request = HistoryRequest::since(since);
+            loop {
+                let batch = self.sync_history(channel_id, &request).await?;
+                for message in &batch.messages {
+                    if message.is_legacy() {
+                        tracing::warn!(message = %message.id, "dropping synced legacy message");
+                        continue;
+                    }
+                    if message.channel != channel_id {
+                        tracing::warn!(
+                            message = %message.id,
+                            other_channel = %message.channel,
+                            "dropping synced message from another channel"
+                        );
+                        continue;
+                    }
+                    if let Err(error) = message.verify_strict(&message.sender) {
+                        tracing::warn!(
+                            message = %message.id,
+                            sender = rexa::hash(&message.sender),
+                            %error,
+                            "dropping synced message with invalid signature"
+                        );
+                        continue;
+                    }
+                    if history.append(&channel_id, message.clone())? {
+                        received += 1;
+                    }
+                    if let Some(counterpart) = self.linked_counterpart(message) {
+                        mailboxes.insert((channel_id, counterpart));
+                    }
+                }
+                match batch.messages.last() {
+                    Some(last) if batch.has_more => {
+                        request = HistoryRequest::since(Some(HistoryCursor::Message(last.id)));
+                    }
+                    _ => break,
+                }
+            }
+        }
+        for (channel_id, counterpart) in mailboxes {
+            if devices.mailbox_key(&channel_id, &self.peer_key).is_some() {
+                continue;
+            }
+            let key = match self.mailbox_key(channel_id, &counterpart).await {
+                Ok(key) => key,
+                Err(error) => {
+                    tracing::warn!(mailbox = %channel_id, %error, "could not fetch mailbox key");
+                    continue;
+                }
+            };
+            if key.channel != channel_id
+                || key.holder != self.peer_key
+                || key.counterpart != counterpart
+                || key.recipient != self.local_key
+            {
+                tracing::warn!(mailbox = %channel_id, "dropping mailbox key for something else");
+                continue;
+            }
+            if let Err(error) = devices.record_mailbox_key(key) {
+                tracing::error!(mailbox = %channel_id, %error, "failed to record mailbox key");
+            }
+        }
+        tracing::debug!(received, "synced linked history");
+        Ok(received)
+    }
+
+    /// If `message` is a direct message the portal's host sent or received, the other end.
+    fn linked_counterpart(&self, message: &Message) -> Option<PeerKey> {
+        let recipient = message.sealed.as_ref()?.recipient?;
+        if message.sender == self.peer_key {
+            Some(recipient)
+        } else if recipient == self.peer_key {
+            Some(message.sender)
+        } else {
+            None
+        }
     }
 
     #[tracing::instrument(skip(self))]
@@ -243,29 +939,77 @@ impl RemotePortal {
         }
     }
 
+    /// The key of the session this portal was opened over.
+    pub fn session_key(&self) -> RemoteKey {
+        *self.base.session().remote_vkey()
+    }
+
     pub async fn connect(
         &self,
         channel_id: ChannelId,
         info: ChannelInfo,
+        history: Arc<HistoryStore>,
+        signing_key: Arc<parking_lot::RwLock<SigningKey>>,
+        locator: Option<&SturdyRefLocator>,
         ev_sender: mpsc::UnboundedSender<ChannelEvent>,
     ) -> Result<Channel, ObjectError> {
-        let channel = Channel::new(channel_id, info, ev_sender);
+        if let Err(error) = history.load(&channel_id) {
+            tracing::error!(channel = %channel_id, %error, "failed to load channel history");
+        }
+        let channel = if info.encrypted {
+            Channel::new_encrypted(channel_id, info, signing_key, history, ev_sender)
+        } else {
+            Channel::new(channel_id, info, history, ev_sender)
+        };
+        channel.set_host(self.peer_key);
+        self.join_channel(&channel, locator).await?;
+        Ok(channel)
+    }
 
+    /// Connect an existing channel to the portal host's copy of it, e.g. to reach a member we were
+    /// introduced to.
+    #[tracing::instrument(skip_all, fields(channel = %channel.id()))]
+    pub async fn join_channel(
+        &self,
+        channel: &Channel,
+        locator: Option<&SturdyRefLocator>,
+    ) -> Result<(), ObjectError> {
         let pos = self
             .base
             .session()
             .exports()
             .export(Arc::new(channel.clone()));
+        channel.note_session(&self.peer_key, *self.base.session().remote_vkey());
 
-        let Some(arg) = self
+        let args = self
             .base
             .call_and(
                 "connect",
-                &syrup::raw_syrup_unwrap![&SyrupUuid(channel_id), &pos],
+                &syrup::raw_syrup_unwrap![&SyrupUuid(*channel.id()), &pos, &locator],
             )
-            .await?
-            .pop()
-        else {
+            .await?;
+        self.join(channel, args).await
+    }
+
+    /// Connect our mailbox for the portal's host to the one the host keeps for us.
+    #[tracing::instrument(skip_all, fields(mailbox = %mailbox.id()))]
+    pub async fn open_mailbox(&self, mailbox: &Channel) -> Result<(), ObjectError> {
+        let pos = self
+            .base
+            .session()
+            .exports()
+            .export(Arc::new(mailbox.clone()));
+
+        let args = self
+            .base
+            .call_and("open_mailbox", &syrup::raw_syrup_unwrap![&pos])
+            .await?;
+        self.join(mailbox, args).await
+    }
+
+    async fn join(&self, channel: &Channel, mut args: Vec<syrup::Item>) -> Result<(), ObjectError> {
+        let channel_id = *channel.id();
+        let Some(arg) = args.pop() else {
             return Err(ObjectError::missing(0, "ConnectResult"));
         };
 
@@ -273,10 +1017,42 @@ impl RemotePortal {
             return Err(ObjectError::unexpected("ConnectResult", 0, arg));
         };
 
-        // channel.connect_peer(self.base.remote_vkey(), connect.self_key, unsafe {
-        //     self.base.get_remote_object_unchecked(connect.position)
-        // });
+        let session = self.base.session();
+        channel.connect_peer(
+            *session.remote_vkey(),
+            self.peer_key,
+            unsafe {
+                session
+                    .clone()
+                    .into_remote_object_unchecked(connect.position)
+            },
+            None,
+            connect.relay,
+        );
 
-        Ok(channel)
+        let remote = RemoteChannel::new(unsafe {
+            self.base
+                .session()
+                .clone()
+                .into_remote_object_unchecked(connect.position)
+        });
+        let since = match channel.history().newest(&channel_id) {
+            Ok(newest) => newest.map(HistoryCursor::Message),
+            Err(error) => {
+                tracing::error!(channel = %channel_id, %error, "failed to read channel history");
+                None
+            }
+        };
+        if let Err(error) = channel.backfill(&remote, since).await {
+            // the host may not know our newest message; fall back to a full sync
+            tracing::warn!(channel = %channel_id, %error, "incremental backfill failed");
+            if since.is_some() {
+                if let Err(error) = channel.backfill(&remote, None).await {
+                    tracing::error!(channel = %channel_id, %error, "failed to backfill channel history");
+                }
+            }
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+
+use dashmap::DashMap;
+
+use crate::{store, PeerKey, Profile, ProfileError, Timestamp};
+
+/// How many peers' profiles the cache holds before it refuses more.
+pub const MAX_CACHED_PROFILES: usize = 4096;
+
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "profile-record")]
+struct ProfileRecord {
+    profile: Profile,
+    updated_at: Timestamp,
+}
+
+/// Appended once a peer's profile is forgotten.
+#[derive(syrup::Serialize, syrup::Deserialize, Clone)]
+#[syrup(name = "profile-removed")]
+struct ProfileRemoved {
+    peer_key: PeerKey,
+}
+
+/// The last profile each peer told us about, so names and avatars show up before (or without)
+/// reconnecting to it. Changes are appended to the file, which is compacted when it's mostly
+/// stale.
+pub struct ProfileCache {
+    path: Option<PathBuf>,
+    entries: DashMap<PeerKey, (Profile, Timestamp)>,
+    /// Records in the file, live or not; once they far outnumber the live ones it's rewritten.
+    records: parking_lot::Mutex<usize>,
+}
+
+impl std::fmt::Debug for ProfileCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProfileCache")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Default for ProfileCache {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl ProfileCache {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let entries = DashMap::new();
+        let mut records = 0;
+        for frame in store::read_frames(&path)? {
+            records += 1;
+            if let Ok(record) = syrup::de::from_bytes::<ProfileRecord>(&frame) {
+                if record.profile.check().is_ok() {
+                    entries.insert(record.profile.vkey, (record.profile, record.updated_at));
+                }
+            } else if let Ok(removed) = syrup::de::from_bytes::<ProfileRemoved>(&frame) {
+                entries.remove(&removed.peer_key);
+            } else {
+                tracing::warn!(?path, "skipping unreadable profile record");
+            }
+        }
+        let stale = records > entries.len();
+        let cache = Self {
+            path: Some(path),
+            entries,
+            records: parking_lot::Mutex::new(records),
+        };
+        if stale {
+            cache.compact(&mut cache.records.lock());
+        }
+        Ok(cache)
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: DashMap::new(),
+            records: parking_lot::Mutex::new(0),
+        }
+    }
+
+    pub fn get(&self, peer_key: &PeerKey) -> Option<Profile> {
+        self.entries.get(peer_key).map(|entry| entry.0.clone())
+    }
+
+    /// Remember `profile` for the peer it names. Returns whether it differs from what we had.
+    /// Profiles with oversized fields are refused, as are new peers once
+    /// [`MAX_CACHED_PROFILES`] are cached.
+    pub fn insert(&self, profile: Profile) -> Result<bool, ProfileError> {
+        profile.check()?;
+        let mut records = self.records.lock();
+        let unchanged = self
+            .entries
+            .get(&profile.vkey)
+            .map(|entry| entry.0 == profile);
+        match unchanged {
+            Some(true) => return Ok(false),
+            None if self.entries.len() >= MAX_CACHED_PROFILES => return Err(ProfileError::Full),
+            _ => {}
+        }
+        let record = ProfileRecord {
+            profile,
+            updated_at: crate::unix_millis(),
+        };
+        self.entries.insert(
+            record.profile.vkey,
+            (record.profile.clone(), record.updated_at),
+        );
+        self.append(&mut records, &record);
+        Ok(true)
+    }
+
+    pub fn remove(&self, peer_key: &PeerKey) {
+        let mut records = self.records.lock();
+        if self.entries.remove(peer_key).is_some() {
+            let removed = ProfileRemoved {
+                peer_key: *peer_key,
+            };
+            self.append(&mut records, &removed);
+        }
+    }
+
+    pub fn entries(&self) -> Vec<Profile> {
+        self.entries
+            .iter()
+            .map(|entry| entry.value().0.clone())
+            .collect()
+    }
+
+    /// Persist a change already made to the entries.
+    fn append<T: syrup::Serialize>(&self, records: &mut usize, record: &T) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        *records += 1;
+        if *records > 2 * self.entries.len() + 64 {
+            return self.compact(records);
+        }
+        if let Err(error) = store::append_record(path, record) {
+            tracing::error!(?path, %error, "failed to persist profile");
+        }
+    }
+
+    /// Rewrite the file with only the profiles we still have.
+    fn compact(&self, records: &mut usize) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let live = self
+            .entries
+            .iter()
+            .map(|entry| ProfileRecord {
+                profile: entry.value().0.clone(),
+                updated_at: entry.value().1,
+            })
+            .collect::<Vec<_>>();
+        match store::write_records(path, &live) {
+            Ok(()) => *records = live.len(),
+            Err(error) => tracing::error!(?path, %error, "failed to compact profile cache"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::MAX_USERNAME_LEN;
+
+    fn profile(username: &str) -> Profile {
+        Profile::new(
+            SigningKey::generate(&mut OsRng).verifying_key(),
+            username.to_owned(),
+            None,
+        )
+    }
+
+    #[test]
+    fn refuses_oversized_profiles() {
+        let cache = ProfileCache::in_memory();
+        let long = "x".repeat(MAX_USERNAME_LEN + 1);
+        assert!(matches!(
+            cache.insert(profile(&long)),
+            Err(ProfileError::UsernameTooLong(_))
+        ));
+        assert!(cache.insert(profile("alice")).unwrap());
+    }
+
+    #[test]
+    fn reopened_cache_keeps_changes() {
+        let path = std::env::temp_dir().join(format!(
+            "troposphere-test-{}.profiles.syrup",
+            uuid::Uuid::new_v4()
+        ));
+        let alice = profile("alice");
+        let bob = profile("bob");
+        let cache = ProfileCache::open(&path).unwrap();
+        assert!(cache.insert(alice.clone()).unwrap());
+        assert!(cache.insert(bob.clone()).unwrap());
+        let renamed = Profile::new(alice.vkey, "alicia".to_owned(), None);
+        assert!(cache.insert(renamed.clone()).unwrap());
+        cache.remove(&bob.vkey);
+        let reopened = ProfileCache::open(&path).unwrap();
+        assert_eq!(reopened.get(&alice.vkey), Some(renamed));
+        assert_eq!(reopened.get(&bob.vkey), None);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+
+use ed25519_dalek::{ed25519::signature::SignerMut, Signature, SignatureError, SigningKey};
+use syrup::{Deserialize, Serialize};
+
+use crate::{ChannelId, MessageId, PeerKey, SyrupUuid, Timestamp};
+
+const RECEIPT_DOMAIN: &[u8] = b"troposphere/read-receipt/v1";
+
+/// A member's signed statement that it has read a message. Receipts are addressed to the message's
+/// author, and relayed by the channel host like messages are.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "read-receipt")]
+pub struct ReadReceipt {
+    #[syrup(as = SyrupUuid)]
+    pub channel: ChannelId,
+    #[syrup(as = SyrupUuid)]
+    pub message: MessageId,
+    pub author: PeerKey,
+    pub reader: PeerKey,
+    pub read_at: Timestamp,
+    pub signature: Signature,
+}
+
+impl ReadReceipt {
+    fn payload(
+        channel: ChannelId,
+        message: MessageId,
+        author: &PeerKey,
+        reader: &PeerKey,
+        read_at: Timestamp,
+    ) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(RECEIPT_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid::from(channel)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&SyrupUuid::from(message)).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(author).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(reader).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&read_at).unwrap());
+        res
+    }
+
+    pub fn new(
+        channel: ChannelId,
+        message: MessageId,
+        author: PeerKey,
+        signing_key: &mut SigningKey,
+    ) -> Result<Self, SignatureError> {
+        let reader = signing_key.verifying_key();
+        let read_at = crate::unix_millis();
+        let signature =
+            signing_key.try_sign(&Self::payload(channel, message, &author, &reader, read_at))?;
+        Ok(Self {
+            channel,
+            message,
+            author,
+            reader,
+            read_at,
+            signature,
+        })
+    }
+
+    pub fn verify(&self) -> Result<(), SignatureError> {
+        self.reader.verify_strict(
+            &Self::payload(
+                self.channel,
+                self.message,
+                &self.author,
+                &self.reader,
+                self.read_at,
+            ),
+            &self.signature,
+        )
+    }
+}
+
+/// What we know about the delivery of a message we sent.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryState {
+    /// Members the message is still being delivered to.
+    pub pending: usize,
+    /// Members that acknowledged the message.
+    pub delivered: HashSet<PeerKey>,
+    /// Members that sent a read receipt for it.
+    pub read: HashSet<PeerKey>,
+    /// Offline members the message is queued for until they reconnect.
+    pub queued: HashSet<PeerKey>,
+}
+
+impl DeliveryState {
+    pub fn is_pending(&self) -> bool {
+        self.pending > 0 || !self.queued.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    fn receipt() -> ReadReceipt {
+        let author = SigningKey::generate(&mut OsRng).verifying_key();
+        let mut reader = SigningKey::generate(&mut OsRng);
+        ReadReceipt::new(
+            ChannelId::new_v4(),
+            MessageId::new_v4(),
+            author,
+            &mut reader,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn verifies() {
+        receipt().verify().unwrap();
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let mut moved = receipt();
+        moved.message = MessageId::new_v4();
+        assert!(moved.verify().is_err());
+
+        let mut redirected = receipt();
+        redirected.channel = ChannelId::new_v4();
+        assert!(redirected.verify().is_err());
+
+        let mut backdated = receipt();
+        backdated.read_at -= 1;
+        assert!(backdated.verify().is_err());
+    }
+
+    #[test]
+    fn rejects_another_reader() {
+        let mut forged = receipt();
+        forged.reader = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(forged.verify().is_err());
+    }
+}
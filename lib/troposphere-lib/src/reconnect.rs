@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use dashmap::{DashMap, DashSet};
+use rand::Rng;
+use rexa::{captp::RemoteKey, locator::NodeLocator};
+
+use crate::{ConnectError, PeerKey};
+
+/// How peers are redialed after their session ends. Only peers we dialed ourselves, or were
+/// introduced to, are redialed; the other side is left to do the same for us.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    /// Give up after this many failed attempts; `None` keeps trying.
+    pub max_attempts: Option<u32>,
+    /// The delay before the first attempt, doubled after every failure.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: Some(10),
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    /// The delay before attempt number `attempt`, counting from 1. Up to half of it is random, so
+    /// peers that lost each other at the same moment don't retry in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    pub fn gives_up_after(&self, attempts: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempts >= max)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReconnectError {
+    #[error("gave up reconnecting to {} after {attempts} attempts: {last}", rexa::hash(.peer_key))]
+    GaveUp {
+        peer_key: PeerKey,
+        attempts: u32,
+        last: ConnectError,
+    },
+}
+
+/// What the manager knows about reaching the peers it may have to redial.
+#[derive(Debug, Default)]
+pub(crate) struct Reconnector {
+    pub(crate) policy: ReconnectPolicy,
+    /// Sessions we dialed whose peer hasn't authenticated yet.
+    dialed: DashMap<RemoteKey, NodeLocator>,
+    known: DashMap<PeerKey, NodeLocator>,
+    /// Peers being redialed right now.
+    active: DashSet<PeerKey>,
+}
+
+impl Reconnector {
+    pub(crate) fn new(policy: ReconnectPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn dialed(&self, session_key: RemoteKey, locator: NodeLocator) {
+        self.dialed.insert(session_key, locator);
+    }
+
+    /// Learn who is on the other end of a session; if we dialed it, the peer is redialed should
+    /// the session drop. Returns where we dialed it.
+    pub(crate) fn identify(
+        &self,
+        session_key: &RemoteKey,
+        peer_key: PeerKey,
+    ) -> Option<NodeLocator> {
+        let (_, locator) = self.dialed.remove(session_key)?;
+        self.known.insert(peer_key, locator.clone());
+        Some(locator)
+    }
+
+    pub(crate) fn remember(&self, peer_key: PeerKey, locator: NodeLocator) {
+        self.known.insert(peer_key, locator);
+    }
+
+    pub(crate) fn forget_session(&self, session_key: &RemoteKey) {
+        self.dialed.remove(session_key);
+    }
+
+    /// Where to redial `peer_key`, unless it's already being redialed. The caller must call
+    /// [`Self::finish`] once it's done.
+    pub(crate) fn begin(&self, peer_key: PeerKey) -> Option<NodeLocator> {
+        if !self.policy.enabled {
+            return None;
+        }
+        let locator = self.known.get(&peer_key)?.value().clone();
+        self.active.insert(peer_key).then_some(locator)
+    }
+
+    pub(crate) fn finish(&self, peer_key: &PeerKey) {
+        self.active.remove(peer_key);
+    }
+}
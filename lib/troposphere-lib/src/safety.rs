@@ -0,0 +1,110 @@
+use sha2::{Digest, Sha512};
+
+use crate::PeerKey;
+
+const FINGERPRINT_INFO: &[u8] = b"troposphere/safety-number/v1";
+/// Iterations of the fingerprint hash, to make finding a key with a matching number expensive.
+const FINGERPRINT_ITERATIONS: usize = 5200;
+const FINGERPRINT_LEN: usize = 30;
+const DIGITS_PER_CHUNK: usize = 5;
+
+/// A number two peers compare out of band, in person or over a call, to confirm each holds the
+/// other's real key. Both ends derive the same number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SafetyNumber([[u8; FINGERPRINT_LEN]; 2]);
+
+impl SafetyNumber {
+    pub fn new(a: &PeerKey, b: &PeerKey) -> Self {
+        let (first, second) = if a.as_bytes() <= b.as_bytes() {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        Self([fingerprint(first), fingerprint(second)])
+    }
+
+    /// The number as 60 decimal digits, without spacing; this is what the QR code encodes.
+    pub fn digits(&self) -> String {
+        self.groups().collect()
+    }
+
+    /// The number in groups of five digits.
+    pub fn groups(&self) -> impl Iterator<Item = String> + '_ {
+        self.0.iter().flat_map(|fingerprint| {
+            fingerprint.chunks_exact(DIGITS_PER_CHUNK).map(|chunk| {
+                let value = chunk
+                    .iter()
+                    .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+                format!("{:05}", value % 100_000)
+            })
+        })
+    }
+}
+
+impl std::fmt::Display for SafetyNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, group) in self.groups().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(&group)?;
+        }
+        Ok(())
+    }
+}
+
+fn fingerprint(key: &PeerKey) -> [u8; FINGERPRINT_LEN] {
+    let mut hash = Sha512::new()
+        .chain_update(FINGERPRINT_INFO)
+        .chain_update(key.as_bytes())
+        .finalize();
+    for _ in 1..FINGERPRINT_ITERATIONS {
+        hash = Sha512::new()
+            .chain_update(hash)
+            .chain_update(key.as_bytes())
+            .finalize();
+    }
+    let mut res = [0u8; FINGERPRINT_LEN];
+    res.copy_from_slice(&hash[..FINGERPRINT_LEN]);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
+
+    use super::*;
+
+    fn peer_key() -> PeerKey {
+        SigningKey::generate(&mut OsRng).verifying_key()
+    }
+
+    #[test]
+    fn both_ends_derive_the_same_number() {
+        let (alice, bob) = (peer_key(), peer_key());
+        assert_eq!(
+            SafetyNumber::new(&alice, &bob),
+            SafetyNumber::new(&bob, &alice)
+        );
+    }
+
+    #[test]
+    fn numbers_differ_between_pairs() {
+        let (alice, bob, mallory) = (peer_key(), peer_key(), peer_key());
+        assert_ne!(
+            SafetyNumber::new(&alice, &bob),
+            SafetyNumber::new(&alice, &mallory)
+        );
+    }
+
+    #[test]
+    fn renders_sixty_digits_in_groups_of_five() {
+        let number = SafetyNumber::new(&peer_key(), &peer_key());
+        let digits = number.digits();
+        assert_eq!(digits.len(), 60);
+        assert!(digits.bytes().all(|digit| digit.is_ascii_digit()));
+        assert_eq!(number.groups().count(), 12);
+        assert_eq!(number.to_string().replace(' ', ""), digits);
+    }
+}
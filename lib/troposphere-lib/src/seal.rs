@@ -0,0 +1,181 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use ed25519_dalek::{SignatureError, SigningKey};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::{ChannelId, PeerKey};
+
+const SEAL_INFO: &[u8] = b"troposphere/conversation-key/v1";
+const SENDER_KEY_INFO: &[u8] = b"troposphere/sender-key/v1";
+const STORAGE_KEY_INFO: &[u8] = b"troposphere/storage-key/v1";
+pub const SEAL_NONCE_LEN: usize = 24;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SealError {
+    #[error("peer key does not yield a usable shared secret")]
+    InvalidPeerKey,
+    #[error("message is not sealed")]
+    NotSealed,
+    #[error("message is addressed to someone else")]
+    NotRecipient,
+    #[error("could not encrypt message")]
+    Encrypt,
+    #[error("could not decrypt message")]
+    Decrypt,
+    #[error("we haven't been given the key this message was sealed with")]
+    UnknownSenderKey,
+    #[error(transparent)]
+    Sign(#[from] SignatureError),
+}
+
+/// The symmetric key two peers share for one conversation: an X25519 exchange between their
+/// (converted) ed25519 identities, expanded with HKDF and salted with the channel id.
+pub struct ConversationKey(chacha20poly1305::Key);
+
+impl ConversationKey {
+    pub fn derive(
+        local: &SigningKey,
+        remote: &PeerKey,
+        channel: &ChannelId,
+    ) -> Result<Self, SealError> {
+        let secret = x25519_dalek::StaticSecret::from(local.to_scalar_bytes());
+        let public = x25519_dalek::PublicKey::from(remote.to_montgomery().to_bytes());
+        let shared = secret.diffie_hellman(&public);
+        if !shared.was_contributory() {
+            return Err(SealError::InvalidPeerKey);
+        }
+        let mut key = chacha20poly1305::Key::default();
+        Hkdf::<Sha256>::new(Some(channel.as_bytes()), shared.as_bytes())
+            .expand(SEAL_INFO, &mut key)
+            .map_err(|_err| SealError::InvalidPeerKey)?;
+        Ok(Self(key))
+    }
+
+    /// The message key for one epoch of a member's sender key in an encrypted channel.
+    pub fn from_sender_key(secret: &[u8; 32], channel: &ChannelId) -> Self {
+        let mut key = chacha20poly1305::Key::default();
+        Hkdf::<Sha256>::new(Some(channel.as_bytes()), secret)
+            .expand(SENDER_KEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self(key)
+    }
+
+    /// A key only the holder of `local` can derive, for secrets kept on disk. `context` keeps the
+    /// keys of different stores apart.
+    pub fn for_storage(local: &SigningKey, context: &[u8]) -> Self {
+        let mut key = chacha20poly1305::Key::default();
+        Hkdf::<Sha256>::new(Some(context), &local.to_bytes())
+            .expand(STORAGE_KEY_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self(key)
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes.into())
+    }
+
+    pub(crate) fn to_bytes(&self) -> [u8; 32] {
+        self.0.into()
+    }
+
+    /// Encrypt `plaintext`, returning the random nonce and the ciphertext.
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), SealError> {
+        let nonce = rand::random::<[u8; SEAL_NONCE_LEN]>();
+        let ciphertext = XChaCha20Poly1305::new(&self.0)
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|_err| SealError::Encrypt)?;
+        Ok((nonce.to_vec(), ciphertext))
+    }
+
+    pub fn open(&self, aad: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, SealError> {
+        if nonce.len() != SEAL_NONCE_LEN {
+            return Err(SealError::Decrypt);
+        }
+        XChaCha20Poly1305::new(&self.0)
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_err| SealError::Decrypt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::Message;
+
+    fn keys() -> (SigningKey, SigningKey, ChannelId) {
+        (
+            SigningKey::generate(&mut OsRng),
+            SigningKey::generate(&mut OsRng),
+            ChannelId::new_v4(),
+        )
+    }
+
+    #[test]
+    fn both_ends_derive_the_same_key() {
+        let (alice, bob, channel) = keys();
+        let sealing = ConversationKey::derive(&alice, &bob.verifying_key(), &channel).unwrap();
+        let opening = ConversationKey::derive(&bob, &alice.verifying_key(), &channel).unwrap();
+        let (nonce, ciphertext) = sealing.seal(b"aad", b"hello").unwrap();
+        assert_eq!(opening.open(b"aad", &nonce, &ciphertext).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn keys_differ_between_channels() {
+        let (alice, bob, channel) = keys();
+        let sealing = ConversationKey::derive(&alice, &bob.verifying_key(), &channel).unwrap();
+        let other =
+            ConversationKey::derive(&bob, &alice.verifying_key(), &ChannelId::new_v4()).unwrap();
+        let (nonce, ciphertext) = sealing.seal(b"aad", b"hello").unwrap();
+        assert!(other.open(b"aad", &nonce, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let (alice, bob, channel) = keys();
+        let key = ConversationKey::derive(&alice, &bob.verifying_key(), &channel).unwrap();
+        let (nonce, mut ciphertext) = key.seal(b"aad", b"hello").unwrap();
+        assert!(key.open(b"other aad", &nonce, &ciphertext).is_err());
+        assert!(key.open(b"aad", &nonce[1..], &ciphertext).is_err());
+        ciphertext[0] ^= 1;
+        assert!(key.open(b"aad", &nonce, &ciphertext).is_err());
+    }
+
+    #[test]
+    fn sealed_messages_open_for_both_ends_only() {
+        let (mut alice, bob, channel) = keys();
+        let eve = SigningKey::generate(&mut OsRng);
+        let message =
+            Message::seal(channel, bob.verifying_key(), None, "hello", &mut alice).unwrap();
+        message.verify_strict(&alice.verifying_key()).unwrap();
+        assert_eq!(message.open(&alice).unwrap(), "hello");
+        assert_eq!(message.open(&bob).unwrap(), "hello");
+        assert!(matches!(message.open(&eve), Err(SealError::NotRecipient)));
+    }
+
+    #[test]
+    fn sealed_message_signature_covers_the_ciphertext() {
+        let (mut alice, bob, channel) = keys();
+        let mut message =
+            Message::seal(channel, bob.verifying_key(), None, "hello", &mut alice).unwrap();
+        message.sealed.as_mut().unwrap().ciphertext.0[0] ^= 1;
+        assert!(message.verify_strict(&alice.verifying_key()).is_err());
+        assert!(message.open(&bob).is_err());
+    }
+}
@@ -12,7 +12,10 @@ use rexa::{
     impl_object,
 };
 use std::{collections::HashMap, sync::Arc};
-use tokio::{sync::watch, task::JoinSet};
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinSet,
+};
 
 struct FulfillResponseHandler;
 impl FulfillResponseHandler {
@@ -29,6 +32,7 @@ pub async fn manage_session<Reader, Writer>(
     session: CapTpSession<Reader, Writer>,
     event_pipe: EventSender,
     mut end_flag: watch::Receiver<bool>,
+    mut abort_req: oneshot::Receiver<String>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>
 where
     Reader: AsyncRead + Unpin + Send + 'static,
@@ -94,6 +98,17 @@ where
                 break Ok(())
             } else {
                 continue
+            },
+            Ok(reason) = &mut abort_req => {
+                tracing::info!(%reason, "aborting session");
+                let session_key = *session.remote_vkey();
+                if !session.is_aborted() {
+                    let reason = reason.clone();
+                    tokio::spawn(async move { session.abort(&reason).await });
+                }
+                // a local abort isn't reported by the session itself
+                event_pipe.send(NetworkEvent::SessionAborted { session_key, reason })?;
+                break Ok(())
             }
         };
         let event = match ev_res {
@@ -0,0 +1,127 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// The largest record a store holds. Anything claiming to be larger is corruption, and isn't
+/// worth allocating for.
+const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+pub(crate) fn encode<T: syrup::Serialize>(record: &T) -> io::Result<Vec<u8>> {
+    syrup::ser::to_bytes(record)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+}
+
+fn write_frame(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|&len| len as usize <= MAX_RECORD_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "record too large"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+pub(crate) fn append_record<T: syrup::Serialize>(path: &Path, record: &T) -> io::Result<()> {
+    write_record(path, record)?.sync_data()
+}
+
+/// Append `record` to `path` without waiting for it to reach the disk; sync the returned file for
+/// that. Lets callers order appends under a lock without holding it through the sync.
+pub(crate) fn write_record<T: syrup::Serialize>(path: &Path, record: &T) -> io::Result<File> {
+    let bytes = encode(record)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // write the frame in one call so a crash can at worst truncate the final record
+    let mut frame = Vec::with_capacity(bytes.len() + 4);
+    write_frame(&mut frame, &bytes)?;
+    file.write_all(&frame)?;
+    Ok(file)
+}
+
+/// Read every raw record from `path`, stopping at the first truncated frame. Returns an empty
+/// list if the file does not exist.
+pub(crate) fn read_frames(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut reader = BufReader::new(file);
+    let mut res = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            tracing::warn!(
+                ?path,
+                len,
+                "ignoring oversized record and everything after it"
+            );
+            break;
+        }
+        let mut frame = vec![0u8; len];
+        match reader.read_exact(&mut frame) {
+            Ok(()) => res.push(frame),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                tracing::warn!(?path, "ignoring truncated record");
+                break;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(res)
+}
+
+pub(crate) fn read_records<T>(path: &Path) -> io::Result<Vec<T>>
+where
+    T: for<'de> syrup::Deserialize<'de>,
+{
+    let mut res = Vec::new();
+    for frame in read_frames(path)? {
+        match syrup::de::from_bytes::<T>(&frame) {
+            Ok(record) => res.push(record),
+            Err(error) => tracing::warn!(?path, %error, "skipping unreadable record"),
+        }
+    }
+    Ok(res)
+}
+
+/// Replace the contents of `path` with `records`, going through a temporary file so a crash never
+/// leaves a half-written store behind.
+pub(crate) fn write_records<'r, T: syrup::Serialize + 'r>(
+    path: &Path,
+    records: impl IntoIterator<Item = &'r T>,
+) -> io::Result<()> {
+    write_frames(path, records.into_iter().map(encode))
+}
+
+/// Like [`write_records`], for stores that mix several kinds of record.
+pub(crate) fn write_frames(
+    path: &Path,
+    frames: impl IntoIterator<Item = io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    // a name of its own, so concurrent writers don't clobber each other's half-written files
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+    let tmp = path.with_file_name(tmp_name);
+    let written = (|| {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for frame in frames {
+            write_frame(&mut writer, &frame?)?;
+        }
+        writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if written.is_err() {
+        drop(fs::remove_file(&tmp));
+    }
+    written
+}
@@ -0,0 +1,254 @@
+use std::path::PathBuf;
+
+use dashmap::DashMap;
+use ed25519_dalek::{Signature, SignatureError, Signer, SigningKey};
+use rand::rngs::OsRng;
+use syrup::{Deserialize, Serialize};
+
+use crate::{store, PeerKey, Timestamp};
+
+const SUCCESSION_DOMAIN: &[u8] = b"troposphere/key-succession/v1";
+/// How many successions the log holds before it refuses more.
+pub const MAX_SUCCESSIONS: usize = 4096;
+
+/// A statement, signed by a retired key, that its holder now goes by `new_key`. Peers that knew the
+/// old key carry what they know about it over to the new one. Messages signed by the old key stay
+/// verifiable, since they name the key that signed them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[syrup(name = "key-succession")]
+pub struct Succession {
+    pub old_key: PeerKey,
+    pub new_key: PeerKey,
+    pub issued_at: Timestamp,
+    pub signature: Signature,
+}
+
+impl Succession {
+    fn payload(old_key: &PeerKey, new_key: &PeerKey, issued_at: Timestamp) -> Vec<u8> {
+        let mut res = syrup::ser::to_bytes(&syrup::Bytes(SUCCESSION_DOMAIN)).unwrap();
+        res.extend_from_slice(&syrup::ser::to_bytes(old_key).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(new_key).unwrap());
+        res.extend_from_slice(&syrup::ser::to_bytes(&issued_at).unwrap());
+        res
+    }
+
+    pub fn new(old: &SigningKey, new_key: PeerKey) -> Self {
+        let old_key = old.verifying_key();
+        let issued_at = crate::unix_millis();
+        let signature = old.sign(&Self::payload(&old_key, &new_key, issued_at));
+        Self {
+            old_key,
+            new_key,
+            issued_at,
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), SignatureError> {
+        self.old_key.verify_strict(
+            &Self::payload(&self.old_key, &self.new_key, self.issued_at),
+            &self.signature,
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SuccessionError {
+    #[error("succession is not signed by the key it retires")]
+    Signature(#[from] SignatureError),
+    /// The old key already named a different successor. The first one we saw wins, so whoever
+    /// steals a key can't take over the identity once its owner has moved on.
+    #[error("{} already has a successor", rexa::hash(.0))]
+    Conflict(PeerKey),
+    /// Only successions of keys we already know of are recorded, so strangers can't fill the log.
+    #[error("{} isn't a key we know of", rexa::hash(.0))]
+    UnknownKey(PeerKey),
+    #[error("succession log is full")]
+    Full,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Successions we've issued or learned of, keyed by the key they retire. Ours are presented to
+/// every gateway we authenticate to, so peers that missed the rotation still catch up.
+pub struct SuccessionLog {
+    path: Option<PathBuf>,
+    entries: DashMap<PeerKey, Succession>,
+    /// The key each key retired, for walking chains back to where they started.
+    predecessors: DashMap<PeerKey, PeerKey>,
+}
+
+impl std::fmt::Debug for SuccessionLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SuccessionLog")
+            .field("path", &self.path)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Default for SuccessionLog {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+impl SuccessionLog {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let log = Self::in_memory();
+        for record in store::read_records::<Succession>(&path)? {
+            if let Err(error) = record.verify() {
+                tracing::warn!(?path, %error, "skipping forged succession");
+                continue;
+            }
+            if !log.entries.contains_key(&record.old_key) {
+                log.insert(record);
+            }
+        }
+        Ok(Self {
+            path: Some(path),
+            ..log
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: DashMap::new(),
+            predecessors: DashMap::new(),
+        }
+    }
+
+    fn insert(&self, succession: Succession) {
+        self.predecessors
+            .entry(succession.new_key)
+            .or_insert(succession.old_key);
+        self.entries.insert(succession.old_key, succession);
+    }
+
+    /// Check and remember `succession`. Returns whether it's new to us.
+    pub fn record(&self, succession: Succession) -> Result<bool, SuccessionError> {
+        succession.verify()?;
+        if let Some(known) = self.entries.get(&succession.old_key) {
+            if known.new_key == succession.new_key {
+                return Ok(false);
+            }
+            return Err(SuccessionError::Conflict(succession.old_key));
+        }
+        if self.entries.len() >= MAX_SUCCESSIONS {
+            return Err(SuccessionError::Full);
+        }
+        if let Some(path) = &self.path {
+            store::append_record(path, &succession)?;
+        }
+        self.insert(succession);
+        Ok(true)
+    }
+
+    /// Whether we know of a successor to `old_key`.
+    pub fn contains(&self, old_key: &PeerKey) -> bool {
+        self.entries.contains_key(old_key)
+    }
+
+    /// Retire `old` in favor of a newly generated key, which is returned.
+    pub fn rotate(&self, old: &SigningKey) -> Result<SigningKey, SuccessionError> {
+        let new = SigningKey::generate(&mut OsRng);
+        self.record(Succession::new(old, new.verifying_key()))?;
+        Ok(new)
+    }
+
+    /// The successions that led to `peer_key`, oldest first.
+    pub fn chain_to(&self, peer_key: &PeerKey) -> Vec<Succession> {
+        let mut res = Vec::new();
+        let mut current = *peer_key;
+        // a chain can't be longer than the log, even if it loops
+        while res.len() < self.entries.len() {
+            let Some(old_key) = self.predecessors.get(&current).map(|entry| *entry) else {
+                break;
+            };
+            let Some(succession) = self.entries.get(&old_key).map(|entry| entry.clone()) else {
+                break;
+            };
+            current = old_key;
+            res.push(succession);
+        }
+        res.reverse();
+        res
+    }
+
+    /// The key the chain leading to `peer_key` started with; `peer_key` itself if it never
+    /// succeeded another.
+    pub fn root(&self, peer_key: &PeerKey) -> PeerKey {
+        self.chain_to(peer_key)
+            .first()
+            .map_or(*peer_key, |succession| succession.old_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_tampered_successions() {
+        let old = SigningKey::generate(&mut OsRng);
+        let mut succession =
+            Succession::new(&old, SigningKey::generate(&mut OsRng).verifying_key());
+        assert!(succession.verify().is_ok());
+        succession.new_key = SigningKey::generate(&mut OsRng).verifying_key();
+        assert!(succession.verify().is_err());
+        assert!(matches!(
+            SuccessionLog::in_memory().record(succession),
+            Err(SuccessionError::Signature(_))
+        ));
+    }
+
+    #[test]
+    fn first_successor_wins() {
+        let log = SuccessionLog::in_memory();
+        let old = SigningKey::generate(&mut OsRng);
+        let first = Succession::new(&old, SigningKey::generate(&mut OsRng).verifying_key());
+        assert!(log.record(first.clone()).unwrap());
+        assert!(!log.record(first).unwrap());
+        let second = Succession::new(&old, SigningKey::generate(&mut OsRng).verifying_key());
+        assert!(matches!(
+            log.record(second),
+            Err(SuccessionError::Conflict(key)) if key == old.verifying_key()
+        ));
+    }
+
+    #[test]
+    fn chains_lead_back_to_the_first_key() {
+        let log = SuccessionLog::in_memory();
+        let first = SigningKey::generate(&mut OsRng);
+        let second = log.rotate(&first).unwrap();
+        let third = log.rotate(&second).unwrap();
+        let chain = log.chain_to(&third.verifying_key());
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].old_key, first.verifying_key());
+        assert_eq!(chain[0].new_key, second.verifying_key());
+        assert_eq!(chain[1].new_key, third.verifying_key());
+        assert_eq!(log.root(&third.verifying_key()), first.verifying_key());
+        assert_eq!(log.root(&first.verifying_key()), first.verifying_key());
+    }
+
+    #[test]
+    fn reopened_log_keeps_chains() {
+        let path = std::env::temp_dir().join(format!(
+            "troposphere-test-{}.successions.syrup",
+            uuid::Uuid::new_v4()
+        ));
+        let first = SigningKey::generate(&mut OsRng);
+        let second = SuccessionLog::open(&path).unwrap().rotate(&first).unwrap();
+        let reopened = SuccessionLog::open(&path).unwrap();
+        assert_eq!(
+            reopened.root(&second.verifying_key()),
+            first.verifying_key()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,11 +1,30 @@
+use std::sync::Arc;
+
 use ed25519_dalek::VerifyingKey;
 use rexa::captp::object::{RemoteError, RemoteObject};
 use syrup::FromSyrupItem;
 use tokio::sync::RwLock;
 
+use crate::AvatarStore;
+
 pub type UserId = uuid::Uuid;
 
-#[derive(Debug, Clone, syrup::Serialize, syrup::Deserialize)]
+/// The longest username, in bytes, we show or accept from a peer. Ours is cut down to it.
+pub const MAX_USERNAME_LEN: usize = 64;
+/// The longest avatar name, in bytes, a profile may carry.
+pub const MAX_AVATAR_NAME_LEN: usize = 128;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProfileError {
+    #[error("username is {0} bytes; at most {MAX_USERNAME_LEN} are allowed")]
+    UsernameTooLong(usize),
+    #[error("avatar name is {0} bytes; at most {MAX_AVATAR_NAME_LEN} are allowed")]
+    AvatarNameTooLong(usize),
+    #[error("already caching the most profiles we keep")]
+    Full,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, syrup::Serialize, syrup::Deserialize)]
 pub struct Profile {
     pub vkey: VerifyingKey,
     pub username: String,
@@ -20,10 +39,36 @@ impl Profile {
             avatar,
         }
     }
+
+    /// Check that the profile's fields are no larger than we're willing to keep.
+    pub fn check(&self) -> Result<(), ProfileError> {
+        if self.username.len() > MAX_USERNAME_LEN {
+            return Err(ProfileError::UsernameTooLong(self.username.len()));
+        }
+        match &self.avatar {
+            Some(avatar) if avatar.len() > MAX_AVATAR_NAME_LEN => {
+                Err(ProfileError::AvatarNameTooLong(avatar.len()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `username` cut down to at most [`MAX_USERNAME_LEN`] bytes, without splitting a character.
+fn truncate_username(mut username: String) -> String {
+    if username.len() > MAX_USERNAME_LEN {
+        let end = (0..=MAX_USERNAME_LEN)
+            .rev()
+            .find(|&end| username.is_char_boundary(end))
+            .unwrap_or(0);
+        username.truncate(end);
+    }
+    username
 }
 
 pub struct Persona {
     pub profile: RwLock<Profile>,
+    avatars: Arc<AvatarStore>,
 }
 
 impl std::fmt::Debug for Persona {
@@ -35,11 +80,37 @@ impl std::fmt::Debug for Persona {
 }
 
 impl Persona {
-    pub fn new(profile: Profile) -> Self {
+    pub fn new(mut profile: Profile, avatars: Arc<AvatarStore>) -> Self {
+        profile.username = truncate_username(profile.username);
         Self {
             profile: profile.into(),
+            avatars,
         }
     }
+
+    /// The image our profile names as its avatar. Nothing else in the avatar store is handed out.
+    pub async fn avatar_bytes(&self, name: &str) -> Result<Vec<u8>, &'static str> {
+        if self.profile.read().await.avatar.as_deref() != Some(name) {
+            return Err("not our avatar");
+        }
+        match self.avatars.get(name) {
+            Ok(Some(bytes)) => Ok(bytes),
+            Ok(None) => Err("avatar not found"),
+            Err(error) => {
+                tracing::error!(%error, "failed to read avatar");
+                Err("could not read avatar")
+            }
+        }
+    }
+
+    /// Change the username and avatar, returning the updated profile. A username longer than
+    /// [`MAX_USERNAME_LEN`] is cut short, since peers would refuse it.
+    pub async fn update(&self, username: String, avatar: Option<String>) -> Profile {
+        let mut profile = self.profile.write().await;
+        profile.username = truncate_username(username);
+        profile.avatar = avatar;
+        profile.clone()
+    }
 }
 
 #[rexa::impl_object(tracing = ::tracing)]
@@ -49,27 +120,6 @@ impl Persona {
     async fn profile<'s>(&'s self) -> impl std::ops::Deref<Target = Profile> + 's {
         self.profile.read().await
     }
-
-    // #[deliver(always_ok)]
-    // async fn get_personal_mailbox(
-    //     &self,
-    //     session: &(dyn AbstractCapTpSession + Sync),
-    // ) -> DescExport {
-    //     let mut pm_channels = self.pm_map.lock().await;
-    //     if let Some((pos, _)) = pm_channels.get(session.remote_vkey()) {
-    //         *pos
-    //     } else {
-    //         let inbox = Channel::new(
-    //             uuid::Uuid::new_v4(),
-    //             "Personal Inbox".to_owned(),
-    //             self.ev_sender.clone(),
-    //         );
-    //         self.data.inboxes.insert(inbox.id, inbox.clone());
-    //         let pos = DescExport::from(session.export(inbox.clone()));
-    //         inboxes.insert(*session.remote_vkey(), (pos, inbox));
-    //         pos
-    //     }
-    // }
 }
 
 pub type PeerKey = VerifyingKey;